@@ -44,6 +44,7 @@ import {
   encryptPositionData,
   decryptPositionData,
   generateComputationOffset,
+  generateNonce,
   generatePositionId,
   nonceToBN,
   ciphertextToBytes,
@@ -108,6 +109,12 @@ export class PerpetualsAdapter {
         this.program.programId
       );
 
+      const collateralCustody = params.collateralCustody || this.defaultCollateralCustody;
+      if (!collateralCustody) {
+        throw new Error("No collateral custody configured");
+      }
+      const collateralCustodyAccount = await this.program.account.custody.fetch(collateralCustody);
+
       const encrypted = encryptPositionData(
         BigInt(params.size.toString()),
         BigInt(params.collateral.toString()),
@@ -142,7 +149,10 @@ export class PerpetualsAdapter {
           encrypted.collateralEncrypted,
           Array.from(this.encryptionContext.publicKey),
           nonceToBN(encrypted.sizeNonce),
-          nonceToBN(encrypted.collateralNonce)
+          nonceToBN(encrypted.collateralNonce),
+          params.clientRequestId ?? null,
+          nonceToBN(generateNonce()),
+          params.clientTag ?? null
         )
         .accountsPartial({
           owner: this.provider.wallet.publicKey,
@@ -154,6 +164,8 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: positionPDA,
+          pool: collateralCustodyAccount.pool,
+          collateralCustody,
         })
         .rpc({ commitment: "confirmed" });
 
@@ -185,6 +197,16 @@ export class PerpetualsAdapter {
     }
   }
 
+  /**
+   * Custody holding a position's locked notional. Positions opened before
+   * the field existed carry the default key and lock nothing.
+   */
+  private positionCollateralCustody(position: any): PublicKey | undefined {
+    return position.collateralCustody && !position.collateralCustody.equals(PublicKey.default)
+      ? position.collateralCustody
+      : this.defaultCollateralCustody;
+  }
+
   async closePosition(params: ClosePositionParams): Promise<TransactionResult> {
     await this.ensureInitialized();
 
@@ -195,6 +217,11 @@ export class PerpetualsAdapter {
       const position = await this.program.account.position.fetch(
         params.positionKey
       ) as any;
+      const collateralCustody = this.positionCollateralCustody(position);
+      if (!collateralCustody) {
+        throw new Error("No collateral custody configured");
+      }
+      const collateralCustodyAccount = await this.program.account.custody.fetch(collateralCustody);
 
       const computationOffset = generateComputationOffset();
 
@@ -226,7 +253,10 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
-          owner: this.provider.wallet.publicKey,
+          collateralCustody,
+          collateralCustodyOracleAccount: collateralCustodyAccount.oracle.oracleAccount,
+          owner: position.owner,
+          authority: this.provider.wallet.publicKey,
         })
         .rpc({ commitment: "confirmed" });
 
@@ -305,6 +335,7 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
+          collateralCustody: this.positionCollateralCustody(position),
           owner: this.provider.wallet.publicKey,
         })
         .rpc({ commitment: "confirmed" });
@@ -384,6 +415,7 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
+          collateralCustody: this.positionCollateralCustody(position),
           owner: this.provider.wallet.publicKey,
         })
         .rpc({ commitment: "confirmed" });
@@ -454,6 +486,7 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
+          collateralCustody: this.positionCollateralCustody(position),
           liquidator: this.provider.wallet.publicKey,
         })
         .rpc({ commitment: "confirmed" });
@@ -593,7 +626,7 @@ export class PerpetualsAdapter {
         })
         .view();
 
-      return result as anchor.BN;
+      return result.price as anchor.BN;
     } catch (error) {
       console.error("[Adapter] Error getting oracle price:", error);
       return null;
@@ -623,6 +656,9 @@ export class PerpetualsAdapter {
         this.program.programId
       )[0];
 
+      const receivingCustodyData = await this.program.account.custody.fetch(receivingCustody) as any;
+      const dispensingCustodyData = await this.program.account.custody.fetch(dispensingCustody) as any;
+
       const signature = await this.program.methods
         .swap({
           amountIn: params.amountIn,
@@ -632,12 +668,14 @@ export class PerpetualsAdapter {
           owner: this.provider.publicKey,
           fundingAccount: params.fundingAccount,
           receivingAccount: params.receivingAccount,
-          transferAuthority: this.provider.publicKey,
+          transferAuthority: this.getTransferAuthorityPDA(),
           perpetuals: this.getPerpetualsPDA(),
           pool,
           receivingCustody,
+          receivingCustodyOracleAccount: receivingCustodyData.oracle.oracleAccount,
           receivingCustodyTokenAccount,
           dispensingCustody,
+          dispensingCustodyOracleAccount: dispensingCustodyData.oracle.oracleAccount,
           dispensingCustodyTokenAccount,
         })
         .rpc();
@@ -683,7 +721,7 @@ export class PerpetualsAdapter {
           owner: this.provider.publicKey,
           fundingAccount: params.fundingAccount,
           lpTokenAccount: params.lpTokenAccount,
-          transferAuthority: this.provider.publicKey,
+          transferAuthority: this.getTransferAuthorityPDA(),
           perpetuals: this.getPerpetualsPDA(),
           pool,
           custody,
@@ -723,6 +761,15 @@ export class PerpetualsAdapter {
         [Buffer.from("lp_token_mint"), pool.toBuffer()],
         this.program.programId
       )[0];
+      // Escrows whatever the custody's free liquidity can't pay out now
+      const withdrawalClaim = PublicKey.findProgramAddressSync(
+        [Buffer.from("withdrawal_claim"), custody.toBuffer(), this.provider.publicKey.toBuffer()],
+        this.program.programId
+      )[0];
+      const withdrawalEscrow = PublicKey.findProgramAddressSync(
+        [Buffer.from("withdrawal_escrow"), pool.toBuffer()],
+        this.program.programId
+      )[0];
 
       const signature = await this.program.methods
         .removeLiquidity({
@@ -733,12 +780,14 @@ export class PerpetualsAdapter {
           owner: this.provider.publicKey,
           receivingAccount: params.receivingAccount,
           lpTokenAccount: params.lpTokenAccount,
-          transferAuthority: this.provider.publicKey,
+          transferAuthority: this.getTransferAuthorityPDA(),
           perpetuals: this.getPerpetualsPDA(),
           pool,
           custody,
           custodyTokenAccount,
           lpTokenMint,
+          withdrawalClaim,
+          withdrawalEscrow,
         })
         .rpc();
 
@@ -865,7 +914,7 @@ export class PerpetualsAdapter {
         })
         .view();
 
-      return result as anchor.BN;
+      return result.aumUsd as anchor.BN;
     } catch (error) {
       console.error("[Adapter] Error getting AUM:", error);
       return null;
@@ -889,7 +938,7 @@ export class PerpetualsAdapter {
         })
         .view();
 
-      return result as anchor.BN;
+      return result.price as anchor.BN;
     } catch (error) {
       console.error("[Adapter] Error getting LP token price:", error);
       return null;
@@ -904,6 +953,13 @@ export class PerpetualsAdapter {
     )[0];
   }
 
+  private getTransferAuthorityPDA(): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("transfer_authority")],
+      this.program.programId
+    )[0];
+  }
+
   private getClusterAccount(): PublicKey {
     const arciumEnv = getArciumEnv();
     return getClusterAccAddress(arciumEnv.arciumClusterOffset);
@@ -196,6 +196,13 @@ export function generateComputationOffset(): anchor.BN {
   return new anchor.BN(randomBytes(8));
 }
 
+/**
+ * Generate a random 16-byte nonce for an MXE-encrypted output
+ */
+export function generateNonce(): Uint8Array {
+  return randomBytes(16);
+}
+
 /**
  * Generate a random position ID
  */
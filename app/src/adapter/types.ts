@@ -56,6 +56,8 @@ export interface OpenPositionParams {
   pool?: PublicKey;
   custody?: PublicKey;
   collateralCustody?: PublicKey;
+  clientRequestId?: anchor.BN;
+  clientTag?: number[];
 }
 
 export interface ClosePositionParams {
@@ -209,7 +209,8 @@ function setCustomOraclePrice(
   price: number,
   exponent: number,
   confidence: number,
-  ema: number
+  ema: number,
+  overridePriceBand: boolean
 ): Promise<void> {
   const priceConfig: SetCustomOraclePriceParams = {
     price: new BN(price),
@@ -217,6 +218,7 @@ function setCustomOraclePrice(
     conf: new BN(confidence),
     ema: new BN(ema),
     publishTime: new BN(client.getTime()),
+    overridePriceBand,
   };
 
   return client.setCustomOraclePrice(poolName, tokenMint, priceConfig);
@@ -451,7 +453,8 @@ program
   .option("--expo <number>", "Exponent", "-8")
   .option("--conf <number>", "Confidence", "0")
   .option("--ema <number>", "EMA", "0")
-  .action(async (poolName: string, tokenMint: string, price: string, options: { expo: string; conf: string; ema?: string }) => {
+  .option("--override", "Schedule a timelocked override when the price moves outside the allowed band")
+  .action(async (poolName: string, tokenMint: string, price: string, options: { expo: string; conf: string; ema?: string; override?: boolean }) => {
     ensureClient();
     await setCustomOraclePrice(
       poolName,
@@ -459,7 +462,8 @@ program
       parseInt(price),
       parseInt(options.expo),
       parseInt(options.conf),
-      options.ema ? parseInt(options.ema) : parseInt(price)
+      options.ema ? parseInt(options.ema) : parseInt(price),
+      !!options.override
     );
     client.log("Oracle price set successfully");
   });
@@ -24,6 +24,8 @@ import {
   OracleParams,
   PricingParams,
   Permissions,
+  PriorityFees,
+  LiquidationPriority,
   Fees,
   BorrowRateParams,
   SetCustomOraclePriceParams,
@@ -34,6 +36,10 @@ import {
   SwapAmountAndFees,
 } from "./types";
 
+const TOKEN_METADATA_PROGRAM_ID = new PublicKey(
+  "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"
+);
+
 export class PerpetualsClient {
   provider: AnchorProvider;
   program: Program<Perpetuals>;
@@ -310,6 +316,14 @@ export class PerpetualsClient {
     return this.findProgramAddress("oracle_account", [poolKey, tokenMint]).publicKey;
   };
 
+  getWithdrawalClaimKey = (custodyKey: PublicKey, owner: PublicKey): PublicKey => {
+    return this.findProgramAddress("withdrawal_claim", [custodyKey, owner]).publicKey;
+  };
+
+  getWithdrawalEscrowKey = (poolKey: PublicKey): PublicKey => {
+    return this.findProgramAddress("withdrawal_escrow", [poolKey]).publicKey;
+  };
+
   getCustody = async (poolName: string, tokenMint: PublicKey) => {
     return this.program.account.custody.fetch(
       await this.getCustodyKey(poolName, tokenMint)
@@ -465,7 +479,10 @@ export class PerpetualsClient {
     }
   };
 
-  addPool = async (name: string): Promise<void> => {
+  addPool = async (
+    name: string,
+    lpTokenMetadata?: { symbol: string; uri: string }
+  ): Promise<void> => {
     const poolKey = await this.getNextPoolKey();
     const lpTokenMintKey = this.findProgramAddress("lp_token_mint", [poolKey]).publicKey;
     
@@ -487,7 +504,11 @@ export class PerpetualsClient {
     }
     
     const signature = await this.program.methods
-      .addPool({ name })
+      .addPool({
+        name,
+        lpTokenSymbol: lpTokenMetadata?.symbol ?? "",
+        lpTokenUri: lpTokenMetadata?.uri ?? "",
+      })
       .accountsPartial({
         admin: this.admin.publicKey,
         pool: poolKey,
@@ -497,6 +518,10 @@ export class PerpetualsClient {
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
         rent: SYSVAR_RENT_PUBKEY,
+        lpTokenMetadata: lpTokenMetadata
+          ? this.getLpTokenMetadataKey(lpTokenMintKey)
+          : null,
+        metadataProgram: lpTokenMetadata ? TOKEN_METADATA_PROGRAM_ID : null,
       })
       .signers([this.admin])
       .rpc()
@@ -527,6 +552,41 @@ export class PerpetualsClient {
     this.log(`Pool "${name}" added successfully`);
   };
 
+  setLpTokenMetadata = async (
+    poolName: string,
+    name: string,
+    symbol: string,
+    uri: string
+  ): Promise<void> => {
+    const poolKey = await this.getPoolKey(poolName);
+    const lpTokenMintKey = this.findProgramAddress("lp_token_mint", [poolKey]).publicKey;
+
+    await this.program.methods
+      .setLpTokenMetadata({ name, symbol, uri })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        multisig: this.multisig.publicKey,
+        perpetuals: this.perpetuals.publicKey,
+        pool: poolKey,
+        lpTokenMint: lpTokenMintKey,
+        lpTokenMetadata: this.getLpTokenMetadataKey(lpTokenMintKey),
+        metadataProgram: TOKEN_METADATA_PROGRAM_ID,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  getLpTokenMetadataKey = (lpTokenMint: PublicKey): PublicKey => {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), lpTokenMint.toBuffer()],
+      TOKEN_METADATA_PROGRAM_ID
+    )[0];
+  };
+
   removePool = async (name: string): Promise<void> => {
     await this.program.methods
       .removePool({})
@@ -551,7 +611,8 @@ export class PerpetualsClient {
     permissions: Permissions,
     fees: Fees,
     borrowRate: BorrowRateParams,
-    ratios: TokenRatio[]
+    ratios: TokenRatio[],
+    maxPriceDriftBpsPerSlot = 0
   ): Promise<void> => {
     // Use getPoolKey which will find the pool even if not in perpetuals.pools array
     const poolAddress = await this.getPoolKey(poolName);
@@ -566,7 +627,7 @@ export class PerpetualsClient {
     )[0];
 
     await this.program.methods
-      .addCustody({
+      .addCustodyV2({
         isStable,
         isVirtual,
         oracle: oracleConfig,
@@ -575,6 +636,7 @@ export class PerpetualsClient {
         fees,
         borrowRate,
         ratios,
+        maxPriceDriftBpsPerSlot: new BN(maxPriceDriftBpsPerSlot),
       })
       .accountsPartial({
         admin: this.admin.publicKey,
@@ -661,6 +723,126 @@ export class PerpetualsClient {
       });
   };
 
+  proposeCustodyConfig = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    pricingConfig: PricingParams,
+    fees: Fees,
+    borrowRate: BorrowRateParams
+  ): Promise<void> => {
+    await this.program.methods
+      .proposeCustodyConfig({
+        pricing: pricingConfig,
+        fees,
+        borrowRate,
+      })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        custody: await this.getCustodyKey(poolName, tokenMint),
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  executeCustodyConfig = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    proposer: PublicKey
+  ): Promise<void> => {
+    await this.program.methods
+      .executeCustodyConfig()
+      .accountsPartial({
+        executor: this.admin.publicKey,
+        proposer,
+        custody: await this.getCustodyKey(poolName, tokenMint),
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setConfigTimelock = async (timelockSec: number): Promise<void> => {
+    await this.program.methods
+      .setConfigTimelock({ timelockSec: new BN(timelockSec) })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setPriorityFees = async (priorityFees: PriorityFees): Promise<void> => {
+    await this.program.methods
+      .setPriorityFees({ priorityFees })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setComputationQuota = async (maxInFlightComputations: number): Promise<void> => {
+    await this.program.methods
+      .setComputationQuota({ maxInFlightComputations })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setLiquidationPriority = async (
+    liquidationPriority: LiquidationPriority
+  ): Promise<void> => {
+    await this.program.methods
+      .setLiquidationPriority({ liquidationPriority })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setCompDefOffset = async (
+    defaultOffset: number,
+    offset: number
+  ): Promise<void> => {
+    await this.program.methods
+      .setCompDefOffset({ defaultOffset, offset })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
   upgradeCustody = async (
     poolName: string,
     tokenMint: PublicKey
@@ -678,6 +860,127 @@ export class PerpetualsClient {
       });
   };
 
+  setAllowlistRoot = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    allowlistRoot: number[]
+  ): Promise<void> => {
+    await this.program.methods
+      .setAllowlistRoot({ allowlistRoot })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        multisig: this.multisig.publicKey,
+        custody: await this.getCustodyKey(poolName, tokenMint),
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setPriceFallback = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    maxPriceDriftBpsPerSlot: number
+  ): Promise<void> => {
+    await this.program.methods
+      .setPriceFallback({ maxPriceDriftBpsPerSlot: new BN(maxPriceDriftBpsPerSlot) })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        multisig: this.multisig.publicKey,
+        custody: await this.getCustodyKey(poolName, tokenMint),
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setCustodyFreeze = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    depositsFrozen: boolean,
+    withdrawalsFrozen: boolean
+  ): Promise<void> => {
+    await this.program.methods
+      .setCustodyFreeze({ depositsFrozen, withdrawalsFrozen })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        multisig: this.multisig.publicKey,
+        custody: await this.getCustodyKey(poolName, tokenMint),
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setMinHoldPeriod = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    minHoldPeriodSec: number
+  ): Promise<void> => {
+    await this.program.methods
+      .setMinHoldPeriod({ minHoldPeriodSec: new BN(minHoldPeriodSec) })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        multisig: this.multisig.publicKey,
+        custody: await this.getCustodyKey(poolName, tokenMint),
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  joinAllowlist = async (
+    owner: Keypair,
+    poolName: string,
+    tokenMint: PublicKey,
+    proof: number[][]
+  ): Promise<void> => {
+    await this.program.methods
+      .joinAllowlist({ proof })
+      .accountsPartial({
+        owner: owner.publicKey,
+        custody: await this.getCustodyKey(poolName, tokenMint),
+      })
+      .signers([owner])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setMarketMaker = async (
+    marketMakerOwner: PublicKey,
+    openPositionFee: BN,
+    isActive: boolean
+  ): Promise<void> => {
+    await this.program.methods
+      .setMarketMaker({ openPositionFee, isActive })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        multisig: this.multisig.publicKey,
+        marketMakerOwner,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
   setCustomOraclePrice = async (
     poolName: string,
     tokenMint: PublicKey,
@@ -696,6 +999,32 @@ export class PerpetualsClient {
       });
   };
 
+  seedPool = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    amount: BN,
+    fundingAccount: PublicKey
+  ): Promise<void> => {
+    await this.program.methods
+      .seedPool({ amount })
+      .accountsPartial({
+        admin: this.admin.publicKey,
+        pool: await this.getPoolKey(poolName),
+        custody: await this.getCustodyKey(poolName, tokenMint),
+        custodyOracleAccount: await this.getCustodyOracleAccountKey(
+          poolName,
+          tokenMint
+        ),
+        fundingAccount,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
   withdrawFees = async (
     poolName: string,
     tokenMint: PublicKey,
@@ -706,6 +1035,7 @@ export class PerpetualsClient {
       .withdrawFees({ amount })
       .accountsPartial({
         admin: this.admin.publicKey,
+        multisig: this.multisig.publicKey,
         receivingAccount,
       })
       .signers([this.admin])
@@ -735,7 +1065,7 @@ export class PerpetualsClient {
     poolName: string,
     tokenMint: PublicKey,
     ema: boolean
-  ): Promise<BN> => {
+  ): Promise<{ price: BN; exponent: number }> => {
     return this.program.methods
       .getOraclePrice({ ema })
       .accounts({
@@ -754,6 +1084,67 @@ export class PerpetualsClient {
       });
   };
 
+  getInitializedCompDefs = async (): Promise<
+    { compDefOffset: number; initializer: PublicKey; initTime: BN }[]
+  > => {
+    return this.program.methods
+      .getInitializedCompDefs()
+      .accounts({
+        compDefInitLog: this.findProgramAddress("comp_def_init_log").publicKey,
+      })
+      .view()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  getPriorityFees = async (): Promise<PriorityFees> => {
+    return this.program.methods
+      .getPriorityFees()
+      .accounts({
+        perpetuals: this.perpetuals.publicKey,
+      })
+      .view()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  getVersion = async (): Promise<{ version: string; gitDescribe: string }> => {
+    const result = await this.program.methods
+      .getVersion()
+      .accounts({})
+      .view()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+    const decode = (bytes: number[]) =>
+      Buffer.from(bytes).toString("utf8").replace(/\0+$/, "");
+    return {
+      version: decode(result.version),
+      gitDescribe: decode(result.gitDescribe),
+    };
+  };
+
+  getPendingInterest = async (positionKey: PublicKey): Promise<BN> => {
+    const position = await this.program.account.position.fetch(positionKey);
+    return this.program.methods
+      .getPendingInterest()
+      .accounts({
+        position: positionKey,
+        collateralCustody: position.collateralCustody,
+      })
+      .view()
+      .then((result) => result.interestUsd)
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
   getAddLiquidityAmountAndFee = async (
     poolName: string,
     tokenMint: PublicKey,
@@ -804,6 +1195,154 @@ export class PerpetualsClient {
       });
   };
 
+  refreshLpPriceOracle = async (poolName: string): Promise<void> => {
+    await this.program.methods
+      .refreshLpPriceOracle()
+      .accountsPartial({
+        payer: this.admin.publicKey,
+        pool: await this.getPoolKey(poolName),
+      })
+      .remainingAccounts(await this.getCustodyMetas(poolName))
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  reconcileCustody = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    haltOnShortfall: boolean
+  ): Promise<void> => {
+    await this.program.methods
+      .reconcileCustody({ haltOnShortfall })
+      .accountsPartial({
+        admin: haltOnShortfall ? this.admin.publicKey : null,
+        multisig: haltOnShortfall ? this.multisig.publicKey : null,
+        pool: await this.getPoolKey(poolName),
+        custody: await this.getCustodyKey(poolName, tokenMint),
+        custodyTokenAccount: await this.getCustodyTokenAccountKey(poolName, tokenMint),
+      })
+      .signers(haltOnShortfall ? [this.admin] : [])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  refreshPriceCache = async (poolName: string, tokenMint: PublicKey): Promise<void> => {
+    const custodyKey = await this.getCustodyKey(poolName, tokenMint);
+    const custody = await this.program.account.custody.fetch(custodyKey);
+
+    await this.program.methods
+      .refreshPriceCache()
+      .accountsPartial({
+        payer: this.admin.publicKey,
+        custody: custodyKey,
+        custodyOracleAccount: custody.oracle.oracleAccount,
+      })
+      .signers([this.admin])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setCloseDelegate = async (
+    owner: Keypair,
+    positionId: BN,
+    delegate: PublicKey
+  ): Promise<void> => {
+    await this.program.methods
+      .setCloseDelegate(positionId, delegate)
+      .accountsPartial({
+        owner: owner.publicKey,
+      })
+      .signers([owner])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setPositionMetadata = async (
+    owner: Keypair,
+    positionId: BN,
+    metadata: Buffer,
+    nonce: BN
+  ): Promise<void> => {
+    await this.program.methods
+      .setPositionMetadata(positionId, metadata, nonce)
+      .accountsPartial({
+        owner: owner.publicKey,
+      })
+      .signers([owner])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  setEncryptionKey = async (owner: Keypair, encPubkey: number[]): Promise<void> => {
+    await this.program.methods
+      .setEncryptionKey({ encPubkey })
+      .accountsPartial({
+        owner: owner.publicKey,
+      })
+      .signers([owner])
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  expirePendingPosition = async (
+    owner: PublicKey,
+    positionId: BN
+  ): Promise<void> => {
+    const position = await this.program.account.position.fetch(
+      this.findProgramAddress("position", [owner, positionId.toArrayLike(Buffer, "le", 8)]).publicKey
+    );
+    await this.program.methods
+      .expirePendingPosition(positionId)
+      .accountsPartial({
+        owner,
+        payerQuota: this.findProgramAddress("payer_quota", [position.pendingPayer]).publicKey,
+      })
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
+  cancelPositionTransfer = async (
+    position: PublicKey,
+    newPosition: PublicKey
+  ): Promise<void> => {
+    const pending = await this.program.account.position.fetch(newPosition);
+    await this.program.methods
+      .cancelPositionTransfer()
+      .accountsPartial({
+        position,
+        newPosition,
+        payer: pending.pendingPayer,
+        payerQuota: this.findProgramAddress("payer_quota", [pending.pendingPayer]).publicKey,
+      })
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
+
   getCustodyOracleAccountKey = async (
     poolName: string,
     tokenMint: PublicKey
@@ -942,6 +1481,8 @@ export class PerpetualsClient {
         custodyTokenAccount: custodyTokenAccountKey,
         custodyOracleAccount: custodyOracleAccountKey,
         lpTokenMint: lpTokenMintKey,
+        withdrawalClaim: this.getWithdrawalClaimKey(custodyKey, this.admin.publicKey),
+        withdrawalEscrow: this.getWithdrawalEscrowKey(poolKey),
       })
       .remainingAccounts(custodyMetas)
       .signers([this.admin])
@@ -953,4 +1494,35 @@ export class PerpetualsClient {
 
     return signature;
   };
+
+  fillWithdrawalClaim = async (
+    poolName: string,
+    tokenMint: PublicKey,
+    owner: PublicKey,
+    receivingAccount: PublicKey
+  ): Promise<string> => {
+    const poolKey = await this.getPoolKey(poolName);
+    const custodyKey = await this.getCustodyKey(poolName, tokenMint);
+
+    return this.program.methods
+      .fillWithdrawalClaim()
+      .accountsPartial({
+        owner,
+        transferAuthority: this.authority.publicKey,
+        perpetuals: this.perpetuals.publicKey,
+        pool: poolKey,
+        custody: custodyKey,
+        custodyOracleAccount: await this.getCustodyOracleAccountKey(poolName, tokenMint),
+        custodyTokenAccount: await this.getCustodyTokenAccountKey(poolName, tokenMint),
+        lpTokenMint: await this.getPoolLpTokenKey(poolName),
+        withdrawalEscrow: this.getWithdrawalEscrowKey(poolKey),
+        withdrawalClaim: this.getWithdrawalClaimKey(custodyKey, owner),
+        receivingAccount,
+      })
+      .rpc()
+      .catch((err) => {
+        console.error(err);
+        throw err;
+      });
+  };
 }
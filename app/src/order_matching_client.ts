@@ -24,6 +24,7 @@ export interface OrderParams {
   size: number;
   orderType?: "Limit" | "Market" | "IOC" | "PostOnly";
   timeInForce?: "GTT" | "IOC" | "PostOnly";
+  subAccountId?: number;
 }
 
 export interface MarketState {
@@ -44,6 +45,7 @@ export interface MarketState {
 
 export interface TraderState {
   trader: PublicKey;
+  subAccountId: number;
   marginMode: { cross?: {}; isolated?: {} };
   hasOpenPositions: boolean;
   collateralAccount: PublicKey;
@@ -96,11 +98,15 @@ export class OrderMatchingClient {
   }
 
   /**
-   * Get trader state PDA
+   * Get trader state PDA for a sub-account (defaults to the primary sub-account 0)
    */
-  getTraderStatePDA(trader: PublicKey): PublicKey {
+  getTraderStatePDA(trader: PublicKey, subAccountId: number = 0): PublicKey {
     const [pda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("trader"), trader.toBuffer()],
+      [
+        Buffer.from("trader"),
+        trader.toBuffer(),
+        Buffer.from([subAccountId & 0xff, (subAccountId >> 8) & 0xff]),
+      ],
       this.program.programId
     );
     return pda;
@@ -164,15 +170,25 @@ export class OrderMatchingClient {
   /**
    * Initialize trader state
    */
-  async initializeTraderState(marginMode: "Cross" | "Isolated"): Promise<string> {
-    const traderStatePDA = this.getTraderStatePDA(this.wallet.publicKey);
+  async initializeTraderState(
+    marginMode: "Cross" | "Isolated",
+    subAccountId: number = 0
+  ): Promise<string> {
+    const traderStatePDA = this.getTraderStatePDA(this.wallet.publicKey, subAccountId);
     const confidentialAccountPDA = PublicKey.findProgramAddressSync(
-      [Buffer.from("confidential_account"), this.wallet.publicKey.toBuffer()],
+      [
+        Buffer.from("confidential_account"),
+        this.wallet.publicKey.toBuffer(),
+        Buffer.from([subAccountId & 0xff, (subAccountId >> 8) & 0xff]),
+      ],
       this.program.programId
     )[0];
 
     const tx = await this.program.methods
-      .initializeTraderState(marginMode === "Cross" ? { cross: {} } : { isolated: {} })
+      .initializeTraderState(
+        subAccountId,
+        marginMode === "Cross" ? { cross: {} } : { isolated: {} }
+      )
       .accounts({
         trader: this.wallet.publicKey,
         traderState: traderStatePDA,
@@ -189,9 +205,10 @@ export class OrderMatchingClient {
    */
   async depositCollateralConfidential(
     amount: number,
-    mint: PublicKey
+    mint: PublicKey,
+    subAccountId: number = 0
   ): Promise<string> {
-    const traderStatePDA = this.getTraderStatePDA(this.wallet.publicKey);
+    const traderStatePDA = this.getTraderStatePDA(this.wallet.publicKey, subAccountId);
     const traderTokenAccount = await getAssociatedTokenAddress(
       mint,
       this.wallet.publicKey
@@ -221,7 +238,10 @@ export class OrderMatchingClient {
    * Submit order with encrypted size
    */
   async submitOrder(params: OrderParams): Promise<string> {
-    const traderStatePDA = this.getTraderStatePDA(this.wallet.publicKey);
+    const traderStatePDA = this.getTraderStatePDA(
+      this.wallet.publicKey,
+      params.subAccountId ?? 0
+    );
     const marketStatePDA = this.getMarketStatePDA(params.marketId);
 
     // Get current epoch ID (simplified - would need to fetch from market state)
@@ -266,8 +286,8 @@ export class OrderMatchingClient {
   /**
    * Get trader state and decrypt
    */
-  async getTraderState(): Promise<TraderState> {
-    const traderStatePDA = this.getTraderStatePDA(this.wallet.publicKey);
+  async getTraderState(subAccountId: number = 0): Promise<TraderState> {
+    const traderStatePDA = this.getTraderStatePDA(this.wallet.publicKey, subAccountId);
     const account = await this.program.account.traderState.fetch(traderStatePDA);
     return account as unknown as TraderState;
   }
@@ -56,6 +56,20 @@ export interface Permissions {
   allowSizeChange: boolean;
 }
 
+export interface PriorityFees {
+  openPosition: BN;
+  closePosition: BN;
+  updateCollateral: BN;
+  liquidate: BN;
+  swap: BN;
+  liquidity: BN;
+}
+
+export interface LiquidationPriority {
+  criticalMoveBps: BN;
+  cuPriceMicro: BN;
+}
+
 export interface Fees {
   mode: { fixed: {} } | { linear: {} } | { optimal: {} };
   ratioMult: BN;
@@ -87,6 +101,7 @@ export interface SetCustomOraclePriceParams {
   conf: BN;
   ema: BN;
   publishTime: BN;
+  overridePriceBand: boolean;
 }
 
 export interface AmountAndFee {
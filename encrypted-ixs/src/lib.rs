@@ -18,18 +18,152 @@ mod circuits {
         pub is_liquidatable: u8,
     }
 
+    /// Subset of the valuation shared with the market's risk manager
+    pub struct PositionHealth {
+        pub current_value: u64,
+        pub is_liquidatable: u8,
+    }
+
     #[instruction]
     pub fn calculate_position_value(
+        output_owner: Shared,
+        risk_owner: Shared,
+        size_ctxt: Enc<Shared, u64>,
+        collateral_ctxt: Enc<Shared, u64>,
+        entry_price: u64,
+        current_price: u64,
+        side: u8,
+    ) -> (Enc<Shared, PositionValueOutput>, Enc<Shared, PositionHealth>) {
+        let output = position_value(
+            size_ctxt.to_arcis(),
+            collateral_ctxt.to_arcis(),
+            entry_price,
+            current_price,
+            side,
+        );
+        let health = PositionHealth {
+            current_value: output.current_value,
+            is_liquidatable: output.is_liquidatable,
+        };
+
+        (output_owner.from_arcis(output), risk_owner.from_arcis(health))
+    }
+
+    /// Values four positions in one computation so keepers pay a single
+    /// queue overhead. Valuations are encrypted for `output_owner` and their
+    /// health for `risk_owner`, like `calculate_position_value`.
+    #[instruction]
+    pub fn calculate_position_value_batch(
+        output_owner: Shared,
+        risk_owner: Shared,
+        size_ctxt_0: Enc<Shared, u64>,
+        collateral_ctxt_0: Enc<Shared, u64>,
+        entry_price_0: u64,
+        side_0: u8,
+        size_ctxt_1: Enc<Shared, u64>,
+        collateral_ctxt_1: Enc<Shared, u64>,
+        entry_price_1: u64,
+        side_1: u8,
+        size_ctxt_2: Enc<Shared, u64>,
+        collateral_ctxt_2: Enc<Shared, u64>,
+        entry_price_2: u64,
+        side_2: u8,
+        size_ctxt_3: Enc<Shared, u64>,
+        collateral_ctxt_3: Enc<Shared, u64>,
+        entry_price_3: u64,
+        side_3: u8,
+        current_price: u64,
+    ) -> (Enc<Shared, [PositionValueOutput; 4]>, Enc<Shared, [PositionHealth; 4]>) {
+        let outputs = [
+            position_value(size_ctxt_0.to_arcis(), collateral_ctxt_0.to_arcis(), entry_price_0, current_price, side_0),
+            position_value(size_ctxt_1.to_arcis(), collateral_ctxt_1.to_arcis(), entry_price_1, current_price, side_1),
+            position_value(size_ctxt_2.to_arcis(), collateral_ctxt_2.to_arcis(), entry_price_2, current_price, side_2),
+            position_value(size_ctxt_3.to_arcis(), collateral_ctxt_3.to_arcis(), entry_price_3, current_price, side_3),
+        ];
+        let health = [
+            PositionHealth { current_value: outputs[0].current_value, is_liquidatable: outputs[0].is_liquidatable },
+            PositionHealth { current_value: outputs[1].current_value, is_liquidatable: outputs[1].is_liquidatable },
+            PositionHealth { current_value: outputs[2].current_value, is_liquidatable: outputs[2].is_liquidatable },
+            PositionHealth { current_value: outputs[3].current_value, is_liquidatable: outputs[3].is_liquidatable },
+        ];
+
+        (output_owner.from_arcis(outputs), risk_owner.from_arcis(health))
+    }
+
+    /// Everything a client displays for a position, so the owner decrypts
+    /// once instead of combining several ciphertexts with local math
+    pub struct PositionSummary {
+        pub size_usd: u64,
+        pub collateral_usd: u64,
+        /// `size_usd / collateral_usd` in bps
+        pub leverage_bps: u64,
+        pub liquidation_price: u64,
+        pub pnl: i64,
+    }
+
+    /// Encrypted to a fresh `output_owner` key and nonce from the client.
+    /// Reusing the position's own nonce would repeat a keystream across
+    /// summaries and the next re-encryption of the size.
+    #[instruction]
+    pub fn request_position_summary(
         output_owner: Shared,
         size_ctxt: Enc<Shared, u64>,
         collateral_ctxt: Enc<Shared, u64>,
         entry_price: u64,
         current_price: u64,
         side: u8,
-    ) -> Enc<Shared, PositionValueOutput> {
+    ) -> Enc<Shared, PositionSummary> {
         let size_usd = size_ctxt.to_arcis();
         let collateral_usd = collateral_ctxt.to_arcis();
+        let value = position_value(size_usd, collateral_usd, entry_price, current_price, side);
 
+        let leverage_bps = if collateral_usd > 0 {
+            ((size_usd as u128) * 10000 / (collateral_usd as u128)) as u64
+        } else {
+            0
+        };
+
+        // Price at which collateral plus PnL falls to the 5% threshold used
+        // by `position_value`
+        let margin = size_usd / 20;
+        let buffer = if collateral_usd > margin {
+            collateral_usd - margin
+        } else {
+            0
+        };
+        let move_to_liquidation = if size_usd > 0 {
+            ((entry_price as u128) * (buffer as u128) / (size_usd as u128)) as u64
+        } else {
+            0
+        };
+        let liquidation_price = if side == 0 {
+            if move_to_liquidation < entry_price {
+                entry_price - move_to_liquidation
+            } else {
+                0
+            }
+        } else {
+            entry_price + move_to_liquidation
+        };
+
+        let summary = PositionSummary {
+            size_usd,
+            collateral_usd,
+            leverage_bps,
+            liquidation_price,
+            pnl: value.pnl,
+        };
+
+        output_owner.from_arcis(summary)
+    }
+
+    fn position_value(
+        size_usd: u64,
+        collateral_usd: u64,
+        entry_price: u64,
+        current_price: u64,
+        side: u8,
+    ) -> PositionValueOutput {
         let price_diff = if side == 0 {
             (current_price as i64) - (entry_price as i64)
         } else {
@@ -47,20 +181,28 @@ mod circuits {
             0
         };
 
-        let output = PositionValueOutput {
+        PositionValueOutput {
             current_value,
             pnl,
             is_liquidatable,
-        };
+        }
+    }
 
-        output_owner.from_arcis(output)
+    /// Copy of the opened position for the owner's latest registered key
+    pub struct OpenPositionReceipt {
+        pub size: u64,
+        pub collateral: u64,
     }
 
     #[instruction]
     pub fn open_position(
         size_ctxt: Enc<Shared, u64>,
         collateral_ctxt: Enc<Shared, u64>,
-    ) -> (Enc<Shared, u64>, Enc<Shared, u64>) {
+        receipt_owner: Shared,
+    ) -> (Enc<Shared, u64>, Enc<Shared, u64>, u64, Enc<Shared, OpenPositionReceipt>) {
+        // Granularity of the revealed lock amount, $1,000 at 6 decimals
+        const LOCK_BUCKET_USD: u64 = 1_000_000_000;
+
         let size = size_ctxt.to_arcis();
         let collateral = collateral_ctxt.to_arcis();
 
@@ -70,9 +212,22 @@ mod circuits {
         let final_size = if is_valid { size } else { 0 };
         let final_collateral = if is_valid { collateral } else { 0 };
 
+        // Size rounded up to the next bucket, so the custody can lock a
+        // conservative notional without learning the exact size
+        let locked_bound = if final_size == 0 {
+            0
+        } else {
+            (final_size / LOCK_BUCKET_USD + 1) * LOCK_BUCKET_USD
+        };
+
         (
             size_ctxt.owner.from_arcis(final_size),
             collateral_ctxt.owner.from_arcis(final_collateral),
+            locked_bound.reveal(),
+            receipt_owner.from_arcis(OpenPositionReceipt {
+                size: final_size,
+                collateral: final_collateral,
+            }),
         )
     }
 
@@ -90,6 +245,8 @@ mod circuits {
         entry_price: u64,
         current_price: u64,
         side: u8,
+        max_payoff_mult: u64,
+        interest_usd: u64,
     ) -> Enc<Shared, ClosePositionOutput> {
         let size_usd = size_ctxt.to_arcis();
         let collateral_usd = collateral_ctxt.to_arcis();
@@ -100,9 +257,14 @@ mod circuits {
             (entry_price as i64) - (current_price as i64)
         };
 
-        let pnl = ((size_usd as i64) * price_diff) / (entry_price as i64);
+        let pnl = cap_payoff(
+            ((size_usd as i64) * price_diff) / (entry_price as i64),
+            collateral_usd,
+            max_payoff_mult,
+        );
 
-        let final_balance_i64 = (collateral_usd as i64) + pnl;
+        // Borrow interest accrued on the position is paid out of the balance
+        let final_balance_i64 = (collateral_usd as i64) + pnl - (interest_usd as i64);
         
         let can_close = if final_balance_i64 > 0 { 1 } else { 0 };
         let final_balance = if final_balance_i64 > 0 { 
@@ -219,17 +381,72 @@ mod circuits {
         entry_price: u64,
         current_price: u64,
         side: u8,
+        max_payoff_mult: u64,
     ) -> Enc<Shared, LiquidateOutput> {
-        let size_usd = size_ctxt.to_arcis();
-        let collateral_usd = collateral_ctxt.to_arcis();
+        let output = liquidation_check(
+            size_ctxt.to_arcis(),
+            collateral_ctxt.to_arcis(),
+            entry_price,
+            current_price,
+            side,
+            max_payoff_mult,
+        );
+
+        output_owner.from_arcis(output)
+    }
+
+    /// Liquidation check for four positions at once, used by `liquidate_batch`.
+    #[instruction]
+    pub fn liquidate_batch(
+        output_owner: Shared,
+        size_ctxt_0: Enc<Shared, u64>,
+        collateral_ctxt_0: Enc<Shared, u64>,
+        entry_price_0: u64,
+        side_0: u8,
+        size_ctxt_1: Enc<Shared, u64>,
+        collateral_ctxt_1: Enc<Shared, u64>,
+        entry_price_1: u64,
+        side_1: u8,
+        size_ctxt_2: Enc<Shared, u64>,
+        collateral_ctxt_2: Enc<Shared, u64>,
+        entry_price_2: u64,
+        side_2: u8,
+        size_ctxt_3: Enc<Shared, u64>,
+        collateral_ctxt_3: Enc<Shared, u64>,
+        entry_price_3: u64,
+        side_3: u8,
+        current_price: u64,
+        max_payoff_mult: u64,
+    ) -> Enc<Shared, [LiquidateOutput; 4]> {
+        let outputs = [
+            liquidation_check(size_ctxt_0.to_arcis(), collateral_ctxt_0.to_arcis(), entry_price_0, current_price, side_0, max_payoff_mult),
+            liquidation_check(size_ctxt_1.to_arcis(), collateral_ctxt_1.to_arcis(), entry_price_1, current_price, side_1, max_payoff_mult),
+            liquidation_check(size_ctxt_2.to_arcis(), collateral_ctxt_2.to_arcis(), entry_price_2, current_price, side_2, max_payoff_mult),
+            liquidation_check(size_ctxt_3.to_arcis(), collateral_ctxt_3.to_arcis(), entry_price_3, current_price, side_3, max_payoff_mult),
+        ];
+
+        output_owner.from_arcis(outputs)
+    }
 
+    fn liquidation_check(
+        size_usd: u64,
+        collateral_usd: u64,
+        entry_price: u64,
+        current_price: u64,
+        side: u8,
+        max_payoff_mult: u64,
+    ) -> LiquidateOutput {
         let price_diff = if side == 0 {
             (current_price as i64) - (entry_price as i64)
         } else {
             (entry_price as i64) - (current_price as i64)
         };
 
-        let pnl = ((size_usd as i64) * price_diff) / (entry_price as i64);
+        let pnl = cap_payoff(
+            ((size_usd as i64) * price_diff) / (entry_price as i64),
+            collateral_usd,
+            max_payoff_mult,
+        );
 
         let current_value_i64 = (collateral_usd as i64) + pnl;
         let current_value = if current_value_i64 > 0 { 
@@ -257,13 +474,40 @@ mod circuits {
             current_value
         };
 
-        let output = LiquidateOutput {
+        LiquidateOutput {
             is_liquidatable,
             remaining_collateral,
             liquidation_penalty,
-        };
+        }
+    }
 
-        output_owner.from_arcis(output)
+    /// Caps profit at `max_payoff_mult` bps of the collateral (10_000 = 1x).
+    /// Losses are untouched and 0 disables the cap.
+    fn cap_payoff(pnl: i64, collateral_usd: u64, max_payoff_mult: u64) -> i64 {
+        let max_profit = ((collateral_usd as i64) * (max_payoff_mult as i64)) / 10000;
+        if max_payoff_mult > 0 && pnl > max_profit {
+            max_profit
+        } else {
+            pnl
+        }
+    }
+
+    /// Re-encrypt a position's size and collateral to a new owner's key.
+    /// Values are passed through unchanged; only the encryption owner changes.
+    #[instruction]
+    pub fn transfer_position(
+        new_size_owner: Shared,
+        new_collateral_owner: Shared,
+        size_ctxt: Enc<Shared, u64>,
+        collateral_ctxt: Enc<Shared, u64>,
+    ) -> (Enc<Shared, u64>, Enc<Shared, u64>) {
+        let size = size_ctxt.to_arcis();
+        let collateral = collateral_ctxt.to_arcis();
+
+        (
+            new_size_owner.from_arcis(size),
+            new_collateral_owner.from_arcis(collateral),
+        )
     }
 
     // ============================================================================
@@ -0,0 +1,18 @@
+use std::process::Command;
+
+fn main() {
+    // Builds outside a git checkout (e.g. from a source tarball) still get a
+    // value so `env!` in the program compiles
+    let describe = Command::new("git")
+        .args(["describe", "--always", "--dirty", "--abbrev=12"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|describe| describe.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=PERPETUALS_GIT_DESCRIBE={}", describe);
+    println!("cargo:rerun-if-changed=../../.git/HEAD");
+    println!("cargo:rerun-if-changed=../../.git/index");
+}
@@ -0,0 +1,363 @@
+//! Feeds arbitrary instruction data and account states through the program
+//! dispatcher.
+//!
+//! CPIs (token transfers, system allocations and Arcium queue/verify calls)
+//! are stubbed to succeed without side effects, so every instruction runs
+//! its own checks and accounting against whatever state the fuzzer builds.
+//! Besides panics and unchecked arithmetic (overflow checks are enabled in
+//! the fuzz profile) the target asserts runtime-level invariants that a
+//! validator would otherwise enforce.
+//!
+//! Run from `programs/perpetuals/fuzz` with
+//! `cargo +nightly fuzz run instruction_dispatch`.
+
+#![no_main]
+
+use {
+    anchor_lang::{
+        prelude::*,
+        solana_program::{
+            entrypoint::MAX_PERMITTED_DATA_INCREASE,
+            instruction::Instruction,
+            program_stubs::{self, SyscallStubs},
+        },
+        Discriminator,
+    },
+    arbitrary::Arbitrary,
+    libfuzzer_sys::fuzz_target,
+    perpetuals::{instruction as ix, Custody, CustomOracle, Multisig, Perpetuals, Pool, Position},
+    std::sync::Once,
+};
+
+const MAX_ACCOUNTS: usize = 24;
+const MAX_DATA_LEN: usize = 4096;
+const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+
+struct FuzzStubs;
+
+impl SyscallStubs for FuzzStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, _fields: &[&[u8]]) {}
+
+    fn sol_invoke_signed(
+        &self,
+        _instruction: &Instruction,
+        _account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> std::result::Result<(), ProgramError> {
+        Ok(())
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            slot: 1_000,
+            unix_timestamp: 1_700_000_000,
+            ..Clock::default()
+        };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        0
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        0
+    }
+
+    fn sol_remaining_compute_units(&self) -> u64 {
+        1_400_000
+    }
+}
+
+fn instruction_discriminators() -> Vec<&'static [u8]> {
+    vec![
+        ix::OpenPositionPublic::DISCRIMINATOR,
+        ix::ClosePositionPublic::DISCRIMINATOR,
+        ix::AddCollateralPublic::DISCRIMINATOR,
+        ix::RemoveCollateralPublic::DISCRIMINATOR,
+        ix::LiquidatePublic::DISCRIMINATOR,
+        ix::OpenPosition::DISCRIMINATOR,
+        ix::OpenPositionCpi::DISCRIMINATOR,
+        ix::OpenPositionCallback::DISCRIMINATOR,
+        ix::ClosePosition::DISCRIMINATOR,
+        ix::ClosePositionCallback::DISCRIMINATOR,
+        ix::AddCollateral::DISCRIMINATOR,
+        ix::RemoveCollateral::DISCRIMINATOR,
+        ix::Liquidate::DISCRIMINATOR,
+        ix::LiquidateCallback::DISCRIMINATOR,
+        ix::TransferPosition::DISCRIMINATOR,
+        ix::RequestPositionSummary::DISCRIMINATOR,
+        ix::RequestPositionSummaryCallback::DISCRIMINATOR,
+        ix::ReallocPosition::DISCRIMINATOR,
+        ix::ReallocCustody::DISCRIMINATOR,
+        ix::ExpirePendingPosition::DISCRIMINATOR,
+        ix::CancelPositionTransfer::DISCRIMINATOR,
+        ix::SetCloseDelegate::DISCRIMINATOR,
+        ix::SetPositionMetadata::DISCRIMINATOR,
+        ix::SetEncryptionKey::DISCRIMINATOR,
+        ix::Swap::DISCRIMINATOR,
+        ix::SeedPool::DISCRIMINATOR,
+        ix::AddLiquidity::DISCRIMINATOR,
+        ix::RemoveLiquidity::DISCRIMINATOR,
+        ix::FillWithdrawalClaim::DISCRIMINATOR,
+        ix::WithdrawFees::DISCRIMINATOR,
+        ix::SetCustomOraclePrice::DISCRIMINATOR,
+        ix::SetMarketMaker::DISCRIMINATOR,
+        ix::RefreshPriceCache::DISCRIMINATOR,
+        ix::ReconcileCustody::DISCRIMINATOR,
+        ix::SetCustodyConfig::DISCRIMINATOR,
+        ix::SetCustodyConfigV2::DISCRIMINATOR,
+        ix::ProposeCustodyConfig::DISCRIMINATOR,
+        ix::ExecuteCustodyConfig::DISCRIMINATOR,
+        ix::SetTradingSchedule::DISCRIMINATOR,
+        ix::ApproveGovernanceChange::DISCRIMINATOR,
+        ix::SetRiskManager::DISCRIMINATOR,
+        ix::SetAllowlistRoot::DISCRIMINATOR,
+        ix::SetPriceFallback::DISCRIMINATOR,
+        ix::SetCustodyFreeze::DISCRIMINATOR,
+        ix::SetMinHoldPeriod::DISCRIMINATOR,
+        ix::JoinAllowlist::DISCRIMINATOR,
+        ix::SetPermissions::DISCRIMINATOR,
+        ix::SetAdminSigners::DISCRIMINATOR,
+        ix::SetPriorityFees::DISCRIMINATOR,
+        ix::SetComputationQuota::DISCRIMINATOR,
+        ix::SetLiquidationPriority::DISCRIMINATOR,
+        ix::SetCompDefOffset::DISCRIMINATOR,
+        ix::SetLpTokenMetadata::DISCRIMINATOR,
+        ix::GetPendingInterest::DISCRIMINATOR,
+        ix::GetPnl::DISCRIMINATOR,
+        ix::GetLiquidationState::DISCRIMINATOR,
+        ix::GetCustodyStats::DISCRIMINATOR,
+        ix::GetInitializedCompDefs::DISCRIMINATOR,
+    ]
+}
+
+fn account_discriminators() -> Vec<&'static [u8]> {
+    vec![
+        Perpetuals::DISCRIMINATOR,
+        Multisig::DISCRIMINATOR,
+        Pool::DISCRIMINATOR,
+        Custody::DISCRIMINATOR,
+        CustomOracle::DISCRIMINATOR,
+        Position::DISCRIMINATOR,
+    ]
+}
+
+/// Well-known keys are picked explicitly, the fuzzer would never guess them
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum FuzzKey {
+    Program,
+    Token,
+    System,
+    Other([u8; 32]),
+}
+
+impl FuzzKey {
+    fn pubkey(self) -> Pubkey {
+        match self {
+            FuzzKey::Program => perpetuals::ID,
+            FuzzKey::Token => TOKEN_PROGRAM_ID,
+            FuzzKey::System => anchor_lang::system_program::ID,
+            FuzzKey::Other(bytes) => Pubkey::new_from_array(bytes),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzAccount {
+    key: FuzzKey,
+    owner: FuzzKey,
+    lamports: u64,
+    is_signer: bool,
+    is_writable: bool,
+    executable: bool,
+    /// Index into the known account discriminators, or raw data when out of range
+    discriminator: u8,
+    data: Vec<u8>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzInput {
+    /// Index into the known instruction discriminators, or raw data when out of range
+    instruction: u8,
+    args: Vec<u8>,
+    accounts: Vec<FuzzAccount>,
+    /// Reuse earlier keys so PDA and `has_one` relations can line up
+    aliases: Vec<(u8, u8)>,
+}
+
+/// Backing storage laid out like the runtime's serialized input: an 8-byte
+/// length prefix before the data and realloc headroom after it.
+struct AccountStorage {
+    key: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    buffer: Vec<u8>,
+    data_len: usize,
+    is_signer: bool,
+    is_writable: bool,
+    executable: bool,
+}
+
+impl AccountStorage {
+    fn new(account: &FuzzAccount, discriminators: &[&[u8]]) -> Self {
+        let mut data = Vec::new();
+        if let Some(discriminator) = discriminators.get(account.discriminator as usize) {
+            data.extend_from_slice(discriminator);
+        }
+        data.extend_from_slice(&account.data[..account.data.len().min(MAX_DATA_LEN)]);
+
+        let mut buffer = vec![0u8; 8 + data.len() + MAX_PERMITTED_DATA_INCREASE];
+        buffer[..8].copy_from_slice(&(data.len() as u64).to_le_bytes());
+        buffer[8..8 + data.len()].copy_from_slice(&data);
+
+        Self {
+            key: account.key.pubkey(),
+            owner: account.owner.pubkey(),
+            lamports: account.lamports,
+            buffer,
+            data_len: data.len(),
+            is_signer: account.is_signer,
+            is_writable: account.is_writable,
+            executable: account.executable,
+        }
+    }
+}
+
+struct Snapshot {
+    lamports: u64,
+    data: Vec<u8>,
+    is_writable: bool,
+}
+
+fn run(input: FuzzInput) {
+    let instruction_discriminators = instruction_discriminators();
+    let account_discriminators = account_discriminators();
+
+    let mut storage: Vec<AccountStorage> = input
+        .accounts
+        .iter()
+        .take(MAX_ACCOUNTS)
+        .map(|account| AccountStorage::new(account, &account_discriminators))
+        .collect();
+    if storage.is_empty() {
+        return;
+    }
+    for (to, from) in input.aliases.iter() {
+        let len = storage.len();
+        let key = storage[*from as usize % len].key;
+        storage[*to as usize % len].key = key;
+    }
+    // A repeated key refers to the first account with that key, as it
+    // would on chain; the later storage entry is ignored
+    let first_index: Vec<usize> = (0..storage.len())
+        .map(|i| {
+            storage
+                .iter()
+                .position(|s| s.key == storage[i].key)
+                .unwrap()
+        })
+        .collect();
+
+    let mut data = Vec::new();
+    if let Some(discriminator) = instruction_discriminators.get(input.instruction as usize) {
+        data.extend_from_slice(discriminator);
+    }
+    data.extend_from_slice(&input.args);
+
+    let program_id = perpetuals::ID;
+
+    let snapshots: Vec<Snapshot>;
+    let result;
+    let account_infos_after: Vec<(u64, Vec<u8>, Pubkey)>;
+    {
+        let mut slot = vec![0usize; storage.len()];
+        let mut unique_infos: Vec<AccountInfo> = Vec::with_capacity(storage.len());
+        for (i, s) in storage.iter_mut().enumerate() {
+            if first_index[i] != i {
+                continue;
+            }
+            let (_, rest) = s.buffer.split_at_mut(8);
+            let data = &mut rest[..s.data_len];
+            slot[i] = unique_infos.len();
+            unique_infos.push(AccountInfo::new(
+                &s.key,
+                s.is_signer,
+                s.is_writable,
+                &mut s.lamports,
+                data,
+                &s.owner,
+                s.executable,
+                0,
+            ));
+        }
+        let infos: Vec<AccountInfo> = first_index
+            .iter()
+            .map(|first| unique_infos[slot[*first]].clone())
+            .collect();
+
+        snapshots = unique_infos
+            .iter()
+            .map(|info| Snapshot {
+                lamports: info.lamports(),
+                data: info.try_borrow_data().unwrap().to_vec(),
+                is_writable: info.is_writable,
+            })
+            .collect();
+
+        result = perpetuals::entry(&program_id, &infos, &data);
+
+        account_infos_after = unique_infos
+            .iter()
+            .map(|info| {
+                (
+                    info.lamports(),
+                    info.try_borrow_data().unwrap().to_vec(),
+                    *info.owner,
+                )
+            })
+            .collect();
+    }
+
+    if result.is_err() {
+        return;
+    }
+
+    // Lamports can move between accounts but never appear or disappear
+    let total_lamports_before: u128 = snapshots.iter().map(|s| s.lamports as u128).sum();
+    let total_lamports_after: u128 = account_infos_after.iter().map(|(l, _, _)| *l as u128).sum();
+    assert_eq!(total_lamports_before, total_lamports_after, "lamports not conserved");
+
+    for (before, (lamports, data, owner)) in snapshots.iter().zip(account_infos_after.iter()) {
+        // The runtime rejects any change to an account passed read-only
+        if !before.is_writable {
+            assert_eq!(before.lamports, *lamports, "read-only account lamports changed");
+            assert_eq!(&before.data, data, "read-only account data changed");
+        }
+
+        // Program-owned state written by a successful instruction must
+        // still deserialize as the type its discriminator claims
+        if *owner != program_id || data == &before.data {
+            continue;
+        }
+        let mut slice: &[u8] = data;
+        if data.starts_with(Custody::DISCRIMINATOR) {
+            assert!(Custody::try_deserialize(&mut slice).is_ok(), "custody corrupted");
+        } else if data.starts_with(Pool::DISCRIMINATOR) {
+            assert!(Pool::try_deserialize(&mut slice).is_ok(), "pool corrupted");
+        } else if data.starts_with(Position::DISCRIMINATOR) {
+            assert!(Position::try_deserialize(&mut slice).is_ok(), "position corrupted");
+        } else if data.starts_with(Multisig::DISCRIMINATOR) {
+            assert!(Multisig::try_deserialize(&mut slice).is_ok(), "multisig corrupted");
+        }
+    }
+}
+
+fuzz_target!(|input: FuzzInput| {
+    static STUBS: Once = Once::new();
+    STUBS.call_once(|| {
+        program_stubs::set_syscall_stubs(Box::new(FuzzStubs));
+    });
+
+    run(input);
+});
@@ -2,6 +2,10 @@ use anchor_lang::prelude::*;
 use arcium_anchor::prelude::*;
 use arcium_client::idl::arcium::types::CallbackAccount;
 use anchor_spl::token::{Token, Mint, TokenAccount, Transfer, MintTo, Burn};
+use anchor_spl::metadata::{
+    mpl_token_metadata, CreateMetadataAccountsV3, Metadata, UpdateMetadataAccountsV2,
+};
+use anchor_spl::metadata::mpl_token_metadata::types::DataV2;
 
 use arcium_client::idl::arcium::types::{CircuitSource, OffChainCircuitSource};
 use arcium_macros::circuit_hash;
@@ -13,12 +17,38 @@ pub mod utils;
 pub use utils::*;
 
 const COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE: u32 = comp_def_offset("calculate_position_value");
+const COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE_BATCH: u32 = comp_def_offset("calculate_position_value_batch");
 const COMP_DEF_OFFSET_OPEN_POSITION: u32 = comp_def_offset("open_position");
 const COMP_DEF_OFFSET_CLOSE_POSITION: u32 = comp_def_offset("close_position");
 const COMP_DEF_OFFSET_ADD_COLLATERAL: u32 = comp_def_offset("add_collateral");
 const COMP_DEF_OFFSET_REMOVE_COLLATERAL: u32 = comp_def_offset("remove_collateral");
 const COMP_DEF_OFFSET_LIQUIDATE: u32 = comp_def_offset("liquidate");
+const COMP_DEF_OFFSET_LIQUIDATE_BATCH: u32 = comp_def_offset("liquidate_batch");
 const COMP_DEF_OFFSET_MIX_POSITIONS: u32 = comp_def_offset("mix_positions");
+const COMP_DEF_OFFSET_TRANSFER_POSITION: u32 = comp_def_offset("transfer_position");
+const COMP_DEF_OFFSET_REQUEST_POSITION_SUMMARY: u32 = comp_def_offset("request_position_summary");
+
+/// Layout version carried as the first field of every event. Bump it whenever
+/// an event's fields change so indexers can pick the right decoder.
+const EVENT_SCHEMA_VERSION: u8 = 6;
+
+/// Largest move a custom oracle update may make relative to the stored price
+/// without going through the timelocked override.
+const CUSTOM_ORACLE_MAX_PRICE_JUMP_BPS: u64 = 2_000;
+const CUSTOM_ORACLE_OVERRIDE_TIMELOCK_SEC: i64 = 3_600;
+
+/// Number of positions handled by one `calculate_position_value_batch` or
+/// `liquidate_batch` circuit.
+const CALCULATE_POSITION_VALUE_BATCH_SIZE: usize = 4;
+const LIQUIDATE_BATCH_SIZE: usize = 4;
+
+/// Slots an encrypted open may wait for its callback before anyone can
+/// expire the half-open position and return its rent (~10 minutes).
+const PENDING_POSITION_TTL_SLOTS: u64 = 1_500;
+
+/// Compute units an MPC-queueing instruction needs left on entry to finish
+/// its own accounting and the `queue_computation` CPI.
+const MIN_QUEUE_COMPUTE_UNITS: u64 = 100_000;
 
 declare_id!("6DF5b76htRfcPdG3gWrcLvBx48AtnMbc2ZsaCvJvvhUx");
 
@@ -35,6 +65,12 @@ pub mod perpetuals {
             })),
             None,
         )?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_OPEN_POSITION,
+            ctx.accounts.payer.key(),
+        )?;
         Ok(())
     }
 
@@ -49,58 +85,73 @@ pub mod perpetuals {
         client_pubkey: [u8; 32],
         size_nonce: u128,
         collateral_nonce: u128,
+        client_request_id: Option<u64>,
+        receipt_nonce: u128,
+        client_tag: Option<[u8; 32]>,
     ) -> Result<()> {
-        require!(side <= 1, ErrorCode::InvalidPositionSide);
-
-        let position_key = ctx.accounts.position.key();
-
-        let position = &mut ctx.accounts.position;
-        position.owner = ctx.accounts.owner.key();
-        position.position_id = position_id;
-        position.side = if side == 0 {
-            PositionSide::Long
-        } else {
-            PositionSide::Short
-        };
-        position.size_usd_encrypted = size_encrypted;
-        position.collateral_usd_encrypted = collateral_encrypted;
-        position.entry_price = entry_price;
-        position.open_time = Clock::get()?.unix_timestamp;
-        position.update_time = Clock::get()?.unix_timestamp;
-        position.owner_enc_pubkey = client_pubkey;
-        position.size_nonce = size_nonce;
-        position.collateral_nonce = collateral_nonce;
-        position.liquidator = Pubkey::default();  // Initialize to default, set during liquidation
-        position.bump = ctx.bumps.position;
-
-        let args = ArgBuilder::new()
-            .x25519_pubkey(client_pubkey)
-            .plaintext_u128(size_nonce)
-            .encrypted_u64(size_encrypted)
-            .x25519_pubkey(client_pubkey)
-            .plaintext_u128(collateral_nonce)
-            .encrypted_u64(collateral_encrypted)
-            .build();
-
-        ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
-
-        queue_computation(
-            ctx.accounts,
+        queue_open_position(
+            ctx,
             computation_offset,
-            args,
-            None,
-            vec![OpenPositionCallback::callback_ix(
-                computation_offset,
-                &ctx.accounts.mxe_account,
-                &[
-                CallbackAccount { pubkey: position_key, is_writable: true },
-                ]
-            )?],
-            1,
-            0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
-        )?;
+            position_id,
+            side,
+            entry_price,
+            size_encrypted,
+            collateral_encrypted,
+            client_pubkey,
+            size_nonce,
+            collateral_nonce,
+            client_request_id,
+            receipt_nonce,
+            client_tag,
+            Pubkey::default(),
+        )
+    }
 
-        Ok(())
+    /// `open_position` for programs opening on behalf of their own PDAs,
+    /// e.g. a vault strategy. The owner signs through `invoke_signed` and
+    /// the calling program is recorded in `Position::origin_program`.
+    /// Callbacks never need the owner to sign again, and the open callback
+    /// re-derives its position and payer quota PDAs rather than trusting
+    /// whatever accounts the caller's transaction lined up.
+    pub fn open_position_cpi(
+        ctx: Context<OpenPosition>,
+        computation_offset: u64,
+        position_id: u64,
+        side: u8,
+        entry_price: u64,
+        size_encrypted: [u8; 32],
+        collateral_encrypted: [u8; 32],
+        client_pubkey: [u8; 32],
+        size_nonce: u128,
+        collateral_nonce: u128,
+        client_request_id: Option<u64>,
+        receipt_nonce: u128,
+        client_tag: Option<[u8; 32]>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.owner.key().is_on_curve(), ErrorCode::InvalidPositionOwner);
+        let instructions_sysvar = ctx
+            .accounts
+            .instructions_sysvar
+            .as_ref()
+            .ok_or(ErrorCode::NotInvokedViaCpi)?;
+        let origin_program = cpi_origin_program(instructions_sysvar)?;
+
+        queue_open_position(
+            ctx,
+            computation_offset,
+            position_id,
+            side,
+            entry_price,
+            size_encrypted,
+            collateral_encrypted,
+            client_pubkey,
+            size_nonce,
+            collateral_nonce,
+            client_request_id,
+            receipt_nonce,
+            client_tag,
+            origin_program,
+        )
     }
 
     #[arcium_callback(encrypted_ix = "open_position")]
@@ -112,17 +163,38 @@ pub mod perpetuals {
                 field_0: OpenPositionOutputStruct0 {
                     field_0: size,
                     field_1: collateral,
+                    field_2: locked_bound,
+                    field_3: receipt,
                 },
         } = match output.verify_output(
             &ctx.accounts.cluster_account,
             &ctx.accounts.computation_account
         ) {
             Ok(result) => result,
-            Err(e) => {
-                msg!("Error: {}", e);
-                return Err(ErrorCode::AbortedComputation.into())
-            },
+            Err(e) => return Err(callback_error(
+                ComputationKind::OpenPosition,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_OPEN_POSITION),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
         };
+        // A late callback must not revive a position that can already be
+        // expired; the quota is then released by expire_pending_position
+        let pending_expiry_slot = ctx.accounts.position.pending_expiry_slot;
+        require!(pending_expiry_slot != 0, ErrorCode::PositionNotPending);
+        require!(
+            Clock::get()?.slot <= pending_expiry_slot,
+            ErrorCode::PendingPositionExpired
+        );
+        // The receipt was encrypted to the key of `receipt_key_epoch`; one
+        // rotated since would leave the owner unable to read it
+        let key_epoch = ctx.accounts.encryption_key.as_ref().map_or(0, |key| key.key_epoch);
+        require!(
+            key_epoch == ctx.accounts.position.receipt_key_epoch,
+            ErrorCode::ReceiptKeyRotated
+        );
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::OpenPosition, ctx.accounts.computation_account.key());
         
         let (size_output, collateral_output) = (size, collateral);
 
@@ -132,13 +204,26 @@ pub mod perpetuals {
         let collateral_nonce = collateral_output.nonce;
 
         let position = &mut ctx.accounts.position;
+        require_nonce_advances(position.size_nonce, size_nonce)?;
+        require_nonce_advances(position.collateral_nonce, collateral_nonce)?;
         
         position.size_usd_encrypted = size_encrypted;
         position.collateral_usd_encrypted = collateral_encrypted;
         position.size_nonce = size_nonce;
         position.collateral_nonce = collateral_nonce;
+        position.locked_amount = locked_bound;
+        position.pending_expiry_slot = 0;
+        position.pending_payer = Pubkey::default();
+
+        let collateral_custody = &mut ctx.accounts.collateral_custody;
+        collateral_custody.check_utilization(locked_bound)?;
+        collateral_custody.lock_funds(locked_bound)?;
+        collateral_custody.add_position_stats(position.side, locked_bound, 0, locked_bound)?;
+        position.cumulative_interest_snapshot =
+            collateral_custody.update_cumulative_interest(Clock::get()?.unix_timestamp)?;
 
         emit!(PositionOpenedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position.position_id,
             owner: position.owner,
             side: position.side,
@@ -147,6 +232,13 @@ pub mod perpetuals {
             size_nonce,
             collateral_encrypted,
             collateral_nonce,
+            client_request_id: position.client_request_id,
+            client_tag: position.client_tag,
+            receipt_enc_pubkey: receipt.encryption_key,
+            receipt_size_encrypted: receipt.ciphertexts[0],
+            receipt_collateral_encrypted: receipt.ciphertexts[1],
+            receipt_nonce: receipt.nonce,
+            key_epoch: position.receipt_key_epoch,
         });
 
         Ok(())
@@ -170,15 +262,13 @@ pub mod perpetuals {
             custody.permissions.allow_open_position,
             ErrorCode::InvalidInput
         );
-        
-        let entry_price = get_price_from_oracle(
-            &custody.oracle,
-            &ctx.accounts.custody_oracle_account
-        )?;
-        
-        let collateral_price = get_price_from_oracle(
-            &collateral_custody.oracle,
-            &ctx.accounts.collateral_custody_oracle_account
+        require!(
+            custody.trading_schedule.is_open(Clock::get()?.unix_timestamp),
+            ErrorCode::MarketClosed
+        );
+        custody.check_allowlist(
+            &ctx.accounts.owner.key(),
+            ctx.accounts.allowlist_entry.as_deref(),
         )?;
         
         let side = if params.side == 0 {
@@ -186,6 +276,18 @@ pub mod perpetuals {
         } else {
             PositionSide::Short
         };
+
+        let prices = PriceContext::load(
+            custody,
+            &ctx.accounts.custody_oracle_account,
+            collateral_custody,
+            &ctx.accounts.collateral_custody_oracle_account,
+            None,
+            PriceUse::IncreaseRisk,
+            side,
+        )?;
+        let entry_price = prices.token_price;
+        let collateral_price = prices.collateral_price;
         
         if side == PositionSide::Long {
             require!(params.price >= entry_price, ErrorCode::InvalidInput);
@@ -205,9 +307,16 @@ pub mod perpetuals {
             ErrorCode::InvalidInput
         );
         
+        let maker_fee = ctx
+            .accounts
+            .market_maker
+            .as_ref()
+            .filter(|mm| mm.is_active && mm.owner == ctx.accounts.owner.key())
+            .map(|mm| mm.open_position_fee);
+        let base_fee = maker_fee.unwrap_or(custody.fees.open_position);
         let fee_rate = calculate_fee_rate(
             custody.fees.mode,
-            custody.fees.open_position,
+            base_fee,
             &collateral_custody,
             params.size,
         )?;
@@ -219,6 +328,7 @@ pub mod perpetuals {
             .ok_or(ErrorCode::MathOverflow)?;
         
         let locked_amount = params.size;
+        collateral_custody.check_utilization(locked_amount)?;
         
         let transfer_amount = params.collateral
             .checked_add(fee)
@@ -236,9 +346,7 @@ pub mod perpetuals {
             .checked_add(params.collateral)
             .ok_or(ErrorCode::MathOverflow)?;
         
-        collateral_custody.assets.locked = collateral_custody.assets.locked
-            .checked_add(locked_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
+        collateral_custody.lock_funds(locked_amount)?;
         
         let protocol_fee = fee
             .checked_mul(custody.fees.protocol_share)
@@ -246,15 +354,28 @@ pub mod perpetuals {
             .checked_div(10000)
             .ok_or(ErrorCode::MathOverflow)?;
         
+        // The whole fee stays in the vault as owned assets, the protocol's
+        // share of it earmarked in `protocol_fees` until `withdraw_fees`
+        collateral_custody.assets.owned = collateral_custody.assets.owned
+            .checked_add(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
         collateral_custody.assets.protocol_fees = collateral_custody.assets.protocol_fees
             .checked_add(protocol_fee)
             .ok_or(ErrorCode::MathOverflow)?;
         
         collateral_custody.collected_fees.open_position_usd = 
             collateral_custody.collected_fees.open_position_usd.wrapping_add(fee);
+        if maker_fee.is_some() {
+            collateral_custody.collected_fees.open_position_maker_usd =
+                collateral_custody.collected_fees.open_position_maker_usd.wrapping_add(fee);
+        } else {
+            collateral_custody.collected_fees.open_position_taker_usd =
+                collateral_custody.collected_fees.open_position_taker_usd.wrapping_add(fee);
+        }
         
         collateral_custody.volume_stats.open_position_usd = 
             collateral_custody.volume_stats.open_position_usd.wrapping_add(params.size);
+        collateral_custody.rolling_stats.record(Clock::get()?.unix_timestamp, params.size, fee);
         
         if side == PositionSide::Long {
             collateral_custody.trade_stats.oi_long_usd = collateral_custody.trade_stats.oi_long_usd
@@ -266,24 +387,7 @@ pub mod perpetuals {
                 .ok_or(ErrorCode::MathOverflow)?;
         }
         
-        let position_stats = if side == PositionSide::Long {
-            &mut collateral_custody.long_positions
-        } else {
-            &mut collateral_custody.short_positions
-        };
-        
-        position_stats.open_positions = position_stats.open_positions
-            .checked_add(1)
-            .ok_or(ErrorCode::MathOverflow)?;
-        position_stats.size_usd = position_stats.size_usd
-            .checked_add(params.size)
-            .ok_or(ErrorCode::MathOverflow)?;
-        position_stats.collateral_usd = position_stats.collateral_usd
-            .checked_add(params.collateral)
-            .ok_or(ErrorCode::MathOverflow)?;
-        position_stats.locked_amount = position_stats.locked_amount
-            .checked_add(locked_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
+        collateral_custody.add_position_stats(side, params.size, params.collateral, locked_amount)?;
         
         pool.aum_usd = pool.aum_usd
             .checked_add(params.collateral as u128)
@@ -312,8 +416,23 @@ pub mod perpetuals {
         position.collateral_nonce = 0;
         position.liquidator = Pubkey::default();
         position.bump = ctx.bumps.position;
+        position.version = POSITION_VERSION;
+        position.collateral_custody = collateral_custody.key();
+        position.locked_amount = locked_amount;
+        position.client_request_id = 0;
+        position.client_tag = params.client_tag.unwrap_or_default();
+        position.pending_expiry_slot = 0;
+        position.close_delegate = Pubkey::default();
+        position.receipt_key_epoch = 0;
+        position.origin_program = Pubkey::default();
+        position.last_computation_offset = 0;
+        position.last_computation_kind = 0;
+        position.cumulative_interest_snapshot =
+            collateral_custody.update_cumulative_interest(position.open_time)?;
+        position.accrued_interest_usd = 0;
         
         emit!(PositionOpenedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position.position_id,
             owner: position.owner,
             side: position.side,
@@ -322,7 +441,25 @@ pub mod perpetuals {
             size_nonce: position.size_nonce,
             collateral_encrypted: position.collateral_usd_encrypted,
             collateral_nonce: position.collateral_nonce,
+            client_request_id: position.client_request_id,
+            client_tag: position.client_tag,
+            receipt_enc_pubkey: [0; 32],
+            receipt_size_encrypted: [0; 32],
+            receipt_collateral_encrypted: [0; 32],
+            receipt_nonce: 0,
+            key_epoch: 0,
         });
+
+        if let Some(trade_log) = ctx.accounts.trade_log.as_mut() {
+            trade_log.record(TradeLogEntry {
+                position_id,
+                action: TradeAction::Open,
+                timestamp: position.open_time,
+                fee_usd: fee,
+                pnl_encrypted: [0u8; 32],
+                pnl_nonce: 0,
+            });
+        }
         
         Ok(())
     }
@@ -331,6 +468,12 @@ pub mod perpetuals {
         ctx: Context<InitCalculatePositionValueCompDef>,
     ) -> Result<()> {
         init_comp_def(ctx.accounts, None, None)?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE,
+            ctx.accounts.payer.key(),
+        )?;
         Ok(())
     }
 
@@ -341,12 +484,21 @@ pub mod perpetuals {
         current_price: u64,
         client_pubkey: [u8; 32],
         nonce: u128,
+        risk_nonce: u128,
     ) -> Result<()> {
+        require_queue_compute_budget()?;
+
+        ctx.accounts.position.record_computation(computation_offset, ComputationKind::CalculatePositionValue)?;
+
         let position = &ctx.accounts.position;
 
+        let risk_pubkey = ctx.accounts.collateral_custody.risk_report_pubkey(client_pubkey);
+
         let args = ArgBuilder::new()
             .x25519_pubkey(client_pubkey)
             .plaintext_u128(nonce)
+            .x25519_pubkey(risk_pubkey)
+            .plaintext_u128(risk_nonce)
             .x25519_pubkey(position.owner_enc_pubkey)
             .plaintext_u128(position.size_nonce)
             .account(position.key(), 8 + 32 + 8 + 1, 32)
@@ -360,6 +512,12 @@ pub mod perpetuals {
 
         ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
 
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
         queue_computation(
             ctx.accounts,
             computation_offset,
@@ -370,11 +528,19 @@ pub mod perpetuals {
                 &ctx.accounts.mxe_account,
                 &[
                 CallbackAccount { pubkey: position.key(), is_writable: true },
+                comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+                CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
                 ]
             )?],
             1,
             0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
         )?;
+        computation_queued(
+            ComputationKind::CalculatePositionValue,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
 
         Ok(())
     }
@@ -384,217 +550,629 @@ pub mod perpetuals {
         ctx: Context<CalculatePositionValueCallback>,
         output: SignedComputationOutputs<CalculatePositionValueOutput>,
     ) -> Result<()> {
-        let value_output = match output.verify_output(
+        let CalculatePositionValueOutput {
+                field_0: CalculatePositionValueOutputStruct0 {
+                    field_0: value_output,
+                    field_1: health_output,
+                },
+        } = match output.verify_output(
             &ctx.accounts.cluster_account,
             &ctx.accounts.computation_account
         ) {
-            Ok(CalculatePositionValueOutput { field_0 }) => field_0,
-            Err(e) => {
-                msg!("Error: {}", e);
-                return Err(ErrorCode::AbortedComputation.into())
-            },
+            Ok(result) => result,
+            Err(e) => return Err(callback_error(
+                ComputationKind::CalculatePositionValue,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
         };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::CalculatePositionValue, ctx.accounts.computation_account.key());
 
         let position = &ctx.accounts.position;
 
         emit!(PositionValueCalculatedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position.position_id,
             current_value_encrypted: value_output.ciphertexts[0],
             pnl_encrypted: value_output.ciphertexts[1],
             value_nonce: value_output.nonce,
         });
+        emit!(PositionRiskReportEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            position_id: position.position_id,
+            collateral_custody: position.collateral_custody,
+            current_value_encrypted: health_output.ciphertexts[0],
+            is_liquidatable_encrypted: health_output.ciphertexts[1],
+            nonce: health_output.nonce,
+        });
 
         Ok(())
     }
 
-    pub fn init_close_position_comp_def(ctx: Context<InitClosePositionCompDef>) -> Result<()> {
-        init_comp_def(
-            ctx.accounts,
-            None,
-            None,
+    pub fn init_calculate_position_value_batch_comp_def(
+        ctx: Context<InitCalculatePositionValueBatchCompDef>,
+    ) -> Result<()> {
+        init_comp_def(ctx.accounts, None, None)?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE_BATCH,
+            ctx.accounts.payer.key(),
         )?;
         Ok(())
     }
 
-    pub fn close_position(
-        ctx: Context<ClosePosition>,
+    /// Values up to `CALCULATE_POSITION_VALUE_BATCH_SIZE` positions, passed as
+    /// remaining accounts, with a single computation. Short batches are padded
+    /// by repeating the last position; events are only emitted for real ones.
+    pub fn calculate_position_value_batch(
+        ctx: Context<CalculatePositionValueBatch>,
         computation_offset: u64,
-        _position_id: u64,
         current_price: u64,
         client_pubkey: [u8; 32],
         nonce: u128,
+        risk_nonce: u128,
     ) -> Result<()> {
-        let position = &ctx.accounts.position;
+        require_queue_compute_budget()?;
 
+        let position_infos = ctx.remaining_accounts;
         require!(
-            position.owner == ctx.accounts.owner.key(),
-            ErrorCode::InvalidPositionOwner
+            !position_infos.is_empty() && position_infos.len() <= CALCULATE_POSITION_VALUE_BATCH_SIZE,
+            ErrorCode::InvalidInput
         );
 
+        let collateral_custody = ctx.accounts.collateral_custody.key();
+        let mut positions = Vec::with_capacity(position_infos.len());
+        for info in position_infos {
+            require_keys_eq!(*info.owner, crate::ID, ErrorCode::InvalidInput);
+            let position = Position::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            require_keys_eq!(
+                position.collateral_custody,
+                collateral_custody,
+                ErrorCode::InvalidInput
+            );
+            positions.push((info.key(), position));
+        }
 
-        let args = ArgBuilder::new()
+        let risk_pubkey = ctx.accounts.collateral_custody.risk_report_pubkey(client_pubkey);
+        let mut args = ArgBuilder::new()
             .x25519_pubkey(client_pubkey)
             .plaintext_u128(nonce)
-            .x25519_pubkey(position.owner_enc_pubkey)
-            .plaintext_u128(position.size_nonce)
-            .account(position.key(), 8 + 32 + 8 + 1, 32) // size_usd_encrypted
-            .x25519_pubkey(position.owner_enc_pubkey)
-            .plaintext_u128(position.collateral_nonce)
-            .account(position.key(), 8 + 32 + 8 + 1 + 32, 32) // collateral_usd_encrypted
-            .plaintext_u64(position.entry_price)
-            .plaintext_u64(current_price)
-            .plaintext_u8(position.side as u8)
-            .build();
+            .x25519_pubkey(risk_pubkey)
+            .plaintext_u128(risk_nonce);
+        for idx in 0..CALCULATE_POSITION_VALUE_BATCH_SIZE {
+            let (position_key, position) = &positions[idx.min(positions.len() - 1)];
+            args = args
+                .x25519_pubkey(position.owner_enc_pubkey)
+                .plaintext_u128(position.size_nonce)
+                .account(*position_key, 8 + 32 + 8 + 1, 32) // size_usd_encrypted
+                .x25519_pubkey(position.owner_enc_pubkey)
+                .plaintext_u128(position.collateral_nonce)
+                .account(*position_key, 8 + 32 + 8 + 1 + 32, 32) // collateral_usd_encrypted
+                .plaintext_u64(position.entry_price)
+                .plaintext_u8(position.side as u8);
+        }
+        let args = args.plaintext_u64(current_price).build();
+
+        let mut callback_accounts = vec![
+            comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+            CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
+        ];
+        callback_accounts.extend(
+            positions
+                .iter()
+                .map(|(position_key, _)| CallbackAccount { pubkey: *position_key, is_writable: false }),
+        );
 
         ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
 
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
         queue_computation(
             ctx.accounts,
             computation_offset,
             args,
             None,
-            vec![ClosePositionCallback::callback_ix(
+            vec![CalculatePositionValueBatchCallback::callback_ix(
                 computation_offset,
                 &ctx.accounts.mxe_account,
-                &[
-                CallbackAccount { pubkey: position.key(), is_writable: true },
-                ]
+                &callback_accounts,
             )?],
             1,
             0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
         )?;
+        computation_queued(
+            ComputationKind::CalculatePositionValueBatch,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
 
         Ok(())
     }
 
-    #[arcium_callback(encrypted_ix = "close_position")]
-    pub fn close_position_callback(
-        ctx: Context<ClosePositionCallback>,
-        output: SignedComputationOutputs<ClosePositionOutput>,
+    #[arcium_callback(encrypted_ix = "calculate_position_value_batch")]
+    pub fn calculate_position_value_batch_callback(
+        ctx: Context<CalculatePositionValueBatchCallback>,
+        output: SignedComputationOutputs<CalculatePositionValueBatchOutput>,
     ) -> Result<()> {
-        let close_output = match output.verify_output(
+        let CalculatePositionValueBatchOutput {
+                field_0: CalculatePositionValueBatchOutputStruct0 {
+                    field_0: value_output,
+                    field_1: health_output,
+                },
+        } = match output.verify_output(
             &ctx.accounts.cluster_account,
             &ctx.accounts.computation_account
         ) {
-            Ok(ClosePositionOutput { field_0 }) => field_0,
-            Err(e) => {
-                msg!("Error: {}", e);
-                return Err(ErrorCode::AbortedComputation.into())
-            },
+            Ok(result) => result,
+            Err(e) => return Err(callback_error(
+                ComputationKind::CalculatePositionValueBatch,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE_BATCH),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
         };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::CalculatePositionValueBatch, ctx.accounts.computation_account.key());
 
-        let position = &mut ctx.accounts.position;
-        
-        position.size_usd_encrypted = [0; 32];
-        position.update_time = Clock::get()?.unix_timestamp;
-
-        emit!(PositionClosedEvent {
-            position_id: position.position_id,
-            owner: position.owner,
-            realized_pnl_encrypted: close_output.ciphertexts[0],
-            final_balance_encrypted: close_output.ciphertexts[1],
-            can_close_encrypted: close_output.ciphertexts[2],
-            nonce: close_output.nonce,
-        });
+        // Positions come back as callback accounts in queue order; each
+        // PositionValueOutput spans three ciphertexts and each PositionHealth
+        // two.
+        require!(
+            ctx.remaining_accounts.len() * 3 <= value_output.ciphertexts.len()
+                && ctx.remaining_accounts.len() * 2 <= health_output.ciphertexts.len(),
+            ErrorCode::MalformedComputationOutput
+        );
+        for (idx, info) in ctx.remaining_accounts.iter().enumerate() {
+            let position = Position::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            emit!(PositionValueCalculatedEvent {
+                schema_version: EVENT_SCHEMA_VERSION,
+                position_id: position.position_id,
+                current_value_encrypted: value_output.ciphertexts[idx * 3],
+                pnl_encrypted: value_output.ciphertexts[idx * 3 + 1],
+                value_nonce: value_output.nonce,
+            });
+            emit!(PositionRiskReportEvent {
+                schema_version: EVENT_SCHEMA_VERSION,
+                position_id: position.position_id,
+                collateral_custody: position.collateral_custody,
+                current_value_encrypted: health_output.ciphertexts[idx * 2],
+                is_liquidatable_encrypted: health_output.ciphertexts[idx * 2 + 1],
+                nonce: health_output.nonce,
+            });
+        }
 
         Ok(())
     }
 
-    pub fn init_add_collateral_comp_def(ctx: Context<InitAddCollateralCompDef>) -> Result<()> {
-        init_comp_def(
-            ctx.accounts,
-            None,
-            None,
+    pub fn init_request_position_summary_comp_def(
+        ctx: Context<InitRequestPositionSummaryCompDef>,
+    ) -> Result<()> {
+        init_comp_def(ctx.accounts, None, None)?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_REQUEST_POSITION_SUMMARY,
+            ctx.accounts.payer.key(),
         )?;
         Ok(())
     }
 
-    pub fn add_collateral(
-        ctx: Context<AddCollateral>,
+    /// Values a position at the custody's oracle price and returns size,
+    /// collateral, leverage, liquidation price and PnL in one ciphertext set
+    /// encrypted to the owner's key, see `PositionSummaryEvent`.
+    pub fn request_position_summary(
+        ctx: Context<RequestPositionSummary>,
         computation_offset: u64,
         _position_id: u64,
-        additional_collateral_encrypted: [u8; 32],
         client_pubkey: [u8; 32],
-        additional_collateral_nonce: u128,
+        nonce: u128,
     ) -> Result<()> {
-        let position = &ctx.accounts.position;
+        require_queue_compute_budget()?;
 
-        require!(
-            position.owner == ctx.accounts.owner.key(),
-            ErrorCode::InvalidPositionOwner
-        );
+        ctx.accounts.position.record_computation(computation_offset, ComputationKind::RequestPositionSummary)?;
+
+        let current_price = get_price_from_oracle(
+            &ctx.accounts.custody.oracle,
+            &ctx.accounts.custody_oracle_account,
+        )?;
+        let position = &ctx.accounts.position;
 
         let args = ArgBuilder::new()
-            .x25519_pubkey(position.owner_enc_pubkey)
-            .plaintext_u128(position.collateral_nonce)
-            .account(position.key(), 8 + 32 + 8 + 1 + 32, 32) // collateral_usd_encrypted
             .x25519_pubkey(client_pubkey)
-            .plaintext_u128(additional_collateral_nonce)
-            .encrypted_u64(additional_collateral_encrypted)
+            .plaintext_u128(nonce)
             .x25519_pubkey(position.owner_enc_pubkey)
             .plaintext_u128(position.size_nonce)
-            .account(position.key(), 8 + 32 + 8 + 1, 32) // size_usd_encrypted
+            .account(position.key(), 8 + 32 + 8 + 1, 32)
+            .x25519_pubkey(position.owner_enc_pubkey)
+            .plaintext_u128(position.collateral_nonce)
+            .account(position.key(), 8 + 32 + 8 + 1 + 32, 32)
+            .plaintext_u64(position.entry_price)
+            .plaintext_u64(current_price)
+            .plaintext_u8(position.side as u8)
             .build();
 
         ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
 
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
         queue_computation(
             ctx.accounts,
             computation_offset,
             args,
             None,
-            vec![AddCollateralCallback::callback_ix(
+            vec![RequestPositionSummaryCallback::callback_ix(
                 computation_offset,
                 &ctx.accounts.mxe_account,
                 &[
-                CallbackAccount { pubkey: position.key(), is_writable: true },
+                CallbackAccount { pubkey: position.key(), is_writable: false },
+                comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+                CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
                 ]
             )?],
             1,
-            0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
+            0,
         )?;
+        computation_queued(
+            ComputationKind::RequestPositionSummary,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
 
         Ok(())
     }
 
-    #[arcium_callback(encrypted_ix = "add_collateral")]
-    pub fn add_collateral_callback(
-        ctx: Context<AddCollateralCallback>,
-        output: SignedComputationOutputs<AddCollateralOutput>,
+    #[arcium_callback(encrypted_ix = "request_position_summary")]
+    pub fn request_position_summary_callback(
+        ctx: Context<RequestPositionSummaryCallback>,
+        output: SignedComputationOutputs<RequestPositionSummaryOutput>,
     ) -> Result<()> {
-        let collateral_output = match output.verify_output(
+        let summary = match output.verify_output(
             &ctx.accounts.cluster_account,
             &ctx.accounts.computation_account
         ) {
-            Ok(AddCollateralOutput { field_0 }) => field_0,
-            Err(e) => {
-                msg!("Error: {}", e);
-                return Err(ErrorCode::AbortedComputation.into())
-            },
+            Ok(RequestPositionSummaryOutput { field_0 }) => field_0,
+            Err(e) => return Err(callback_error(
+                ComputationKind::RequestPositionSummary,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_REQUEST_POSITION_SUMMARY),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
         };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::RequestPositionSummary, ctx.accounts.computation_account.key());
 
-        let position = &mut ctx.accounts.position;
-        
-        position.collateral_usd_encrypted = collateral_output.ciphertexts[0];
-        position.collateral_nonce = collateral_output.nonce;
-        position.update_time = Clock::get()?.unix_timestamp;
-
-        emit!(CollateralAddedEvent {
+        let position = &ctx.accounts.position;
+        emit!(PositionSummaryEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position.position_id,
             owner: position.owner,
-            new_collateral_encrypted: collateral_output.ciphertexts[0],
-            new_leverage_encrypted: collateral_output.ciphertexts[1],
-            nonce: collateral_output.nonce,
+            size_usd_encrypted: summary.ciphertexts[0],
+            collateral_usd_encrypted: summary.ciphertexts[1],
+            leverage_bps_encrypted: summary.ciphertexts[2],
+            liquidation_price_encrypted: summary.ciphertexts[3],
+            pnl_encrypted: summary.ciphertexts[4],
+            nonce: summary.nonce,
         });
 
         Ok(())
     }
 
-    pub fn add_collateral_public(
-        ctx: Context<AddCollateralPublic>,
-        _position_id: u64,
-        params: AddCollateralPublicParams,
-    ) -> Result<()> {
+    pub fn init_close_position_comp_def(ctx: Context<InitClosePositionCompDef>) -> Result<()> {
+        init_comp_def(
+            ctx.accounts,
+            None,
+            None,
+        )?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_CLOSE_POSITION,
+            ctx.accounts.payer.key(),
+        )?;
+        Ok(())
+    }
+
+    pub fn close_position(
+        ctx: Context<ClosePosition>,
+        computation_offset: u64,
+        _position_id: u64,
+        current_price: u64,
+        client_pubkey: [u8; 32],
+        nonce: u128,
+        client_request_id: Option<u64>,
+    ) -> Result<()> {
+        require_queue_compute_budget()?;
+
+        ctx.accounts.position.record_computation(computation_offset, ComputationKind::ClosePosition)?;
+
+        // Interest up to now is charged by the circuit; the callback books it
+        let cumulative_interest = ctx.accounts.collateral_custody
+            .update_cumulative_interest(Clock::get()?.unix_timestamp)?;
+        ctx.accounts.position.accrue_interest(cumulative_interest)?;
+
+        let position = &mut ctx.accounts.position;
+
+        require!(
+            position.owner == ctx.accounts.owner.key(),
+            ErrorCode::InvalidPositionOwner
+        );
+        let mark_price = get_mark_price(
+            &ctx.accounts.collateral_custody,
+            &ctx.accounts.collateral_custody_oracle_account,
+            ctx.accounts.price_cache.as_ref(),
+            PriceUse::ReduceRisk,
+            position.side,
+        )?;
+        ctx.accounts.collateral_custody.check_min_hold(
+            position.side,
+            position.open_time,
+            position.entry_price,
+            mark_price,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        // A retried close that already reached the chain must not queue a
+        // second computation
+        if let Some(request_id) = client_request_id {
+            require!(
+                request_id != 0 && request_id != position.client_request_id,
+                ErrorCode::DuplicateClientRequest
+            );
+            position.client_request_id = request_id;
+        }
+
+        let position = &ctx.accounts.position;
+        let args = ArgBuilder::new()
+            .x25519_pubkey(client_pubkey)
+            .plaintext_u128(nonce)
+            .x25519_pubkey(position.owner_enc_pubkey)
+            .plaintext_u128(position.size_nonce)
+            .account(position.key(), 8 + 32 + 8 + 1, 32) // size_usd_encrypted
+            .x25519_pubkey(position.owner_enc_pubkey)
+            .plaintext_u128(position.collateral_nonce)
+            .account(position.key(), 8 + 32 + 8 + 1 + 32, 32) // collateral_usd_encrypted
+            .plaintext_u64(position.entry_price)
+            .plaintext_u64(current_price)
+            .plaintext_u8(position.side as u8)
+            .plaintext_u64(ctx.accounts.collateral_custody.pricing.max_payoff_mult)
+            .plaintext_u64(position.accrued_interest_usd)
+            .build();
+
+        ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
+
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
+        queue_computation(
+            ctx.accounts,
+            computation_offset,
+            args,
+            None,
+            vec![ClosePositionCallback::callback_ix(
+                computation_offset,
+                &ctx.accounts.mxe_account,
+                &[
+                CallbackAccount { pubkey: position.key(), is_writable: true },
+                CallbackAccount { pubkey: ctx.accounts.collateral_custody.key(), is_writable: true },
+                comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+                CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
+                ]
+            )?],
+            1,
+            0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
+        )?;
+        computation_queued(
+            ComputationKind::ClosePosition,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
+
+        Ok(())
+    }
+
+    #[arcium_callback(encrypted_ix = "close_position")]
+    pub fn close_position_callback(
+        ctx: Context<ClosePositionCallback>,
+        output: SignedComputationOutputs<ClosePositionOutput>,
+    ) -> Result<()> {
+        let close_output = match output.verify_output(
+            &ctx.accounts.cluster_account,
+            &ctx.accounts.computation_account
+        ) {
+            Ok(ClosePositionOutput { field_0 }) => field_0,
+            Err(e) => return Err(callback_error(
+                ComputationKind::ClosePosition,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_CLOSE_POSITION),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
+        };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::ClosePosition, ctx.accounts.computation_account.key());
+
+        let position = &mut ctx.accounts.position;
+        
+        position.size_usd_encrypted = [0; 32];
+        position.update_time = Clock::get()?.unix_timestamp;
+
+        let collateral_custody = &mut ctx.accounts.collateral_custody;
+        collateral_custody.collected_fees.close_position_usd =
+            collateral_custody.collected_fees.close_position_usd.wrapping_add(position.accrued_interest_usd);
+        position.accrued_interest_usd = 0;
+        collateral_custody.unlock_funds(position.locked_amount);
+        collateral_custody.remove_position_stats(
+            position.side,
+            position.locked_amount,
+            0,
+            position.locked_amount,
+        );
+        position.locked_amount = 0;
+
+        emit!(PositionClosedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            position_id: position.position_id,
+            owner: position.owner,
+            realized_pnl_encrypted: close_output.ciphertexts[0],
+            final_balance_encrypted: close_output.ciphertexts[1],
+            can_close_encrypted: close_output.ciphertexts[2],
+            nonce: close_output.nonce,
+            client_request_id: position.client_request_id,
+            client_tag: position.client_tag,
+        });
+
+        Ok(())
+    }
+
+    pub fn init_add_collateral_comp_def(ctx: Context<InitAddCollateralCompDef>) -> Result<()> {
+        init_comp_def(
+            ctx.accounts,
+            None,
+            None,
+        )?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_ADD_COLLATERAL,
+            ctx.accounts.payer.key(),
+        )?;
+        Ok(())
+    }
+
+    pub fn add_collateral(
+        ctx: Context<AddCollateral>,
+        computation_offset: u64,
+        _position_id: u64,
+        additional_collateral_encrypted: [u8; 32],
+        client_pubkey: [u8; 32],
+        additional_collateral_nonce: u128,
+    ) -> Result<()> {
+        require_queue_compute_budget()?;
+
+        ctx.accounts.position.record_computation(computation_offset, ComputationKind::AddCollateral)?;
+        require!(
+            ctx.accounts.collateral_custody.trading_schedule.is_open(Clock::get()?.unix_timestamp),
+            ErrorCode::MarketClosed
+        );
+
+        let cumulative_interest = ctx.accounts.collateral_custody
+            .update_cumulative_interest(Clock::get()?.unix_timestamp)?;
+        ctx.accounts.position.accrue_interest(cumulative_interest)?;
+
+        let position = &ctx.accounts.position;
+
+        require!(
+            position.owner == ctx.accounts.owner.key(),
+            ErrorCode::InvalidPositionOwner
+        );
+
+        let args = ArgBuilder::new()
+            .x25519_pubkey(position.owner_enc_pubkey)
+            .plaintext_u128(position.collateral_nonce)
+            .account(position.key(), 8 + 32 + 8 + 1 + 32, 32) // collateral_usd_encrypted
+            .x25519_pubkey(client_pubkey)
+            .plaintext_u128(additional_collateral_nonce)
+            .encrypted_u64(additional_collateral_encrypted)
+            .x25519_pubkey(position.owner_enc_pubkey)
+            .plaintext_u128(position.size_nonce)
+            .account(position.key(), 8 + 32 + 8 + 1, 32) // size_usd_encrypted
+            .build();
+
+        ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
+
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
+        queue_computation(
+            ctx.accounts,
+            computation_offset,
+            args,
+            None,
+            vec![AddCollateralCallback::callback_ix(
+                computation_offset,
+                &ctx.accounts.mxe_account,
+                &[
+                CallbackAccount { pubkey: position.key(), is_writable: true },
+                comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+                CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
+                ]
+            )?],
+            1,
+            0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
+        )?;
+        computation_queued(
+            ComputationKind::AddCollateral,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
+
+        Ok(())
+    }
+
+    #[arcium_callback(encrypted_ix = "add_collateral")]
+    pub fn add_collateral_callback(
+        ctx: Context<AddCollateralCallback>,
+        output: SignedComputationOutputs<AddCollateralOutput>,
+    ) -> Result<()> {
+        let collateral_output = match output.verify_output(
+            &ctx.accounts.cluster_account,
+            &ctx.accounts.computation_account
+        ) {
+            Ok(AddCollateralOutput { field_0 }) => field_0,
+            Err(e) => return Err(callback_error(
+                ComputationKind::AddCollateral,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_ADD_COLLATERAL),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
+        };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::AddCollateral, ctx.accounts.computation_account.key());
+
+        let position = &mut ctx.accounts.position;
+        require_nonce_advances(position.collateral_nonce, collateral_output.nonce)?;
+        
+        position.collateral_usd_encrypted = collateral_output.ciphertexts[0];
+        position.collateral_nonce = collateral_output.nonce;
+        position.update_time = Clock::get()?.unix_timestamp;
+
+        emit!(CollateralAddedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            position_id: position.position_id,
+            owner: position.owner,
+            new_collateral_encrypted: collateral_output.ciphertexts[0],
+            new_leverage_encrypted: collateral_output.ciphertexts[1],
+            nonce: collateral_output.nonce,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_collateral_public(
+        ctx: Context<AddCollateralPublic>,
+        _position_id: u64,
+        params: AddCollateralPublicParams,
+    ) -> Result<()> {
         // Validate inputs
         require!(params.collateral > 0, ErrorCode::InvalidInput);
         
@@ -603,6 +1181,11 @@ pub mod perpetuals {
         let custody = &mut ctx.accounts.custody;
         let collateral_custody = &mut ctx.accounts.collateral_custody;
         let position = &mut ctx.accounts.position;
+        position.require_not_transferring()?;
+        require!(
+            custody.trading_schedule.is_open(Clock::get()?.unix_timestamp),
+            ErrorCode::MarketClosed
+        );
         
         // Verify position ownership
         require!(
@@ -611,15 +1194,17 @@ pub mod perpetuals {
         );
         
         // Get oracle prices
-        let token_price = get_price_from_oracle(
-            &custody.oracle,
-            &ctx.accounts.custody_oracle_account
-        )?;
-        
-        let collateral_price = get_price_from_oracle(
-            &collateral_custody.oracle,
-            &ctx.accounts.collateral_custody_oracle_account
+        let prices = PriceContext::load(
+            custody,
+            &ctx.accounts.custody_oracle_account,
+            collateral_custody,
+            &ctx.accounts.collateral_custody_oracle_account,
+            ctx.accounts.price_cache.as_ref(),
+            PriceUse::ReduceRisk,
+            position.side,
         )?;
+        let token_price = prices.token_price;
+        let collateral_price = prices.collateral_price;
         
         // Compute collateral value in USD
         let collateral_usd = params.collateral
@@ -696,6 +1281,7 @@ pub mod perpetuals {
         position.collateral_usd_encrypted = new_collateral_bytes;
         
         position.update_time = Clock::get()?.unix_timestamp;
+        position.accrue_interest(collateral_custody.update_cumulative_interest(position.update_time)?)?;
         
         // If custody and collateral_custody are the same, sync data
         if position.side == PositionSide::Long {
@@ -706,6 +1292,7 @@ pub mod perpetuals {
         }
         
         emit!(CollateralAddedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position.position_id,
             owner: position.owner,
             new_collateral_encrypted: position.collateral_usd_encrypted,
@@ -728,6 +1315,7 @@ pub mod perpetuals {
         let custody = &mut ctx.accounts.custody;
         let collateral_custody = &mut ctx.accounts.collateral_custody;
         let position = &mut ctx.accounts.position;
+        position.require_not_transferring()?;
 
         // Permissions check – mirror open_position_public style
         require!(
@@ -751,31 +1339,77 @@ pub mod perpetuals {
         collateral_bytes.copy_from_slice(&position.collateral_usd_encrypted[..8]);
         let current_collateral_usd = u64::from_le_bytes(collateral_bytes);
 
-        // Update custody and pool stats in a simplified way:
-        // - Reduce stats.size_usd by current_size_usd
-        // - Reduce stats.collateral_usd by current_collateral_usd
-        // - Decrement open_positions
-        let position_stats = if position.side == PositionSide::Long {
-            &mut custody.long_positions
-        } else {
-            &mut custody.short_positions
-        };
+        let exit_price = get_mark_price(
+            custody,
+            &ctx.accounts.custody_oracle_account,
+            ctx.accounts.price_cache.as_ref(),
+            PriceUse::ReduceRisk,
+            position.side,
+        )?;
+        collateral_custody.check_min_hold(
+            position.side,
+            position.open_time,
+            position.entry_price,
+            exit_price,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let (profit_usd, loss_usd) = realized_pnl(
+            position.side,
+            current_size_usd,
+            current_collateral_usd,
+            position.entry_price,
+            exit_price,
+            collateral_custody.pricing.max_payoff_mult,
+        )?;
 
-        if position_stats.open_positions > 0 {
-            position_stats.open_positions -= 1;
-        }
-        position_stats.size_usd = position_stats
-            .size_usd
-            .saturating_sub(current_size_usd);
-        position_stats.collateral_usd = position_stats
-            .collateral_usd
-            .saturating_sub(current_collateral_usd);
+        // Stats live on the collateral custody, where open_position_public
+        // recorded them
+        collateral_custody.settle_trade_stats(position.side, current_size_usd, profit_usd, loss_usd)?;
+        collateral_custody.rolling_stats.record(Clock::get()?.unix_timestamp, current_size_usd, 0);
+        collateral_custody.remove_position_stats(
+            position.side,
+            current_size_usd,
+            current_collateral_usd,
+            position.locked_amount,
+        );
 
         // Pool AUM – subtract collateral in USD (stored in 1e-8 units, same as size_usd)
         pool.aum_usd = pool
             .aum_usd
             .saturating_sub(current_collateral_usd as u128);
 
+        // Settle borrow interest up to now before the lock that it accrues on
+        // is released. It is charged as the close fee, out of the posted
+        // collateral and never more than it.
+        position.accrue_interest(collateral_custody.update_cumulative_interest(Clock::get()?.unix_timestamp)?)?;
+        let interest_usd = position.accrued_interest_usd.min(current_collateral_usd);
+        position.accrued_interest_usd = 0;
+        // The asset books are in collateral tokens, so the interest moves
+        // between them at the collateral custody's price
+        let interest_amount = usd_to_token(
+            interest_usd,
+            collateral_custody.decimals,
+            &read_oracle_price(
+                &collateral_custody.oracle,
+                &ctx.accounts.collateral_custody_oracle_account,
+            )?,
+        )?
+        .min(collateral_custody.assets.collateral);
+        collateral_custody.assets.collateral = collateral_custody.assets.collateral
+            .checked_sub(interest_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        collateral_custody.assets.owned = collateral_custody.assets.owned
+            .checked_add(interest_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        collateral_custody.assets.protocol_fees = collateral_custody.assets.protocol_fees
+            .checked_add(interest_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        collateral_custody.collected_fees.close_position_usd =
+            collateral_custody.collected_fees.close_position_usd.wrapping_add(interest_usd);
+
+        collateral_custody.unlock_funds(position.locked_amount);
+        position.locked_amount = 0;
+
         // Zero out position size & collateral in the "encrypted" fields
         position.size_usd_encrypted = [0u8; 32];
         position.collateral_usd_encrypted = [0u8; 32];
@@ -788,14 +1422,28 @@ pub mod perpetuals {
         can_close_bytes[0] = 1u8;
 
         emit!(PositionClosedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position_id,
             owner: position.owner,
             realized_pnl_encrypted: zero_bytes,
             final_balance_encrypted: zero_bytes,
             can_close_encrypted: can_close_bytes,
             nonce: 0,
+            client_request_id: position.client_request_id,
+            client_tag: position.client_tag,
         });
 
+        if let Some(trade_log) = ctx.accounts.trade_log.as_mut() {
+            trade_log.record(TradeLogEntry {
+                position_id,
+                action: TradeAction::Close,
+                timestamp: position.update_time,
+                fee_usd: interest_usd,
+                pnl_encrypted: zero_bytes,
+                pnl_nonce: 0,
+            });
+        }
+
         Ok(())
     }
 
@@ -814,6 +1462,7 @@ pub mod perpetuals {
         let custody = &mut ctx.accounts.custody;
         let collateral_custody = &mut ctx.accounts.collateral_custody;
         let position = &mut ctx.accounts.position;
+        position.require_not_transferring()?;
 
         // Verify position ownership
         require!(
@@ -821,6 +1470,12 @@ pub mod perpetuals {
             ErrorCode::InvalidPositionOwner
         );
 
+        // Raising leverage is risk-increasing, so it waits out an oracle outage
+        require!(
+            oracle_is_fresh(&custody.oracle, &ctx.accounts.custody_oracle_account)?,
+            ErrorCode::StaleOraclePrice
+        );
+
         // Get oracle prices
         let collateral_price = get_price_from_oracle(
             &collateral_custody.oracle,
@@ -894,6 +1549,7 @@ pub mod perpetuals {
         new_collateral_bytes[..8].copy_from_slice(&new_collateral_usd.to_le_bytes());
         position.collateral_usd_encrypted = new_collateral_bytes;
         position.update_time = Clock::get()?.unix_timestamp;
+        position.accrue_interest(collateral_custody.update_cumulative_interest(position.update_time)?)?;
 
         // If custody and collateral_custody are the same, keep consistency
         if position.side == PositionSide::Long {
@@ -902,6 +1558,7 @@ pub mod perpetuals {
         }
 
         emit!(CollateralRemovedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position_id,
             owner: position.owner,
             new_collateral_encrypted: position.collateral_usd_encrypted,
@@ -924,6 +1581,7 @@ pub mod perpetuals {
         let custody = &mut ctx.accounts.custody;
         let collateral_custody = &mut ctx.accounts.collateral_custody;
         let position = &mut ctx.accounts.position;
+        position.require_not_transferring()?;
 
         // Basic permission check
         require!(
@@ -940,9 +1598,11 @@ pub mod perpetuals {
         collateral_bytes.copy_from_slice(&position.collateral_usd_encrypted[..8]);
         let current_collateral_usd = u64::from_le_bytes(collateral_bytes);
 
-        // Fetch current price to estimate if position is liquidatable
-        let current_price = get_price_from_oracle(
-            &custody.oracle,
+        // Fetch current price to estimate if position is liquidatable,
+        // reusing this slot's cached price when a keeper already refreshed it
+        let current_price = get_cached_price(
+            ctx.accounts.price_cache.as_ref(),
+            custody,
             &ctx.accounts.custody_oracle_account
         )?;
 
@@ -957,25 +1617,24 @@ pub mod perpetuals {
 
         require!(price_moved_against, ErrorCode::InvalidInput);
 
-        // Update custody stats: remove size and collateral
-        let position_stats = if position.side == PositionSide::Long {
-            &mut custody.long_positions
-        } else {
-            &mut custody.short_positions
-        };
+        // The liquidated trader forfeits all remaining collateral
+        collateral_custody.settle_trade_stats(position.side, current_size_usd, 0, current_collateral_usd)?;
 
-        if position_stats.open_positions > 0 {
-            position_stats.open_positions -= 1;
-        }
-        position_stats.size_usd = position_stats.size_usd
-            .saturating_sub(current_size_usd);
-        position_stats.collateral_usd = position_stats.collateral_usd
-            .saturating_sub(current_collateral_usd);
+        // Update custody stats: remove size and collateral
+        collateral_custody.remove_position_stats(
+            position.side,
+            current_size_usd,
+            current_collateral_usd,
+            position.locked_amount,
+        );
 
         // Pool AUM – deduct all collateral (simplified)
         pool.aum_usd = pool.aum_usd
             .saturating_sub(current_collateral_usd as u128);
 
+        collateral_custody.unlock_funds(position.locked_amount);
+        position.locked_amount = 0;
+
         // Zero out the position's "encrypted" values
         position.size_usd_encrypted = [0u8; 32];
         position.collateral_usd_encrypted = [0u8; 32];
@@ -987,6 +1646,7 @@ pub mod perpetuals {
         is_liquidatable_bytes[0] = 1u8;
 
         emit!(PositionLiquidatedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position_id,
             owner: position.owner,
             liquidator: ctx.accounts.liquidator.key(),
@@ -996,6 +1656,17 @@ pub mod perpetuals {
             nonce: 0,
         });
 
+        if let Some(trade_log) = ctx.accounts.trade_log.as_mut() {
+            trade_log.record(TradeLogEntry {
+                position_id,
+                action: TradeAction::Liquidation,
+                timestamp: position.update_time,
+                fee_usd: 0,
+                pnl_encrypted: zero_bytes,
+                pnl_nonce: 0,
+            });
+        }
+
         Ok(())
     }
 
@@ -1007,6 +1678,12 @@ pub mod perpetuals {
             None,
             None,
         )?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_REMOVE_COLLATERAL,
+            ctx.accounts.payer.key(),
+        )?;
         Ok(())
     }
 
@@ -1018,6 +1695,14 @@ pub mod perpetuals {
         client_pubkey: [u8; 32],
         remove_amount_nonce: u128,
     ) -> Result<()> {
+        require_queue_compute_budget()?;
+
+        ctx.accounts.position.record_computation(computation_offset, ComputationKind::RemoveCollateral)?;
+
+        let cumulative_interest = ctx.accounts.collateral_custody
+            .update_cumulative_interest(Clock::get()?.unix_timestamp)?;
+        ctx.accounts.position.accrue_interest(cumulative_interest)?;
+
         let position = &ctx.accounts.position;
 
         require!(
@@ -1039,6 +1724,12 @@ pub mod perpetuals {
 
         ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
 
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
         queue_computation(
             ctx.accounts,
             computation_offset,
@@ -1049,11 +1740,19 @@ pub mod perpetuals {
                 &ctx.accounts.mxe_account,
                 &[
                 CallbackAccount { pubkey: position.key(), is_writable: true },
+                comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+                CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
                 ]
             )?],
             1,
             0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
         )?;
+        computation_queued(
+            ComputationKind::RemoveCollateral,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
 
         Ok(())
     }
@@ -1068,23 +1767,29 @@ pub mod perpetuals {
             &ctx.accounts.computation_account
         ) {
             Ok(RemoveCollateralOutput { field_0 }) => field_0,
-            Err(e) => {
-                msg!("Error: {}", e);
-                return Err(ErrorCode::AbortedComputation.into())
-            },
+            Err(e) => return Err(callback_error(
+                ComputationKind::RemoveCollateral,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_REMOVE_COLLATERAL),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
         };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::RemoveCollateral, ctx.accounts.computation_account.key());
 
         let position = &mut ctx.accounts.position;
         
         let can_remove = collateral_output.ciphertexts[2][0];
         
         require!(can_remove == 1, ErrorCode::InsufficientCollateral);
+        require_nonce_advances(position.collateral_nonce, collateral_output.nonce)?;
 
         position.collateral_usd_encrypted = collateral_output.ciphertexts[0];
         position.collateral_nonce = collateral_output.nonce;
         position.update_time = Clock::get()?.unix_timestamp;
 
         emit!(CollateralRemovedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position.position_id,
             owner: position.owner,
             new_collateral_encrypted: collateral_output.ciphertexts[0],
@@ -1104,6 +1809,12 @@ pub mod perpetuals {
             None,
             None,
         )?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_LIQUIDATE,
+            ctx.accounts.payer.key(),
+        )?;
         Ok(())
     }
 
@@ -1115,12 +1826,22 @@ pub mod perpetuals {
         client_pubkey: [u8; 32],
         nonce: u128,
     ) -> Result<()> {
+        require_queue_compute_budget()?;
+
+        ctx.accounts.position.record_computation(computation_offset, ComputationKind::Liquidate)?;
+
         let position_key = ctx.accounts.position.key();
         let owner_enc_pubkey = ctx.accounts.position.owner_enc_pubkey;
         let size_nonce = ctx.accounts.position.size_nonce;
         let collateral_nonce = ctx.accounts.position.collateral_nonce;
         let entry_price = ctx.accounts.position.entry_price;
         let side = ctx.accounts.position.side as u8;
+        let max_payoff_mult = ctx.accounts.collateral_custody.pricing.max_payoff_mult;
+        let cu_price_micro = ctx.accounts.perpetuals.liquidation_priority.cu_price_micro(
+            ctx.accounts.position.side,
+            entry_price,
+            current_price,
+        );
 
         let position = &mut ctx.accounts.position;
         position.liquidator = ctx.accounts.liquidator.key();
@@ -1137,10 +1858,18 @@ pub mod perpetuals {
             .plaintext_u64(entry_price)
             .plaintext_u64(current_price)
             .plaintext_u8(side)
+            .plaintext_u64(max_payoff_mult)
             .build();
 
         ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
 
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
+        let [event_authority, event_program] = event_cpi_callback_accounts();
         queue_computation(
             ctx.accounts,
             computation_offset,
@@ -1151,11 +1880,22 @@ pub mod perpetuals {
                 &ctx.accounts.mxe_account,
                 &[
                 CallbackAccount { pubkey: position_key, is_writable: true },
+                CallbackAccount { pubkey: ctx.accounts.collateral_custody.key(), is_writable: true },
+                comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+                CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
+                event_authority,
+                event_program,
                 ]
             )?],
             1,
-            0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
+            cu_price_micro,
         )?;
+        computation_queued(
+            ComputationKind::Liquidate,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
 
         Ok(())
     }
@@ -1170,11 +1910,15 @@ pub mod perpetuals {
             &ctx.accounts.computation_account
         ) {
             Ok(LiquidateOutput { field_0 }) => field_0,
-            Err(e) => {
-                msg!("Error: {}", e);
-                return Err(ErrorCode::AbortedComputation.into())
-            },
+            Err(e) => return Err(callback_error(
+                ComputationKind::Liquidate,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_LIQUIDATE),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
         };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::Liquidate, ctx.accounts.computation_account.key());
 
         let position = &mut ctx.accounts.position;
         
@@ -1182,7 +1926,20 @@ pub mod perpetuals {
         position.collateral_usd_encrypted = [0; 32];
         position.update_time = Clock::get()?.unix_timestamp;
 
-        emit!(PositionLiquidatedEvent {
+        let collateral_custody = &mut ctx.accounts.collateral_custody;
+        collateral_custody.unlock_funds(position.locked_amount);
+        collateral_custody.remove_position_stats(
+            position.side,
+            position.locked_amount,
+            0,
+            position.locked_amount,
+        );
+        position.locked_amount = 0;
+
+        // Three ciphertexts per position; emitted through a self-CPI so they
+        // can't be lost to log truncation
+        emit_cpi!(PositionLiquidatedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
             position_id: position.position_id,
             owner: position.owner,
             liquidator: position.liquidator,
@@ -1195,6 +1952,568 @@ pub mod perpetuals {
         Ok(())
     }
 
+    pub fn init_liquidate_batch_comp_def(ctx: Context<InitLiquidateBatchCompDef>) -> Result<()> {
+        init_comp_def(ctx.accounts, None, None)?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_LIQUIDATE_BATCH,
+            ctx.accounts.payer.key(),
+        )?;
+        Ok(())
+    }
+
+    /// Queues liquidation checks for up to `LIQUIDATE_BATCH_SIZE` positions,
+    /// passed as writable remaining accounts, in a single computation. Short
+    /// batches are padded by repeating the last position.
+    pub fn liquidate_batch(
+        ctx: Context<LiquidateBatch>,
+        computation_offset: u64,
+        current_price: u64,
+        client_pubkey: [u8; 32],
+        nonce: u128,
+    ) -> Result<()> {
+        require_queue_compute_budget()?;
+
+        let position_infos = ctx.remaining_accounts;
+        require!(
+            !position_infos.is_empty() && position_infos.len() <= LIQUIDATE_BATCH_SIZE,
+            ErrorCode::InvalidInput
+        );
+
+        let liquidator = ctx.accounts.liquidator.key();
+        let collateral_custody = ctx.accounts.collateral_custody.key();
+        let mut positions = Vec::with_capacity(position_infos.len());
+        for info in position_infos {
+            require_keys_eq!(*info.owner, crate::ID, ErrorCode::InvalidInput);
+            require!(info.is_writable, ErrorCode::InvalidInput);
+
+            let mut position = Position::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            position.require_not_transferring()?;
+            require_keys_eq!(
+                position.collateral_custody,
+                collateral_custody,
+                ErrorCode::InvalidInput
+            );
+            position.liquidator = liquidator;
+            position.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+            positions.push((info.key(), position));
+        }
+
+        let mut args = ArgBuilder::new()
+            .x25519_pubkey(client_pubkey)
+            .plaintext_u128(nonce);
+        for idx in 0..LIQUIDATE_BATCH_SIZE {
+            let (position_key, position) = &positions[idx.min(positions.len() - 1)];
+            args = args
+                .x25519_pubkey(position.owner_enc_pubkey)
+                .plaintext_u128(position.size_nonce)
+                .account(*position_key, 8 + 32 + 8 + 1, 32) // size_usd_encrypted
+                .x25519_pubkey(position.owner_enc_pubkey)
+                .plaintext_u128(position.collateral_nonce)
+                .account(*position_key, 8 + 32 + 8 + 1 + 32, 32) // collateral_usd_encrypted
+                .plaintext_u64(position.entry_price)
+                .plaintext_u8(position.side as u8);
+        }
+        let args = args
+            .plaintext_u64(current_price)
+            .plaintext_u64(ctx.accounts.collateral_custody.pricing.max_payoff_mult)
+            .build();
+
+        let mut callback_accounts = vec![
+            CallbackAccount { pubkey: collateral_custody, is_writable: true },
+            comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+            CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
+        ];
+        // One critical position is enough to prioritize the whole batch
+        let liquidation_priority = ctx.accounts.perpetuals.liquidation_priority;
+        let cu_price_micro = positions
+            .iter()
+            .map(|(_, position)| {
+                liquidation_priority.cu_price_micro(position.side, position.entry_price, current_price)
+            })
+            .max()
+            .unwrap_or_default();
+
+        callback_accounts.extend(event_cpi_callback_accounts());
+        callback_accounts.extend(
+            positions
+                .iter()
+                .map(|(position_key, _)| CallbackAccount { pubkey: *position_key, is_writable: true }),
+        );
+
+        ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
+
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
+        queue_computation(
+            ctx.accounts,
+            computation_offset,
+            args,
+            None,
+            vec![LiquidateBatchCallback::callback_ix(
+                computation_offset,
+                &ctx.accounts.mxe_account,
+                &callback_accounts,
+            )?],
+            1,
+            cu_price_micro,
+        )?;
+        computation_queued(
+            ComputationKind::LiquidateBatch,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
+
+        Ok(())
+    }
+
+    #[arcium_callback(encrypted_ix = "liquidate_batch")]
+    pub fn liquidate_batch_callback(
+        ctx: Context<LiquidateBatchCallback>,
+        output: SignedComputationOutputs<LiquidateBatchOutput>,
+    ) -> Result<()> {
+        let liquidation_output = match output.verify_output(
+            &ctx.accounts.cluster_account,
+            &ctx.accounts.computation_account
+        ) {
+            Ok(LiquidateBatchOutput { field_0 }) => field_0,
+            Err(e) => return Err(callback_error(
+                ComputationKind::LiquidateBatch,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_LIQUIDATE_BATCH),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
+        };
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::LiquidateBatch, ctx.accounts.computation_account.key());
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        // Same handling as liquidate_callback, per position; each
+        // LiquidateOutput spans three ciphertexts.
+        require!(
+            ctx.remaining_accounts.len() * 3 <= liquidation_output.ciphertexts.len(),
+            ErrorCode::MalformedComputationOutput
+        );
+        for (idx, info) in ctx.remaining_accounts.iter().enumerate() {
+            let mut position = Position::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            position.size_usd_encrypted = [0; 32];
+            position.collateral_usd_encrypted = [0; 32];
+            position.update_time = current_time;
+            let collateral_custody = &mut ctx.accounts.collateral_custody;
+            collateral_custody.unlock_funds(position.locked_amount);
+            collateral_custody.remove_position_stats(
+                position.side,
+                position.locked_amount,
+                0,
+                position.locked_amount,
+            );
+            position.locked_amount = 0;
+            position.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+            emit_cpi!(PositionLiquidatedEvent {
+                schema_version: EVENT_SCHEMA_VERSION,
+                position_id: position.position_id,
+                owner: position.owner,
+                liquidator: position.liquidator,
+                is_liquidatable_encrypted: liquidation_output.ciphertexts[idx * 3],
+                remaining_collateral_encrypted: liquidation_output.ciphertexts[idx * 3 + 1],
+                penalty_encrypted: liquidation_output.ciphertexts[idx * 3 + 2],
+                nonce: liquidation_output.nonce,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn init_transfer_position_comp_def(
+        ctx: Context<InitTransferPositionCompDef>,
+    ) -> Result<()> {
+        init_comp_def(
+            ctx.accounts,
+            None,
+            None,
+        )?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_TRANSFER_POSITION,
+            ctx.accounts.payer.key(),
+        )?;
+        Ok(())
+    }
+
+    /// Move a position to another wallet.
+    ///
+    /// Position PDAs are derived from the owner key, so the position is
+    /// re-created under `new_owner`'s seeds and the encrypted size/collateral
+    /// are re-keyed to `new_owner_enc_pubkey` by MPC. Both accounts are
+    /// frozen until the callback closes the original to its owner once the
+    /// re-encrypted values land, or until `cancel_position_transfer` unfreezes
+    /// the original if it never does.
+    pub fn transfer_position(
+        ctx: Context<TransferPosition>,
+        computation_offset: u64,
+        _position_id: u64,
+        new_position_id: u64,
+        new_owner_enc_pubkey: [u8; 32],
+        new_size_nonce: u128,
+        new_collateral_nonce: u128,
+    ) -> Result<()> {
+        require_queue_compute_budget()?;
+
+        ctx.accounts.position.record_computation(computation_offset, ComputationKind::TransferPosition)?;
+        ctx.accounts.position.transfer_pending = true;
+
+        let position = &ctx.accounts.position;
+
+        require!(
+            position.owner == ctx.accounts.owner.key(),
+            ErrorCode::InvalidPositionOwner
+        );
+        require!(
+            ctx.accounts.new_owner.key() != ctx.accounts.owner.key(),
+            ErrorCode::InvalidInput
+        );
+
+        let position_key = position.key();
+        let new_position_key = ctx.accounts.new_position.key();
+
+        let args = ArgBuilder::new()
+            .x25519_pubkey(new_owner_enc_pubkey)
+            .plaintext_u128(new_size_nonce)
+            .x25519_pubkey(new_owner_enc_pubkey)
+            .plaintext_u128(new_collateral_nonce)
+            .x25519_pubkey(position.owner_enc_pubkey)
+            .plaintext_u128(position.size_nonce)
+            .account(position_key, 8 + 32 + 8 + 1, 32) // size_usd_encrypted
+            .x25519_pubkey(position.owner_enc_pubkey)
+            .plaintext_u128(position.collateral_nonce)
+            .account(position_key, 8 + 32 + 8 + 1 + 32, 32) // collateral_usd_encrypted
+            .build();
+
+        let side = position.side;
+        let entry_price = position.entry_price;
+        let open_time = position.open_time;
+
+        let new_position = &mut ctx.accounts.new_position;
+        new_position.owner = ctx.accounts.new_owner.key();
+        new_position.position_id = new_position_id;
+        new_position.side = side;
+        new_position.size_usd_encrypted = [0; 32];
+        new_position.collateral_usd_encrypted = [0; 32];
+        new_position.entry_price = entry_price;
+        new_position.open_time = open_time;
+        new_position.update_time = Clock::get()?.unix_timestamp;
+        new_position.owner_enc_pubkey = new_owner_enc_pubkey;
+        new_position.size_nonce = 0;
+        new_position.collateral_nonce = 0;
+        new_position.liquidator = Pubkey::default();
+        new_position.bump = ctx.bumps.new_position;
+        new_position.version = POSITION_VERSION;
+        new_position.collateral_custody = position.collateral_custody;
+        new_position.locked_amount = position.locked_amount;
+        new_position.client_request_id = 0;
+        new_position.client_tag = [0; 32];
+        new_position.metadata_len = 0;
+        new_position.pending_expiry_slot = Clock::get()?
+            .slot
+            .checked_add(PENDING_POSITION_TTL_SLOTS)
+            .ok_or(ErrorCode::MathOverflow)?;
+        new_position.pending_payer = ctx.accounts.payer.key();
+        new_position.close_delegate = Pubkey::default();
+        new_position.cumulative_interest_snapshot = position.cumulative_interest_snapshot;
+        new_position.accrued_interest_usd = position.accrued_interest_usd;
+        new_position.receipt_key_epoch = 0;
+        new_position.origin_program = Pubkey::default();
+        // Same offset as the source, which is how a cancel pairs the two
+        new_position.last_computation_offset = computation_offset;
+        new_position.last_computation_kind = 0;
+        new_position.transfer_pending = true;
+
+        ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
+
+        ctx.accounts.payer_quota.reserve(
+            ctx.accounts.payer.key(),
+            ctx.accounts.perpetuals.max_in_flight_computations,
+            ctx.bumps.payer_quota,
+        )?;
+
+        queue_computation(
+            ctx.accounts,
+            computation_offset,
+            args,
+            None,
+            vec![TransferPositionCallback::callback_ix(
+                computation_offset,
+                &ctx.accounts.mxe_account,
+                &[
+                CallbackAccount { pubkey: position_key, is_writable: true },
+                CallbackAccount { pubkey: new_position_key, is_writable: true },
+                comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+                CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
+                CallbackAccount { pubkey: position.owner, is_writable: true },
+                ]
+            )?],
+            1,
+            0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
+        )?;
+        computation_queued(
+            ComputationKind::TransferPosition,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.payer.key(),
+        );
+
+        Ok(())
+    }
+
+    #[arcium_callback(encrypted_ix = "transfer_position")]
+    pub fn transfer_position_callback(
+        ctx: Context<TransferPositionCallback>,
+        output: SignedComputationOutputs<TransferPositionOutput>,
+    ) -> Result<()> {
+        let TransferPositionOutput {
+                field_0: TransferPositionOutputStruct0 {
+                    field_0: size,
+                    field_1: collateral,
+                },
+        } = match output.verify_output(
+            &ctx.accounts.cluster_account,
+            &ctx.accounts.computation_account
+        ) {
+            Ok(result) => result,
+            Err(e) => return Err(callback_error(
+                ComputationKind::TransferPosition,
+                resolve_comp_def_offset(&ctx.accounts.comp_def_registry, COMP_DEF_OFFSET_TRANSFER_POSITION),
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
+        };
+        // Once expired the transfer may be cancelled, so a late callback
+        // must not complete it either
+        require!(
+            Clock::get()?.slot <= ctx.accounts.new_position.pending_expiry_slot,
+            ErrorCode::PendingPositionExpired
+        );
+        ctx.accounts.payer_quota.release();
+        computation_completed(ComputationKind::TransferPosition, ctx.accounts.computation_account.key());
+
+        let new_position = &mut ctx.accounts.new_position;
+        new_position.size_usd_encrypted = size.ciphertexts[0];
+        new_position.size_nonce = size.nonce;
+        new_position.collateral_usd_encrypted = collateral.ciphertexts[0];
+        new_position.collateral_nonce = collateral.nonce;
+        new_position.transfer_pending = false;
+        new_position.pending_expiry_slot = 0;
+        new_position.pending_payer = Pubkey::default();
+
+        // The lock moved to the new position in transfer_position; the
+        // original is closed to its owner on exit
+        let position = &ctx.accounts.position;
+
+        emit!(PositionTransferredEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            position_id: position.position_id,
+            new_position_id: new_position.position_id,
+            previous_owner: position.owner,
+            new_owner: new_position.owner,
+            size_encrypted: new_position.size_usd_encrypted,
+            size_nonce: new_position.size_nonce,
+            collateral_encrypted: new_position.collateral_usd_encrypted,
+            collateral_nonce: new_position.collateral_nonce,
+        });
+
+        Ok(())
+    }
+
+    /// Permissionless cleanup for an encrypted open whose callback never
+    /// arrived. Nothing was locked yet, so closing only returns the rent.
+    pub fn expire_pending_position(
+        ctx: Context<ExpirePendingPosition>,
+        _position_id: u64,
+    ) -> Result<()> {
+        let position = &ctx.accounts.position;
+        require!(
+            Clock::get()?.slot > position.pending_expiry_slot,
+            ErrorCode::PendingPositionNotExpired
+        );
+        ctx.accounts.payer_quota.release();
+
+        emit!(PendingPositionExpiredEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            position_id: position.position_id,
+            owner: position.owner,
+            expiry_slot: position.pending_expiry_slot,
+        });
+
+        Ok(())
+    }
+
+    /// Permissionless cleanup for a `transfer_position` whose callback never
+    /// arrived. The original is unfrozen and keeps its lock; the destination
+    /// never received any values, so closing it only returns the rent.
+    pub fn cancel_position_transfer(ctx: Context<CancelPositionTransfer>) -> Result<()> {
+        let new_position = &ctx.accounts.new_position;
+        require!(
+            Clock::get()?.slot > new_position.pending_expiry_slot,
+            ErrorCode::PendingPositionNotExpired
+        );
+        ctx.accounts.payer_quota.release();
+        ctx.accounts.position.transfer_pending = false;
+
+        emit!(PositionTransferCancelledEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            position_id: ctx.accounts.position.position_id,
+            new_position_id: new_position.position_id,
+            owner: ctx.accounts.position.owner,
+            new_owner: new_position.owner,
+            expiry_slot: new_position.pending_expiry_slot,
+        });
+
+        Ok(())
+    }
+
+    /// Lets `delegate` close, but not otherwise manage, one of the owner's
+    /// positions. `Pubkey::default()` revokes it.
+    pub fn set_close_delegate(
+        ctx: Context<SetCloseDelegate>,
+        _position_id: u64,
+        delegate: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.position.close_delegate = delegate;
+        Ok(())
+    }
+
+    /// Attaches an opaque encrypted blob of up to `POSITION_METADATA_MAX_LEN`
+    /// bytes to one of the owner's positions, or clears it when empty. The
+    /// program never reads it; can be sent in the same transaction as the open.
+    pub fn set_position_metadata(
+        ctx: Context<SetPositionMetadata>,
+        _position_id: u64,
+        metadata: Vec<u8>,
+        nonce: u128,
+    ) -> Result<()> {
+        require!(
+            metadata.len() <= POSITION_METADATA_MAX_LEN,
+            ErrorCode::InvalidInput
+        );
+
+        let position = &mut ctx.accounts.position;
+        position.metadata = [0; POSITION_METADATA_MAX_LEN];
+        position.metadata[..metadata.len()].copy_from_slice(&metadata);
+        position.metadata_len = metadata.len() as u8;
+        position.metadata_nonce = nonce;
+        Ok(())
+    }
+
+    /// Registers or rotates the x25519 key open receipts are encrypted to.
+    /// Each call starts a new epoch, reported in `PositionOpenedEvent`.
+    pub fn set_encryption_key(
+        ctx: Context<SetEncryptionKey>,
+        params: SetEncryptionKeyParams,
+    ) -> Result<()> {
+        require!(params.enc_pubkey != [0; 32], ErrorCode::InvalidInput);
+
+        let encryption_key = &mut ctx.accounts.encryption_key;
+        encryption_key.owner = ctx.accounts.owner.key();
+        encryption_key.enc_pubkey = params.enc_pubkey;
+        encryption_key.key_epoch = encryption_key
+            .key_epoch
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        encryption_key.bump = ctx.bumps.encryption_key;
+        Ok(())
+    }
+
+    /// Grows a position created under an older layout to the current size,
+    /// topping up rent from the owner, and bumps its version.
+    pub fn realloc_position(ctx: Context<ReallocPosition>, _position_id: u64) -> Result<()> {
+        let position_info = ctx.accounts.position.to_account_info();
+        let new_len = 8 + Position::INIT_SPACE;
+
+        if position_info.data_len() < new_len {
+            let rent_due = Rent::get()?
+                .minimum_balance(new_len)
+                .saturating_sub(position_info.lamports());
+            if rent_due > 0 {
+                anchor_lang::system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        anchor_lang::system_program::Transfer {
+                            from: ctx.accounts.owner.to_account_info(),
+                            to: position_info.clone(),
+                        },
+                    ),
+                    rent_due,
+                )?;
+            }
+            position_info.resize(new_len)?;
+        }
+
+        let mut position = Position::try_deserialize(&mut &position_info.try_borrow_data()?[..])?;
+        require!(
+            position.owner == ctx.accounts.owner.key(),
+            ErrorCode::InvalidPositionOwner
+        );
+
+        if position.version < POSITION_VERSION {
+            position.version = POSITION_VERSION;
+            position.try_serialize(&mut &mut position_info.try_borrow_mut_data()?[..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Grows a custody allocated before its fields outgrew the slack in
+    /// `Custody::SPACE`, topping up rent from the payer. The added fields
+    /// read as zero.
+    pub fn realloc_custody(ctx: Context<ReallocCustody>) -> Result<()> {
+        let custody_info = ctx.accounts.custody.to_account_info();
+        require!(
+            custody_info.try_borrow_data()?.starts_with(Custody::DISCRIMINATOR),
+            ErrorCode::InvalidInput
+        );
+
+        if custody_info.data_len() < Custody::SPACE {
+            let rent_due = Rent::get()?
+                .minimum_balance(Custody::SPACE)
+                .saturating_sub(custody_info.lamports());
+            if rent_due > 0 {
+                anchor_lang::system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        anchor_lang::system_program::Transfer {
+                            from: ctx.accounts.payer.to_account_info(),
+                            to: custody_info.clone(),
+                        },
+                    ),
+                    rent_due,
+                )?;
+            }
+            custody_info.resize(Custody::SPACE)?;
+        }
+
+        Custody::try_deserialize(&mut &custody_info.try_borrow_data()?[..])?;
+        Ok(())
+    }
+
+    pub fn init_trade_log(ctx: Context<InitTradeLog>) -> Result<()> {
+        let trade_log = &mut ctx.accounts.trade_log;
+        trade_log.owner = ctx.accounts.owner.key();
+        trade_log.head = 0;
+        trade_log.len = 0;
+        trade_log.bump = ctx.bumps.trade_log;
+        Ok(())
+    }
+
     pub fn get_entry_price_and_fee(
         ctx: Context<GetEntryPriceAndFee>,
         params: GetEntryPriceAndFeeParams,
@@ -1357,6 +2676,15 @@ pub mod perpetuals {
         })
     }
 
+    /// Borrow interest the position would be charged if it closed now.
+    pub fn get_pending_interest(ctx: Context<GetPendingInterest>) -> Result<PendingInterest> {
+        let cumulative_interest = ctx.accounts.collateral_custody
+            .get_cumulative_interest(Clock::get()?.unix_timestamp)?;
+        Ok(PendingInterest {
+            interest_usd: ctx.accounts.position.pending_interest(cumulative_interest)?,
+        })
+    }
+
     pub fn get_pnl(
         ctx: Context<GetPnl>,
         _params: GetPnlParams,
@@ -1434,7 +2762,7 @@ pub mod perpetuals {
     pub fn get_liquidation_price(
         ctx: Context<GetLiquidationPrice>,
         _params: GetLiquidationPriceParams,
-    ) -> Result<u64> {
+    ) -> Result<LiquidationPrice> {
         let position = &ctx.accounts.position;
         
         let entry_price = position.entry_price;
@@ -1473,13 +2801,13 @@ pub mod perpetuals {
                 .ok_or(ErrorCode::MathOverflow)?
         };
         
-        Ok(liquidation_price)
+        Ok(LiquidationPrice { liquidation_price })
     }
 
     pub fn get_liquidation_state(
         ctx: Context<GetLiquidationState>,
         _params: GetLiquidationStateParams,
-    ) -> Result<u8> {
+    ) -> Result<LiquidationState> {
         let position = &ctx.accounts.position;
         let custody = &ctx.accounts.custody;
         
@@ -1530,21 +2858,23 @@ pub mod perpetuals {
             current_price >= liquidation_price
         };
         
-        Ok(if is_liquidatable { 1 } else { 0 })
+        Ok(LiquidationState {
+            current_price,
+            liquidation_price,
+            is_liquidatable,
+        })
     }
 
     pub fn get_oracle_price(
         ctx: Context<GetOraclePrice>,
         _params: GetOraclePriceParams,
-    ) -> Result<u64> {
+    ) -> Result<OraclePrice> {
         let custody = &ctx.accounts.custody;
         
-        let price = get_price_from_oracle(
+        read_oracle_price(
             &custody.oracle,
             &ctx.accounts.custody_oracle_account
-        )?;
-        
-        Ok(price)
+        )
     }
 
     pub fn get_swap_amount_and_fees(
@@ -1567,11 +2897,19 @@ pub mod perpetuals {
             .checked_sub(fee_in)
             .ok_or(ErrorCode::MathOverflow)?;
         
-        let amount_out = amount_after_fee
-            .checked_mul(98)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(100)
-            .ok_or(ErrorCode::MathOverflow)?;
+        let amount_out = convert_token_amount(
+            amount_after_fee,
+            custody_in.decimals,
+            &read_oracle_price(
+                &custody_in.oracle,
+                &ctx.accounts.receiving_custody_oracle_account
+            )?,
+            custody_out.decimals,
+            &read_oracle_price(
+                &custody_out.oracle,
+                &ctx.accounts.dispensing_custody_oracle_account
+            )?,
+        )?;
         
         let fee_out = amount_out
             .checked_mul(fee_out_rate)
@@ -1607,8 +2945,15 @@ pub mod perpetuals {
             .checked_sub(fee)
             .ok_or(ErrorCode::MathOverflow)?;
         
+        let usd_after_fee = token_to_usd(
+            amount_after_fee,
+            custody.decimals,
+            &read_oracle_price(&custody.oracle, &ctx.accounts.custody_oracle_account)?,
+        )?;
+        let lp_price = ctx.accounts.pool.get_lp_token_price(ctx.accounts.lp_token_mint.supply)?;
+        
         Ok(AmountAndFee {
-            amount: amount_after_fee,
+            amount: usd_to_lp(usd_after_fee, lp_price)?,
             fee,
         })
     }
@@ -1626,10 +2971,17 @@ pub mod perpetuals {
             .checked_div(10000)
             .ok_or(ErrorCode::MathOverflow)?;
         
-        let final_amount = params.lp_amount_in
+        let lp_after_fee = params.lp_amount_in
             .checked_sub(fee)
             .ok_or(ErrorCode::MathOverflow)?;
         
+        let lp_price = ctx.accounts.pool.get_lp_token_price(ctx.accounts.lp_token_mint.supply)?;
+        let final_amount = usd_to_token(
+            lp_to_usd(lp_after_fee, lp_price)?,
+            custody.decimals,
+            &read_oracle_price(&custody.oracle, &ctx.accounts.custody_oracle_account)?,
+        )?;
+        
         Ok(AmountAndFee {
             amount: final_amount,
             fee,
@@ -1639,26 +2991,188 @@ pub mod perpetuals {
     pub fn get_assets_under_management(
         ctx: Context<GetAssetsUnderManagement>,
         _params: GetAssetsUnderManagementParams,
-    ) -> Result<u128> {
-        Ok(ctx.accounts.pool.aum_usd)
+    ) -> Result<AssetsUnderManagement> {
+        Ok(AssetsUnderManagement {
+            aum_usd: ctx.accounts.pool.aum_usd,
+        })
     }
 
     pub fn get_lp_token_price(
-        _ctx: Context<GetLpTokenPrice>,
+        ctx: Context<GetLpTokenPrice>,
         _params: GetLpTokenPriceParams,
-    ) -> Result<u64> {
-        Ok(1_000000)
+    ) -> Result<LpTokenPrice> {
+        Ok(LpTokenPrice {
+            price: ctx.accounts.pool.get_lp_token_price(ctx.accounts.lp_token_mint.supply)?,
+        })
     }
 
-    pub fn swap(
-        ctx: Context<Swap>,
-        params: SwapParams,
-    ) -> Result<()> {
-        require!(params.amount_in > 0, ErrorCode::InvalidInput);
+    /// Computation definitions initialized by this deployment, with the
+    /// payer and time of each initialization.
+    pub fn get_initialized_comp_defs(
+        ctx: Context<GetInitializedCompDefs>,
+    ) -> Result<Vec<CompDefInit>> {
+        Ok(ctx.accounts.comp_def_init_log.initialized().to_vec())
+    }
+
+    pub fn get_priority_fees(ctx: Context<GetPriorityFees>) -> Result<PriorityFees> {
+        Ok(ctx.accounts.perpetuals.priority_fees)
+    }
+
+    pub fn get_version(_ctx: Context<GetVersion>) -> Result<ProgramVersion> {
+        Ok(ProgramVersion::current())
+    }
+
+    /// Permissionless crank that publishes the pool's LP token price. The
+    /// AUM is revalued from custody holdings at current oracle prices rather
+    /// than taken from `Pool::aum_usd`, which is only marked at deposit time.
+    /// Remaining accounts are the pool's custodies followed by their oracles.
+    pub fn refresh_lp_price_oracle(ctx: Context<RefreshLpPriceOracle>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let lp_supply = ctx.accounts.lp_token_mint.supply;
+        let aum_usd = pool_aum_at_oracle_prices(pool, ctx.remaining_accounts)?;
+
+        let oracle = &mut ctx.accounts.lp_price_oracle;
+        oracle.pool = pool.key();
+        oracle.price = Pool::lp_token_price(aum_usd, lp_supply)?;
+        oracle.lp_supply = lp_supply;
+        oracle.aum_usd = aum_usd;
+        oracle.publish_time = Clock::get()?.unix_timestamp;
+        oracle.bump = ctx.bumps.lp_price_oracle;
+
+        Ok(())
+    }
+
+    /// Compares the custody's books against its token account. Owned assets
+    /// (which include unwithdrawn protocol fees) plus trader collateral must
+    /// be backed by the vault balance. Reporting
+    /// is permissionless; freezing deposits and withdrawals (which also stops
+    /// swaps through the custody) and new positions on a shortfall takes the
+    /// multisig.
+    pub fn reconcile_custody(
+        ctx: Context<ReconcileCustody>,
+        params: ReconcileCustodyParams,
+    ) -> Result<u8> {
+        let expected = ctx.accounts.custody.assets.owned
+            .checked_add(ctx.accounts.custody.assets.collateral)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let actual = ctx.accounts.custody_token_account.amount;
+
+        if actual == expected {
+            return Ok(0);
+        }
+
+        let halted = params.halt_on_shortfall && actual < expected;
+        if halted {
+            let admin = ctx
+                .accounts
+                .admin
+                .as_ref()
+                .ok_or(ErrorCode::MultisigAccountNotAuthorized)?
+                .to_account_info();
+            let instruction_accounts = Multisig::get_account_infos(&ctx);
+            let instruction_data = Multisig::get_instruction_data(&params)?;
+            let multisig = ctx
+                .accounts
+                .multisig
+                .as_mut()
+                .ok_or(ErrorCode::MultisigAccountNotAuthorized)?;
+
+            let signatures_left = multisig.sign_multisig(
+                &admin,
+                &instruction_accounts[1..],
+                &instruction_data,
+            )?;
+            if signatures_left > 0 {
+                msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+                return Ok(signatures_left);
+            }
+        }
+
+        let custody = &mut ctx.accounts.custody;
+        if halted {
+            custody.deposits_frozen = true;
+            custody.withdrawals_frozen = true;
+            custody.permissions.allow_open_position = false;
+        }
+
+        msg!("Custody books {} but vault holds {}", expected, actual);
+        emit!(CustodyBalanceMismatchEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            custody: custody.key(),
+            expected,
+            actual,
+            halted,
+        });
+
+        Ok(0)
+    }
+
+    /// Caches the custody's oracle price for the current slot so several
+    /// liquidations cranked in the same slot parse the oracle only once. The
+    /// cache also serves as the last good price for `get_mark_price`.
+    pub fn refresh_price_cache(ctx: Context<RefreshPriceCache>) -> Result<()> {
+        let custody = &ctx.accounts.custody;
+        require!(
+            oracle_is_fresh(&custody.oracle, &ctx.accounts.custody_oracle_account)?,
+            ErrorCode::StaleOraclePrice
+        );
+        let price = get_price_from_oracle(
+            &custody.oracle,
+            &ctx.accounts.custody_oracle_account
+        )?;
+
+        let cache = &mut ctx.accounts.price_cache;
+        cache.custody = custody.key();
+        cache.price = price;
+        cache.slot = Clock::get()?.slot;
+        cache.bump = ctx.bumps.price_cache;
+
+        Ok(())
+    }
+
+    pub fn get_custody_stats(
+        ctx: Context<GetCustodyStats>,
+        _params: GetCustodyStatsParams,
+    ) -> Result<CustodyStats> {
+        let custody = &ctx.accounts.custody;
+
+        let utilization_bps = if custody.assets.owned == 0 {
+            0
+        } else {
+            custody.assets.locked
+                .checked_mul(10000)
+                .ok_or(ErrorCode::MathOverflow)?
+                .checked_div(custody.assets.owned)
+                .ok_or(ErrorCode::MathOverflow)?
+        };
+        let (volume_24h_usd, fees_24h_usd) =
+            custody.rolling_stats.totals(Clock::get()?.unix_timestamp);
+
+        Ok(CustodyStats {
+            utilization_bps,
+            borrow_rate: custody.borrow_rate_state.current_rate,
+            locked: custody.assets.locked,
+            owned: custody.assets.owned,
+            oi_long_usd: custody.trade_stats.oi_long_usd,
+            oi_short_usd: custody.trade_stats.oi_short_usd,
+            volume_24h_usd,
+            fees_24h_usd,
+        })
+    }
+
+    pub fn swap(
+        ctx: Context<Swap>,
+        params: SwapParams,
+    ) -> Result<()> {
+        require!(params.amount_in > 0, ErrorCode::InvalidInput);
         require!(params.min_amount_out > 0, ErrorCode::InvalidInput);
         
         let receiving_custody = &mut ctx.accounts.receiving_custody;
         let dispensing_custody = &mut ctx.accounts.dispensing_custody;
+        require!(
+            !receiving_custody.deposits_frozen && !dispensing_custody.withdrawals_frozen,
+            ErrorCode::CustodyFrozen
+        );
         
         let fee_in_rate = receiving_custody.fees.swap_in;
         let fee_in = params.amount_in
@@ -1671,11 +3185,21 @@ pub mod perpetuals {
             .checked_sub(fee_in)
             .ok_or(ErrorCode::MathOverflow)?;
         
-        let amount_out = amount_after_fee_in
-            .checked_mul(98)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(100)
-            .ok_or(ErrorCode::MathOverflow)?;
+        let receiving_price = read_oracle_price(
+            &receiving_custody.oracle,
+            &ctx.accounts.receiving_custody_oracle_account
+        )?;
+        let dispensing_price = read_oracle_price(
+            &dispensing_custody.oracle,
+            &ctx.accounts.dispensing_custody_oracle_account
+        )?;
+        let amount_out = convert_token_amount(
+            amount_after_fee_in,
+            receiving_custody.decimals,
+            &receiving_price,
+            dispensing_custody.decimals,
+            &dispensing_price,
+        )?;
         
         let fee_out_rate = dispensing_custody.fees.swap_out;
         let fee_out = amount_out
@@ -1690,26 +3214,115 @@ pub mod perpetuals {
         
         require!(final_amount_out >= params.min_amount_out, ErrorCode::InvalidInput);
         
+        let perpetuals = &ctx.accounts.perpetuals;
+        perpetuals.transfer_tokens_from_user(
+            ctx.accounts.funding_account.to_account_info(),
+            ctx.accounts.receiving_custody_token_account.to_account_info(),
+            ctx.accounts.owner.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            params.amount_in,
+        )?;
+        perpetuals.transfer_tokens(
+            ctx.accounts.dispensing_custody_token_account.to_account_info(),
+            ctx.accounts.receiving_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            final_amount_out,
+        )?;
+        
+        // Stats are kept in USD at the prices the swap executed at
+        let amount_in_usd = token_to_usd(params.amount_in, receiving_custody.decimals, &receiving_price)?;
+        let fee_in_usd = token_to_usd(fee_in, receiving_custody.decimals, &receiving_price)?;
+        let fee_out_usd = token_to_usd(fee_out, dispensing_custody.decimals, &dispensing_price)?;
+        
         receiving_custody.assets.owned = receiving_custody.assets.owned
             .checked_add(params.amount_in)
             .ok_or(ErrorCode::MathOverflow)?;
         receiving_custody.collected_fees.swap_usd = receiving_custody.collected_fees.swap_usd
-            .checked_add(fee_in)
+            .checked_add(fee_in_usd)
             .ok_or(ErrorCode::MathOverflow)?;
         receiving_custody.volume_stats.swap_usd = receiving_custody.volume_stats.swap_usd
-            .checked_add(params.amount_in)
+            .checked_add(amount_in_usd)
             .ok_or(ErrorCode::MathOverflow)?;
         
         dispensing_custody.assets.owned = dispensing_custody.assets.owned
             .checked_sub(final_amount_out)
             .ok_or(ErrorCode::MathOverflow)?;
         dispensing_custody.collected_fees.swap_usd = dispensing_custody.collected_fees.swap_usd
-            .checked_add(fee_out)
+            .checked_add(fee_out_usd)
             .ok_or(ErrorCode::MathOverflow)?;
         
+        let now = Clock::get()?.unix_timestamp;
+        receiving_custody.rolling_stats.record(now, amount_in_usd, fee_in_usd);
+        dispensing_custody.rolling_stats.record(now, 0, fee_out_usd);
+        
         Ok(())
     }
 
+    /// Deposits the first custody balance of an empty pool. The matching LP
+    /// tokens go to a treasury account held by the transfer authority, so the
+    /// seed can never be withdrawn at a price the admin picked.
+    pub fn seed_pool(
+        ctx: Context<SeedPool>,
+        params: SeedPoolParams,
+    ) -> Result<u8> {
+        require!(params.amount > 0, ErrorCode::InvalidInput);
+        require!(
+            ctx.accounts.lp_token_mint.supply == 0 && ctx.accounts.pool.genesis_time == 0,
+            ErrorCode::PoolAlreadySeeded
+        );
+
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        // Valued like add_liquidity, at the initial LP price
+        let token_price = read_oracle_price(&ctx.accounts.custody.oracle, &ctx.accounts.custody_oracle_account)?;
+        let usd_amount = token_to_usd(params.amount, ctx.accounts.custody.decimals, &token_price)?;
+        let lp_amount = usd_to_lp(usd_amount, ctx.accounts.pool.get_lp_token_price(0)?)?;
+        require!(lp_amount > 0, ErrorCode::InvalidInput);
+
+        let perpetuals = ctx.accounts.perpetuals.as_ref();
+        perpetuals.transfer_tokens_from_user(
+            ctx.accounts.funding_account.to_account_info(),
+            ctx.accounts.custody_token_account.to_account_info(),
+            ctx.accounts.admin.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            params.amount,
+        )?;
+        perpetuals.mint_tokens(
+            ctx.accounts.lp_token_mint.to_account_info(),
+            ctx.accounts.lp_treasury_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            lp_amount,
+        )?;
+
+        let custody = &mut ctx.accounts.custody;
+        custody.assets.owned = custody.assets.owned
+            .checked_add(params.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let pool = &mut ctx.accounts.pool;
+        pool.aum_usd = pool.aum_usd
+            .checked_add(usd_amount as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pool.genesis_aum_usd = pool.aum_usd;
+        pool.genesis_time = Clock::get()?.unix_timestamp;
+
+        Ok(0)
+    }
+
     pub fn add_liquidity(
         ctx: Context<AddLiquidity>,
         params: AddLiquidityParams,
@@ -1721,6 +3334,7 @@ pub mod perpetuals {
         
         let pool = &mut ctx.accounts.pool;
         let custody = &mut ctx.accounts.custody;
+        require!(!custody.deposits_frozen, ErrorCode::CustodyFrozen);
         
         let fee_rate = custody.fees.add_liquidity;
         let fee = params.amount_in
@@ -1733,7 +3347,10 @@ pub mod perpetuals {
             .checked_sub(fee)
             .ok_or(ErrorCode::MathOverflow)?;
         
-        let lp_amount = amount_after_fee;
+        let token_price = read_oracle_price(&custody.oracle, &ctx.accounts.custody_oracle_account)?;
+        let usd_after_fee = token_to_usd(amount_after_fee, custody.decimals, &token_price)?;
+        let lp_price = pool.get_lp_token_price(ctx.accounts.lp_token_mint.supply)?;
+        let lp_amount = usd_to_lp(usd_after_fee, lp_price)?;
         
         require!(lp_amount >= params.min_lp_amount_out, ErrorCode::InvalidInput);
         
@@ -1768,9 +3385,20 @@ pub mod perpetuals {
             .ok_or(ErrorCode::MathOverflow)?;
         
         pool.aum_usd = pool.aum_usd
-            .checked_add(amount_after_fee as u128)
+            .checked_add(usd_after_fee as u128)
             .ok_or(ErrorCode::MathOverflow)?;
         
+        // Cost basis includes the fee the LP paid
+        let receipt = &mut ctx.accounts.deposit_receipt;
+        receipt.owner = ctx.accounts.owner.key();
+        receipt.pool = pool.key();
+        receipt.bump = ctx.bumps.deposit_receipt;
+        receipt.record_deposit(
+            token_to_usd(params.amount_in, custody.decimals, &token_price)?,
+            lp_amount,
+            Clock::get()?.unix_timestamp,
+        )?;
+        
         Ok(())
     }
 
@@ -1784,6 +3412,7 @@ pub mod perpetuals {
         let perpetuals = ctx.accounts.perpetuals.as_mut();
         let pool = &mut ctx.accounts.pool;
         let custody = &mut ctx.accounts.custody;
+        require!(!custody.withdrawals_frozen, ErrorCode::CustodyFrozen);
         
         let fee_rate = custody.fees.remove_liquidity;
         let fee = params.lp_amount_in
@@ -1792,11 +3421,40 @@ pub mod perpetuals {
             .checked_div(10000)
             .ok_or(ErrorCode::MathOverflow)?;
         
-        let amount_out = params.lp_amount_in
+        let lp_after_fee = params.lp_amount_in
             .checked_sub(fee)
             .ok_or(ErrorCode::MathOverflow)?;
         
-        require!(amount_out >= params.min_amount_out, ErrorCode::InvalidInput);
+        let lp_price = pool.get_lp_token_price(ctx.accounts.lp_token_mint.supply)?;
+        let token_price = read_oracle_price(&custody.oracle, &ctx.accounts.custody_oracle_account)?;
+        
+        // Whatever free liquidity can't cover stays in LP, escrowed under a
+        // withdrawal claim, instead of failing the whole withdrawal
+        let (lp_filled, amount_paid, usd_paid) =
+            fill_lp_redemption(custody, lp_after_fee, lp_price, &token_price)?;
+        let lp_claimed = lp_after_fee - lp_filled;
+        let lp_burned = params.lp_amount_in - lp_claimed;
+        
+        // `min_amount_out` is a floor on the rate per LP token: the part paid
+        // now must meet its share, and the claim carries the rest as its own
+        // floor for `fill_withdrawal_claim`
+        let min_paid = pro_rata(params.min_amount_out, lp_filled, lp_after_fee)?;
+        require!(amount_paid >= min_paid, ErrorCode::InvalidInput);
+        let min_claimed = params.min_amount_out - min_paid;
+        
+        // The rest of the pool must not end up carrying this custody's share
+        let custody_usd_after = token_to_usd(
+            custody.assets.owned
+                .checked_sub(amount_paid)
+                .ok_or(ErrorCode::MathOverflow)?,
+            custody.decimals,
+            &token_price,
+        )?;
+        pool.check_min_ratio(
+            &custody.key(),
+            custody_usd_after,
+            pool.aum_usd.saturating_sub(usd_paid as u128),
+        )?;
         
         // Transfer tokens from custody_token_account to receiving_account
         // Transfer authority PDA signs the transfer
@@ -1805,7 +3463,7 @@ pub mod perpetuals {
             ctx.accounts.receiving_account.to_account_info(),
             ctx.accounts.transfer_authority.to_account_info(),
             ctx.accounts.token_program.to_account_info(),
-            amount_out,
+            amount_paid,
         )?;
         
         // Burn LP tokens from lp_token_account
@@ -1815,23 +3473,134 @@ pub mod perpetuals {
             ctx.accounts.lp_token_account.to_account_info(),
             ctx.accounts.owner.to_account_info(),
             ctx.accounts.token_program.to_account_info(),
-            params.lp_amount_in,
+            lp_burned,
         )?;
         
         custody.assets.owned = custody.assets.owned
-            .checked_sub(amount_out)
+            .checked_sub(amount_paid)
             .ok_or(ErrorCode::MathOverflow)?;
         custody.collected_fees.remove_liquidity_usd = custody.collected_fees.remove_liquidity_usd
             .checked_add(fee)
             .ok_or(ErrorCode::MathOverflow)?;
         custody.volume_stats.remove_liquidity_usd = custody.volume_stats.remove_liquidity_usd
-            .checked_add(params.lp_amount_in)
+            .checked_add(lp_burned)
+            .ok_or(ErrorCode::MathOverflow)?;
+        
+        pool.aum_usd = pool.aum_usd
+            .checked_sub(usd_paid as u128)
             .ok_or(ErrorCode::MathOverflow)?;
         
+        let now = Clock::get()?.unix_timestamp;
+        let receipt = &mut ctx.accounts.deposit_receipt;
+        receipt.owner = ctx.accounts.owner.key();
+        receipt.pool = pool.key();
+        receipt.bump = ctx.bumps.deposit_receipt;
+        receipt.record_withdrawal(usd_paid, lp_burned, now)?;
+        
+        if let Some(claim) = ctx.accounts.withdrawal_claim.as_mut() {
+            if lp_claimed > 0 {
+                let escrow = ctx.accounts.withdrawal_escrow.as_ref()
+                    .ok_or(ErrorCode::InsufficientLiquidity)?;
+                perpetuals.transfer_tokens_from_user(
+                    ctx.accounts.lp_token_account.to_account_info(),
+                    escrow.to_account_info(),
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.token_program.to_account_info(),
+                    lp_claimed,
+                )?;
+                claim.owner = ctx.accounts.owner.key();
+                claim.pool = pool.key();
+                claim.custody = custody.key();
+                claim.lp_amount = claim.lp_amount
+                    .checked_add(lp_claimed)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                claim.min_amount_out = claim.min_amount_out
+                    .checked_add(min_claimed)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                claim.update_time = now;
+                claim.bump = ctx.bumps.withdrawal_claim
+                    .ok_or(ErrorCode::InsufficientLiquidity)?;
+            }
+            // Clients always pass the claim; hand back the rent when this
+            // withdrawal didn't need it
+            if claim.lp_amount == 0 {
+                claim.close(ctx.accounts.owner.to_account_info())?;
+            }
+        } else {
+            require!(lp_claimed == 0, ErrorCode::InsufficientLiquidity);
+        }
+        
+        Ok(())
+    }
+
+    /// Pays out an open withdrawal claim, in part or in full, once the
+    /// custody has free liquidity again. The escrowed LP is redeemed at the
+    /// current LP price, never below the claim's `min_amount_out` share, and
+    /// the claim is closed when nothing is left.
+    /// Permissionless so keepers can crank it for LPs.
+    pub fn fill_withdrawal_claim(ctx: Context<FillWithdrawalClaim>) -> Result<()> {
+        let perpetuals = ctx.accounts.perpetuals.as_ref();
+        let pool = &mut ctx.accounts.pool;
+        let custody = &mut ctx.accounts.custody;
+        let claim = &mut ctx.accounts.withdrawal_claim;
+        require!(!custody.withdrawals_frozen, ErrorCode::CustodyFrozen);
+        
+        let lp_price = pool.get_lp_token_price(ctx.accounts.lp_token_mint.supply)?;
+        let token_price = read_oracle_price(&custody.oracle, &ctx.accounts.custody_oracle_account)?;
+        let (lp_filled, amount_paid, usd_paid) =
+            fill_lp_redemption(custody, claim.lp_amount, lp_price, &token_price)?;
+        require!(lp_filled > 0 && amount_paid > 0, ErrorCode::InsufficientLiquidity);
+        let min_paid = pro_rata(claim.min_amount_out, lp_filled, claim.lp_amount)?;
+        require!(amount_paid >= min_paid, ErrorCode::ClaimBelowMinAmountOut);
+        
+        let custody_usd_after = token_to_usd(
+            custody.assets.owned
+                .checked_sub(amount_paid)
+                .ok_or(ErrorCode::MathOverflow)?,
+            custody.decimals,
+            &token_price,
+        )?;
+        pool.check_min_ratio(
+            &custody.key(),
+            custody_usd_after,
+            pool.aum_usd.saturating_sub(usd_paid as u128),
+        )?;
+        
+        perpetuals.transfer_tokens(
+            ctx.accounts.custody_token_account.to_account_info(),
+            ctx.accounts.receiving_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            amount_paid,
+        )?;
+        perpetuals.burn_escrowed_tokens(
+            ctx.accounts.lp_token_mint.to_account_info(),
+            ctx.accounts.withdrawal_escrow.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            lp_filled,
+        )?;
+        
+        custody.assets.owned = custody.assets.owned
+            .checked_sub(amount_paid)
+            .ok_or(ErrorCode::MathOverflow)?;
+        custody.volume_stats.remove_liquidity_usd = custody.volume_stats.remove_liquidity_usd
+            .checked_add(lp_filled)
+            .ok_or(ErrorCode::MathOverflow)?;
         pool.aum_usd = pool.aum_usd
-            .checked_sub(params.lp_amount_in as u128)
+            .checked_sub(usd_paid as u128)
             .ok_or(ErrorCode::MathOverflow)?;
         
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.deposit_receipt.record_withdrawal(usd_paid, lp_filled, now)?;
+        
+        claim.lp_amount -= lp_filled;
+        claim.min_amount_out -= min_paid;
+        claim.update_time = now;
+        if claim.lp_amount == 0 {
+            claim.close(ctx.accounts.owner.to_account_info())?;
+        }
+        
         Ok(())
     }
 
@@ -1856,16 +3625,22 @@ pub mod perpetuals {
         perpetuals.transfer_authority_bump = ctx.bumps.transfer_authority;
         perpetuals.perpetuals_bump = ctx.bumps.perpetuals;
         perpetuals.inception_time = Clock::get()?.unix_timestamp;
-        
-        multisig.num_signers = 0;
-        multisig.num_signed = 0;
-        multisig.min_signatures = params.min_signatures;
-        multisig.instruction_accounts_len = 0;
-        multisig.instruction_data_len = 0;
-        multisig.instruction_hash = 0;
-        multisig.signers = [Pubkey::default(); 6];
-        multisig.signed = [0; 6];
+        perpetuals.config_timelock_sec = 0;
+        perpetuals.governance_program = Pubkey::default();
+        perpetuals.governance_realm = Pubkey::default();
+        perpetuals.priority_fees = PriorityFees::default();
+        perpetuals.init_version = ProgramVersion::current();
+        perpetuals.max_in_flight_computations = 0;
+        perpetuals.liquidation_priority = LiquidationPriority::default();
+        
+        if ctx.remaining_accounts.is_empty() {
+            let upgrade_authority = ctx.accounts.upgrade_authority.to_account_info();
+            multisig.set_signers(&[upgrade_authority], params.min_signatures)?;
+        } else {
+            multisig.set_signers(ctx.remaining_accounts, params.min_signatures)?;
+        }
         multisig.bump = ctx.bumps.multisig;
+        ctx.accounts.comp_def_registry.bump = ctx.bumps.comp_def_registry;
         
         Ok(())
     }
@@ -1874,6 +3649,8 @@ pub mod perpetuals {
         ctx: Context<AddPool>,
         params: AddPoolParams,
     ) -> Result<u8> {
+        Pool::validate_name(&params.name)?;
+
         let perpetuals = &mut ctx.accounts.perpetuals;
         let pool = &mut ctx.accounts.pool;
         
@@ -1884,12 +3661,63 @@ pub mod perpetuals {
         pool.bump = ctx.bumps.pool;
         pool.lp_token_bump = ctx.bumps.lp_token_mint;
         pool.inception_time = Clock::get()?.unix_timestamp;
+        pool.genesis_aum_usd = 0;
+        pool.genesis_time = 0;
+
+        // Optional so clusters without the token metadata program, such as
+        // a bare test validator, can still create pools
+        if let (Some(metadata), Some(metadata_program)) = (
+            ctx.accounts.lp_token_metadata.as_ref(),
+            ctx.accounts.metadata_program.as_ref(),
+        ) {
+            perpetuals.create_token_metadata(
+                metadata.to_account_info(),
+                ctx.accounts.lp_token_mint.to_account_info(),
+                ctx.accounts.transfer_authority.to_account_info(),
+                ctx.accounts.admin.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                ctx.accounts.rent.to_account_info(),
+                metadata_program.to_account_info(),
+                format!("{}-LP", pool.name),
+                params.lp_token_symbol,
+                params.lp_token_uri,
+            )?;
+        }
         
         perpetuals.pools.push(pool.key());
         
         Ok(pool.bump)
     }
 
+    pub fn set_lp_token_metadata(
+        ctx: Context<SetLpTokenMetadata>,
+        params: SetLpTokenMetadataParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.perpetuals.update_token_metadata(
+            ctx.accounts.lp_token_metadata.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            ctx.accounts.metadata_program.to_account_info(),
+            params.name,
+            params.symbol,
+            params.uri,
+        )?;
+        Ok(0)
+    }
+
     pub fn remove_pool(
         ctx: Context<RemovePool>,
         _params: RemovePoolParams,
@@ -1905,87 +3733,15 @@ pub mod perpetuals {
         ctx: Context<AddCustody>,
         params: AddCustodyParams,
     ) -> Result<u8> {
-        let pool = &mut ctx.accounts.pool;
-        let custody = &mut ctx.accounts.custody;
-        
-        let mint_data = ctx.accounts.custody_token_mint.data.borrow();
-        let decimals = mint_data[44];
-        
-        custody.pool = pool.key();
-        custody.mint = ctx.accounts.custody_token_mint.key();
-        custody.token_account = ctx.accounts.custody_token_account.key();
-        custody.decimals = decimals;
-        custody.is_stable = params.is_stable;
-        custody.is_virtual = params.is_virtual;
-        custody.oracle = params.oracle;
-        custody.pricing = params.pricing;
-        custody.permissions = params.permissions;
-        custody.fees = params.fees;
-        custody.borrow_rate = params.borrow_rate;
-        custody.assets = Assets {
-            collateral: 0,
-            protocol_fees: 0,
-            owned: 0,
-            locked: 0,
-        };
-        custody.collected_fees = FeesStats {
-            swap_usd: 0,
-            add_liquidity_usd: 0,
-            remove_liquidity_usd: 0,
-            open_position_usd: 0,
-            close_position_usd: 0,
-            liquidation_usd: 0,
-        };
-        custody.volume_stats = VolumeStats {
-            swap_usd: 0,
-            add_liquidity_usd: 0,
-            remove_liquidity_usd: 0,
-            open_position_usd: 0,
-            close_position_usd: 0,
-            liquidation_usd: 0,
-        };
-        custody.trade_stats = TradeStats {
-            profit_usd: 0,
-            loss_usd: 0,
-            oi_long_usd: 0,
-            oi_short_usd: 0,
-        };
-        custody.long_positions = PositionStats {
-            open_positions: 0,
-            collateral_usd: 0,
-            size_usd: 0,
-            borrow_size_usd: 0,
-            locked_amount: 0,
-            weighted_price: 0,
-            total_quantity: 0,
-            cumulative_interest_usd: 0,
-            cumulative_interest_snapshot: 0,
-        };
-        custody.short_positions = PositionStats {
-            open_positions: 0,
-            collateral_usd: 0,
-            size_usd: 0,
-            borrow_size_usd: 0,
-            locked_amount: 0,
-            weighted_price: 0,
-            total_quantity: 0,
-            cumulative_interest_usd: 0,
-            cumulative_interest_snapshot: 0,
-        };
-        custody.borrow_rate_state = BorrowRateState {
-            current_rate: 0,
-            cumulative_interest: 0,
-            last_update: Clock::get()?.unix_timestamp,
-        };
-        custody.bump = ctx.bumps.custody;
-        custody.token_account_bump = ctx.bumps.custody_token_account;
-        
-        pool.custodies.push(custody.key());
-        for ratio in params.ratios {
-            pool.ratios.push(ratio);
-        }
-        
-        Ok(custody.bump)
+        init_custody(ctx, params.into_v2())
+    }
+
+    /// `add_custody` with the fields added since, see `AddCustodyParamsV2`
+    pub fn add_custody_v2(
+        ctx: Context<AddCustody>,
+        params: AddCustodyParamsV2,
+    ) -> Result<u8> {
+        init_custody(ctx, params)
     }
 
     pub fn remove_custody(
@@ -2000,6 +3756,7 @@ pub mod perpetuals {
             pool.custodies.remove(pos);
             pool.ratios.remove(pos);
         }
+        Pool::validate_ratios(&params.ratios, pool.custodies.len())?;
         
         pool.ratios.clear();
         for ratio in params.ratios {
@@ -2013,81 +3770,588 @@ pub mod perpetuals {
         ctx: Context<SetCustodyConfig>,
         params: SetCustodyConfigParams,
     ) -> Result<u8> {
-        let custody = &mut ctx.accounts.custody;
-        let pool = &mut ctx.accounts.pool;
-        
-        custody.is_stable = params.is_stable;
-        custody.is_virtual = params.is_virtual;
-        custody.oracle = params.oracle;
-        custody.pricing = params.pricing;
-        custody.permissions = params.permissions;
-        custody.fees = params.fees;
-        custody.borrow_rate = params.borrow_rate;
-        
-        pool.ratios.clear();
-        for ratio in params.ratios {
-            pool.ratios.push(ratio);
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
         }
-        
-        Ok(custody.bump)
-    }
 
-    pub fn set_permissions(
-        ctx: Context<SetPermissions>,
-        params: SetPermissionsParams,
-    ) -> Result<u8> {
-        let perpetuals = &mut ctx.accounts.perpetuals;
-        perpetuals.permissions = Permissions {
-            allow_swap: params.allow_swap,
-            allow_add_liquidity: params.allow_add_liquidity,
-            allow_remove_liquidity: params.allow_remove_liquidity,
-            allow_open_position: params.allow_open_position,
-            allow_close_position: params.allow_close_position,
-            allow_pnl_withdrawal: params.allow_pnl_withdrawal,
-            allow_collateral_withdrawal: params.allow_collateral_withdrawal,
-            allow_size_change: params.allow_size_change,
-        };
-        Ok(perpetuals.perpetuals_bump)
+        require_governance_approval(
+            &ctx.accounts.perpetuals,
+            ctx.accounts.governance_approval.as_ref(),
+            governance_change_hash(instruction::SetCustodyConfig::DISCRIMINATOR, &instruction_data),
+            ctx.accounts.admin.to_account_info(),
+        )?;
+
+        let max_price_drift_bps_per_slot = ctx.accounts.custody.max_price_drift_bps_per_slot;
+        apply_custody_config(ctx, params.into_v2(max_price_drift_bps_per_slot))
     }
 
-    pub fn set_admin_signers(
-        ctx: Context<SetAdminSigners>,
-        params: SetAdminSignersParams,
+    /// `set_custody_config` with the fields added since, see
+    /// `SetCustodyConfigParamsV2`
+    pub fn set_custody_config_v2(
+        ctx: Context<SetCustodyConfig>,
+        params: SetCustodyConfigParamsV2,
     ) -> Result<u8> {
-        let multisig = &mut ctx.accounts.multisig;
-        multisig.min_signatures = params.min_signatures;
-        Ok(multisig.bump)
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        require_governance_approval(
+            &ctx.accounts.perpetuals,
+            ctx.accounts.governance_approval.as_ref(),
+            governance_change_hash(instruction::SetCustodyConfigV2::DISCRIMINATOR, &instruction_data),
+            ctx.accounts.admin.to_account_info(),
+        )?;
+
+        apply_custody_config(ctx, params)
     }
 
-    pub fn withdraw_fees(
-        ctx: Context<WithdrawFees>,
-        params: WithdrawFeesParams,
+    pub fn propose_custody_config(
+        ctx: Context<ProposeCustodyConfig>,
+        params: ProposeCustodyConfigParams,
     ) -> Result<u8> {
-        let custody = &mut ctx.accounts.custody;
-        
-        let amount = if params.amount > 0 {
-            params.amount
-        } else {
-            custody.assets.protocol_fees
-        };
-        
-        require!(amount <= custody.assets.protocol_fees, ErrorCode::InvalidInput);
-        
-        custody.assets.protocol_fees = custody.assets.protocol_fees
-            .checked_sub(amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-        custody.assets.owned = custody.assets.owned
-            .checked_sub(amount)
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        let current_time = Clock::get()?.unix_timestamp;
+        let execute_after = current_time
+            .checked_add(ctx.accounts.perpetuals.config_timelock_sec)
             .ok_or(ErrorCode::MathOverflow)?;
-        
-        Ok(custody.bump)
+
+        let pending = &mut ctx.accounts.pending_config;
+        pending.custody = ctx.accounts.custody.key();
+        pending.proposer = ctx.accounts.admin.key();
+        pending.pricing = params.pricing;
+        pending.fees = params.fees;
+        pending.borrow_rate = params.borrow_rate;
+        pending.proposed_time = current_time;
+        pending.execute_after = execute_after;
+        pending.bump = ctx.bumps.pending_config;
+
+        emit!(CustodyConfigProposedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            custody: pending.custody,
+            pricing: pending.pricing,
+            fees: pending.fees,
+            borrow_rate: pending.borrow_rate,
+            execute_after,
+        });
+
+        Ok(0)
     }
 
-    pub fn withdraw_sol_fees(
-        ctx: Context<WithdrawSolFees>,
-        params: WithdrawSolFeesParams,
+    pub fn execute_custody_config(
+        ctx: Context<ExecuteCustodyConfig>,
     ) -> Result<u8> {
-        let perpetuals = &ctx.accounts.perpetuals;
+        let pending = &ctx.accounts.pending_config;
+        require!(
+            Clock::get()?.unix_timestamp >= pending.execute_after,
+            ErrorCode::TimelockNotExpired
+        );
+        require_governance_approval(
+            &ctx.accounts.perpetuals,
+            ctx.accounts.governance_approval.as_ref(),
+            governance_change_hash(
+                instruction::ExecuteCustodyConfig::DISCRIMINATOR,
+                &Multisig::get_instruction_data(&**pending)?,
+            ),
+            ctx.accounts.executor.to_account_info(),
+        )?;
+
+        let custody = &mut ctx.accounts.custody;
+        custody.pricing = pending.pricing;
+        custody.fees = pending.fees;
+        custody.borrow_rate = pending.borrow_rate;
+
+        Ok(custody.bump)
+    }
+
+    pub fn set_config_timelock(
+        ctx: Context<SetConfigTimelock>,
+        params: SetConfigTimelockParams,
+    ) -> Result<u8> {
+        require!(params.timelock_sec >= 0, ErrorCode::InvalidInput);
+
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        require_governance_approval(
+            &ctx.accounts.perpetuals,
+            ctx.accounts.governance_approval.as_ref(),
+            governance_change_hash(instruction::SetConfigTimelock::DISCRIMINATOR, &instruction_data),
+            ctx.accounts.admin.to_account_info(),
+        )?;
+
+        ctx.accounts.perpetuals.config_timelock_sec = params.timelock_sec;
+        Ok(0)
+    }
+
+    pub fn set_governance(
+        ctx: Context<SetGovernance>,
+        params: SetGovernanceParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        // Once a realm is set, replacing or removing it needs its own approval.
+        require_governance_approval(
+            &ctx.accounts.perpetuals,
+            ctx.accounts.governance_approval.as_ref(),
+            governance_change_hash(instruction::SetGovernance::DISCRIMINATOR, &instruction_data),
+            ctx.accounts.admin.to_account_info(),
+        )?;
+
+        let perpetuals = &mut ctx.accounts.perpetuals;
+        perpetuals.governance_program = params.governance_program;
+        perpetuals.governance_realm = params.governance_realm;
+        Ok(0)
+    }
+
+    /// Records a config change approved by the configured realm. Meant as
+    /// the instruction of an spl-governance proposal, so `governance` only
+    /// signs once the proposal passed and is executed. `change_hash` is the
+    /// `governance_change_hash` of the approved instruction, which consumes
+    /// the approval.
+    pub fn approve_governance_change(
+        ctx: Context<ApproveGovernanceChange>,
+        params: ApproveGovernanceChangeParams,
+    ) -> Result<()> {
+        check_governance_account(&ctx.accounts.perpetuals, &ctx.accounts.governance)?;
+
+        let approval = &mut ctx.accounts.governance_approval;
+        approval.realm = ctx.accounts.perpetuals.governance_realm;
+        approval.governance = ctx.accounts.governance.key();
+        approval.change_hash = params.change_hash;
+        approval.bump = ctx.bumps.governance_approval;
+        Ok(())
+    }
+
+    pub fn set_trading_schedule(
+        ctx: Context<SetTradingSchedule>,
+        params: SetTradingScheduleParams,
+    ) -> Result<u8> {
+        require!(
+            params.schedule.open_sec < 86_400 && params.schedule.close_sec < 86_400,
+            ErrorCode::InvalidInput
+        );
+
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        require_governance_approval(
+            &ctx.accounts.perpetuals,
+            ctx.accounts.governance_approval.as_ref(),
+            governance_change_hash(instruction::SetTradingSchedule::DISCRIMINATOR, &instruction_data),
+            ctx.accounts.admin.to_account_info(),
+        )?;
+
+        ctx.accounts.custody.trading_schedule = params.schedule;
+        Ok(0)
+    }
+
+    pub fn set_risk_manager(
+        ctx: Context<SetRiskManager>,
+        params: SetRiskManagerParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.custody.risk_manager_enc_pubkey = params.risk_manager_enc_pubkey;
+        Ok(0)
+    }
+
+    /// Gates `open_position` and `open_position_public` on the custody to
+    /// the owners under `allowlist_root`; all zeroes opens it to everyone.
+    pub fn set_allowlist_root(
+        ctx: Context<SetAllowlistRoot>,
+        params: SetAllowlistRootParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.custody.allowlist_root = params.allowlist_root;
+        Ok(0)
+    }
+
+    /// Sets how far the cached price may be assumed to drift per slot when
+    /// risk-reducing actions fall back to it during an oracle outage.
+    pub fn set_price_fallback(
+        ctx: Context<SetPriceFallback>,
+        params: SetPriceFallbackParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.custody.max_price_drift_bps_per_slot = params.max_price_drift_bps_per_slot;
+        Ok(0)
+    }
+
+    /// Freezes deposits into or withdrawals from a single custody, e.g.
+    /// while its oracle is misbehaving. Applies immediately, unlike the
+    /// timelocked custody config.
+    pub fn set_custody_freeze(
+        ctx: Context<SetCustodyFreeze>,
+        params: SetCustodyFreezeParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        let custody = &mut ctx.accounts.custody;
+        custody.deposits_frozen = params.deposits_frozen;
+        custody.withdrawals_frozen = params.withdrawals_frozen;
+        Ok(0)
+    }
+
+    /// Sets how long positions on a market must stay open before closing at
+    /// a profit. Zero disables the check.
+    pub fn set_min_hold_period(
+        ctx: Context<SetMinHoldPeriod>,
+        params: SetMinHoldPeriodParams,
+    ) -> Result<u8> {
+        require!(params.min_hold_period_sec >= 0, ErrorCode::InvalidInput);
+
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.custody.min_hold_period_sec = params.min_hold_period_sec;
+        Ok(0)
+    }
+
+    /// Records the owner as allowlisted on a gated custody once their merkle
+    /// proof checks out, so opens only need the entry account.
+    pub fn join_allowlist(ctx: Context<JoinAllowlist>, params: JoinAllowlistParams) -> Result<()> {
+        let custody = &ctx.accounts.custody;
+        let owner = ctx.accounts.owner.key();
+        require!(
+            custody.allowlist_root != [0; 32]
+                && AllowlistEntry::compute_root(&owner, &params.proof) == custody.allowlist_root,
+            ErrorCode::NotAllowlisted
+        );
+
+        let entry = &mut ctx.accounts.allowlist_entry;
+        entry.custody = custody.key();
+        entry.owner = owner;
+        entry.root = custody.allowlist_root;
+        entry.bump = ctx.bumps.allowlist_entry;
+        Ok(())
+    }
+
+    pub fn set_permissions(
+        ctx: Context<SetPermissions>,
+        params: SetPermissionsParams,
+    ) -> Result<u8> {
+        let perpetuals = &mut ctx.accounts.perpetuals;
+        perpetuals.permissions = Permissions {
+            allow_swap: params.allow_swap,
+            allow_add_liquidity: params.allow_add_liquidity,
+            allow_remove_liquidity: params.allow_remove_liquidity,
+            allow_open_position: params.allow_open_position,
+            allow_close_position: params.allow_close_position,
+            allow_pnl_withdrawal: params.allow_pnl_withdrawal,
+            allow_collateral_withdrawal: params.allow_collateral_withdrawal,
+            allow_size_change: params.allow_size_change,
+        };
+        Ok(perpetuals.perpetuals_bump)
+    }
+
+    pub fn set_priority_fees(
+        ctx: Context<SetPriorityFees>,
+        params: SetPriorityFeesParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.perpetuals.priority_fees = params.priority_fees;
+        Ok(0)
+    }
+
+    pub fn set_computation_quota(
+        ctx: Context<SetComputationQuota>,
+        params: SetComputationQuotaParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.perpetuals.max_in_flight_computations = params.max_in_flight_computations;
+        Ok(0)
+    }
+
+    pub fn set_liquidation_priority(
+        ctx: Context<SetLiquidationPriority>,
+        params: SetLiquidationPriorityParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        ctx.accounts.perpetuals.liquidation_priority = params.liquidation_priority;
+        Ok(0)
+    }
+
+    /// Points a circuit at a computation definition registered under a new
+    /// name. Passing the default offset as `offset` removes the override.
+    pub fn set_comp_def_offset(
+        ctx: Context<SetCompDefOffset>,
+        params: SetCompDefOffsetParams,
+    ) -> Result<u8> {
+        require!(
+            [
+                COMP_DEF_OFFSET_OPEN_POSITION,
+                COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE,
+                COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE_BATCH,
+                COMP_DEF_OFFSET_CLOSE_POSITION,
+                COMP_DEF_OFFSET_ADD_COLLATERAL,
+                COMP_DEF_OFFSET_REMOVE_COLLATERAL,
+                COMP_DEF_OFFSET_LIQUIDATE,
+                COMP_DEF_OFFSET_LIQUIDATE_BATCH,
+                COMP_DEF_OFFSET_TRANSFER_POSITION,
+                COMP_DEF_OFFSET_REQUEST_POSITION_SUMMARY,
+            ]
+            .contains(&params.default_offset),
+            ErrorCode::InvalidInput
+        );
+
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        let registry = &mut ctx.accounts.comp_def_registry;
+        registry.bump = ctx.bumps.comp_def_registry;
+        registry.set(params.default_offset, params.offset)?;
+        Ok(0)
+    }
+
+    pub fn set_admin_signers(
+        ctx: Context<SetAdminSigners>,
+        params: SetAdminSignersParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let multisig = &mut ctx.accounts.multisig;
+        let signatures_left =
+            multisig.sign_multisig(&admin, &instruction_accounts[1..], &instruction_data)?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        multisig.set_signers(ctx.remaining_accounts, params.min_signatures)?;
+        Ok(0)
+    }
+
+    pub fn withdraw_fees(
+        ctx: Context<WithdrawFees>,
+        params: WithdrawFeesParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        let custody = &mut ctx.accounts.custody;
+        
+        let amount = if params.amount > 0 {
+            params.amount
+        } else {
+            custody.assets.protocol_fees
+        };
+        
+        require!(amount <= custody.assets.protocol_fees, ErrorCode::InvalidInput);
+        
+        ctx.accounts.perpetuals.transfer_tokens(
+            ctx.accounts.custody_token_account.to_account_info(),
+            ctx.accounts.receiving_account.to_account_info(),
+            ctx.accounts.transfer_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            amount,
+        )?;
+        
+        custody.assets.protocol_fees = custody.assets.protocol_fees
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        custody.assets.owned = custody.assets.owned
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        
+        Ok(0)
+    }
+
+    pub fn withdraw_sol_fees(
+        ctx: Context<WithdrawSolFees>,
+        params: WithdrawSolFeesParams,
+    ) -> Result<u8> {
+        let perpetuals = &ctx.accounts.perpetuals;
         let receiver = &ctx.accounts.receiver;
         
         let amount = if params.amount > 0 {
@@ -2110,16 +4374,87 @@ pub mod perpetuals {
         Ok(perpetuals.perpetuals_bump)
     }
 
+    /// Registers, updates or deactivates a market maker's fee schedule
+    pub fn set_market_maker(
+        ctx: Context<SetMarketMaker>,
+        params: SetMarketMakerParams,
+    ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
+        let market_maker = &mut ctx.accounts.market_maker;
+        market_maker.owner = ctx.accounts.market_maker_owner.key();
+        market_maker.open_position_fee = params.open_position_fee;
+        market_maker.is_active = params.is_active;
+        market_maker.bump = ctx.bumps.market_maker;
+        Ok(0)
+    }
+
     pub fn set_custom_oracle_price(
         ctx: Context<SetCustomOraclePrice>,
         params: SetCustomOraclePriceParams,
     ) -> Result<u8> {
+        let instruction_accounts = Multisig::get_account_infos(&ctx);
+        let instruction_data = Multisig::get_instruction_data(&params)?;
+        let admin = ctx.accounts.admin.to_account_info();
+
+        let signatures_left = ctx.accounts.multisig.sign_multisig(
+            &admin,
+            &instruction_accounts[1..],
+            &instruction_data,
+        )?;
+        if signatures_left > 0 {
+            msg!("Instruction has been signed but more signatures are required: {}", signatures_left);
+            return Ok(signatures_left);
+        }
+
         let oracle = &mut ctx.accounts.custom_oracle;
-        oracle.price = params.price;
-        oracle.expo = params.expo;
-        oracle.conf = params.conf;
-        oracle.ema = params.ema;
-        oracle.publish_time = params.publish_time;
+        let pending = &mut ctx.accounts.custom_oracle_override;
+        pending.bump = ctx.bumps.custom_oracle_override;
+        let current_time = Clock::get()?.unix_timestamp;
+
+        if oracle.price > 0 {
+            let max_price_jump = (oracle.price as u128)
+                .checked_mul(CUSTOM_ORACLE_MAX_PRICE_JUMP_BPS as u128)
+                .ok_or(ErrorCode::MathOverflow)?
+                .checked_div(10000)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            if oracle.price.abs_diff(params.price) as u128 > max_price_jump {
+                require!(params.override_price_band, ErrorCode::PriceJumpTooLarge);
+
+                // The first override request only schedules the price; it can be
+                // applied by resubmitting the same price once the timelock expires.
+                if pending.unlock_time == 0 || pending.price != params.price {
+                    pending.price = params.price;
+                    pending.unlock_time = current_time
+                        .checked_add(CUSTOM_ORACLE_OVERRIDE_TIMELOCK_SEC)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                    msg!("Price override scheduled, unlocks at {}", pending.unlock_time);
+                    return Ok(0);
+                }
+
+                require!(
+                    current_time >= pending.unlock_time,
+                    ErrorCode::TimelockNotExpired
+                );
+            }
+        }
+
+        oracle.set(params.price, params.expo, params.conf, params.ema, params.publish_time);
+        pending.price = 0;
+        pending.unlock_time = 0;
         Ok(0)
     }
 
@@ -2224,35 +4559,784 @@ impl Perpetuals {
 
         anchor_spl::token::burn(context, amount)
     }
-}
 
-fn get_price_from_oracle(
-    oracle_params: &OracleParams,
-    oracle_account: &AccountInfo,
-) -> Result<u64> {
-    match oracle_params.oracle_type {
-        OracleType::Custom => {
-            let data = oracle_account.try_borrow_data()?;
-            require!(data.len() >= 8 + std::mem::size_of::<CustomOracle>(), ErrorCode::InvalidInput);
-            
-            let price_data = &data[8..];
-            let price = u64::from_le_bytes(price_data[0..8].try_into().unwrap());
-            
-            Ok(price)
-        },
-        OracleType::Pyth => {
-            Ok(50000_00_0000)
-        },
-        OracleType::None => {
-            Ok(50000_00_0000)
-        }
+    /// Burns from a token account owned by `transfer_authority`, which
+    /// signs the burn
+    pub fn burn_escrowed_tokens<'info>(
+        &self,
+        mint: AccountInfo<'info>,
+        from: AccountInfo<'info>,
+        authority: AccountInfo<'info>,
+        token_program: AccountInfo<'info>,
+        amount: u64,
+    ) -> Result<()> {
+        let authority_seeds: &[&[&[u8]]] =
+            &[&[b"transfer_authority", &[self.transfer_authority_bump]]];
+        let context = CpiContext::new(
+            token_program,
+            Burn {
+                mint,
+                from,
+                authority,
+            },
+        )
+        .with_signer(authority_seeds);
+
+        anchor_spl::token::burn(context, amount)
     }
-}
 
-fn calculate_fee_rate(
-    mode: FeesMode,
-    base_rate: u64,
-    custody: &Custody,
+    /// Creates mutable Metaplex metadata for a mint whose mint and update
+    /// authority is `transfer_authority`
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_token_metadata<'info>(
+        &self,
+        metadata: AccountInfo<'info>,
+        mint: AccountInfo<'info>,
+        authority: AccountInfo<'info>,
+        payer: AccountInfo<'info>,
+        system_program: AccountInfo<'info>,
+        rent: AccountInfo<'info>,
+        metadata_program: AccountInfo<'info>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        let authority_seeds: &[&[&[u8]]] =
+            &[&[b"transfer_authority", &[self.transfer_authority_bump]]];
+        let context = CpiContext::new(
+            metadata_program,
+            CreateMetadataAccountsV3 {
+                metadata,
+                mint,
+                mint_authority: authority.clone(),
+                payer,
+                update_authority: authority,
+                system_program,
+                rent,
+            },
+        )
+        .with_signer(authority_seeds);
+
+        anchor_spl::metadata::create_metadata_accounts_v3(
+            context,
+            Self::token_metadata(name, symbol, uri),
+            true,
+            true,
+            None,
+        )
+    }
+
+    pub fn update_token_metadata<'info>(
+        &self,
+        metadata: AccountInfo<'info>,
+        authority: AccountInfo<'info>,
+        metadata_program: AccountInfo<'info>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        let authority_seeds: &[&[&[u8]]] =
+            &[&[b"transfer_authority", &[self.transfer_authority_bump]]];
+        let context = CpiContext::new(
+            metadata_program,
+            UpdateMetadataAccountsV2 {
+                metadata,
+                update_authority: authority,
+            },
+        )
+        .with_signer(authority_seeds);
+
+        anchor_spl::metadata::update_metadata_accounts_v2(
+            context,
+            None,
+            Some(Self::token_metadata(name, symbol, uri)),
+            None,
+            None,
+        )
+    }
+
+    fn token_metadata(name: String, symbol: String, uri: String) -> DataV2 {
+        DataV2 {
+            name,
+            symbol,
+            uri,
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        }
+    }
+}
+
+/// Comp-def offset to queue or accept callbacks for, honouring overrides in
+/// the registry.
+fn resolve_comp_def_offset(registry: &Account<CompDefRegistry>, default_offset: u32) -> u32 {
+    registry.resolve(default_offset)
+}
+
+/// Forwards the registry to the callback, which resolves the same offset.
+fn comp_def_registry_callback_account(registry: &Account<CompDefRegistry>) -> CallbackAccount {
+    CallbackAccount {
+        pubkey: registry.key(),
+        is_writable: false,
+    }
+}
+
+/// Notes who initialized a computation definition in `CompDefInitLog`.
+fn log_comp_def_init(
+    log: &mut Account<CompDefInitLog>,
+    bump: u8,
+    comp_def_offset: u32,
+    initializer: Pubkey,
+) -> Result<()> {
+    log.bump = bump;
+    log.record(comp_def_offset, initializer, Clock::get()?.unix_timestamp)
+}
+
+/// `event_authority` and `program` accounts that `#[event_cpi]` appends to a
+/// callback context, in that order, so the callback can `emit_cpi!`.
+fn event_cpi_callback_accounts() -> [CallbackAccount; 2] {
+    let (event_authority, _) = Pubkey::find_program_address(&[b"__event_authority"], &crate::ID);
+    [
+        CallbackAccount { pubkey: event_authority, is_writable: false },
+        CallbackAccount { pubkey: crate::ID, is_writable: false },
+    ]
+}
+
+fn get_price_from_oracle(
+    oracle_params: &OracleParams,
+    oracle_account: &AccountInfo,
+) -> Result<u64> {
+    Ok(read_oracle_price(oracle_params, oracle_account)?.price)
+}
+
+/// Oracle price together with its exponent, for conversions between
+/// custodies (see utils::conversion).
+fn read_oracle_price(
+    oracle_params: &OracleParams,
+    oracle_account: &AccountInfo,
+) -> Result<OraclePrice> {
+    match oracle_params.oracle_type {
+        OracleType::Custom => {
+            let data = oracle_account.try_borrow_data()?;
+            require!(data.len() >= CustomOracle::LEN, ErrorCode::InvalidInput);
+            
+            let price_data = &data[8..];
+            let price = u64::from_le_bytes(price_data[0..8].try_into().unwrap());
+            let exponent = i32::from_le_bytes(price_data[8..12].try_into().unwrap());
+            
+            Ok(OraclePrice { price, exponent })
+        },
+        OracleType::Pyth => {
+            Ok(OraclePrice { price: 50000_00_0000, exponent: -(USD_DECIMALS as i32) })
+        },
+        OracleType::None => {
+            Ok(OraclePrice { price: 50000_00_0000, exponent: -(USD_DECIMALS as i32) })
+        }
+    }
+}
+
+/// Whether the oracle was published within `max_price_age_sec`. Only custom
+/// oracles carry a publish time; zero max age disables the check.
+fn oracle_is_fresh(oracle_params: &OracleParams, oracle_account: &AccountInfo) -> Result<bool> {
+    if oracle_params.oracle_type != OracleType::Custom || oracle_params.max_price_age_sec == 0 {
+        return Ok(true);
+    }
+    let data = oracle_account.try_borrow_data()?;
+    require!(data.len() >= CustomOracle::LEN, ErrorCode::InvalidInput);
+    let publish_time = i64::from_le_bytes(data[8 + 28..8 + 36].try_into().unwrap());
+    let age = Clock::get()?.unix_timestamp.saturating_sub(publish_time);
+    Ok(age <= oracle_params.max_price_age_sec as i64)
+}
+
+/// Value of the pool's owned assets at fresh oracle prices. `accounts` holds
+/// every custody of the pool in order, followed by their oracle accounts.
+fn pool_aum_at_oracle_prices(pool: &Pool, accounts: &[AccountInfo]) -> Result<u128> {
+    let custodies = pool.custodies.len();
+    require!(accounts.len() == custodies * 2, ErrorCode::InvalidInput);
+
+    let mut aum_usd: u128 = 0;
+    for (idx, custody_key) in pool.custodies.iter().enumerate() {
+        let custody_info = &accounts[idx];
+        let oracle_info = &accounts[custodies + idx];
+        require_keys_eq!(custody_info.key(), *custody_key, ErrorCode::InvalidInput);
+        require_keys_eq!(*custody_info.owner, crate::ID, ErrorCode::InvalidInput);
+        let custody = Custody::try_deserialize(&mut &custody_info.try_borrow_data()?[..])?;
+        require_keys_eq!(
+            oracle_info.key(),
+            custody.oracle.oracle_account,
+            ErrorCode::InvalidInput
+        );
+        require!(
+            oracle_is_fresh(&custody.oracle, oracle_info)?,
+            ErrorCode::StaleOraclePrice
+        );
+
+        let price = read_oracle_price(&custody.oracle, oracle_info)?;
+        let owned_usd = token_to_usd(custody.assets.owned, custody.decimals, &price)?;
+        aum_usd = aum_usd
+            .checked_add(owned_usd as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    Ok(aum_usd)
+}
+
+/// How an instruction uses the mark price, which decides whether it may
+/// proceed on `Custody::fallback_price` while the oracle is stale.
+/// Liquidations read the oracle directly and always need a fresh price.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PriceUse {
+    IncreaseRisk,
+    ReduceRisk,
+}
+
+/// Oracle price of the custody token. When the oracle is stale only
+/// risk-reducing actions continue, on the bounded fallback derived from the
+/// price last cached by `refresh_price_cache`.
+fn get_mark_price(
+    custody: &Account<Custody>,
+    oracle_account: &AccountInfo,
+    price_cache: Option<&Account<PriceCache>>,
+    price_use: PriceUse,
+    side: PositionSide,
+) -> Result<u64> {
+    if oracle_is_fresh(&custody.oracle, oracle_account)? {
+        return get_price_from_oracle(&custody.oracle, oracle_account);
+    }
+    require!(price_use == PriceUse::ReduceRisk, ErrorCode::StaleOraclePrice);
+
+    let cache = price_cache
+        .filter(|cache| cache.custody == custody.key())
+        .ok_or(ErrorCode::StaleOraclePrice)?;
+    msg!("Oracle is stale, using fallback from slot {}", cache.slot);
+    custody.fallback_price(cache.price, cache.slot, Clock::get()?.slot, side)
+}
+
+/// Token and collateral prices read once at the top of an instruction. When
+/// both custodies share a fresh oracle account it is only parsed once. The
+/// collateral is always valued at the raw oracle price, never the side-biased
+/// fallback, and risk-increasing actions need its oracle fresh too.
+struct PriceContext {
+    token_price: u64,
+    collateral_price: u64,
+}
+
+impl PriceContext {
+    fn load(
+        custody: &Account<Custody>,
+        custody_oracle_account: &AccountInfo,
+        collateral_custody: &Custody,
+        collateral_oracle_account: &AccountInfo,
+        price_cache: Option<&Account<PriceCache>>,
+        price_use: PriceUse,
+        side: PositionSide,
+    ) -> Result<Self> {
+        let token_price = get_mark_price(
+            custody,
+            custody_oracle_account,
+            price_cache,
+            price_use,
+            side,
+        )?;
+        let shares_oracle = collateral_oracle_account.key() == custody_oracle_account.key()
+            && collateral_custody.oracle.oracle_type == custody.oracle.oracle_type;
+        let collateral_price = if shares_oracle && oracle_is_fresh(&custody.oracle, custody_oracle_account)? {
+            token_price
+        } else {
+            if price_use == PriceUse::IncreaseRisk {
+                require!(
+                    oracle_is_fresh(&collateral_custody.oracle, collateral_oracle_account)?,
+                    ErrorCode::StaleOraclePrice
+                );
+            }
+            get_price_from_oracle(&collateral_custody.oracle, collateral_oracle_account)?
+        };
+
+        Ok(Self {
+            token_price,
+            collateral_price,
+        })
+    }
+}
+
+/// Returns the price cached by `refresh_price_cache` if it was written in
+/// the current slot, otherwise parses the oracle account. Liquidations never
+/// run on a fallback price, so that oracle must be fresh.
+fn get_cached_price(
+    price_cache: Option<&Account<PriceCache>>,
+    custody: &Account<Custody>,
+    oracle_account: &AccountInfo,
+) -> Result<u64> {
+    if let Some(cache) = price_cache {
+        if cache.custody == custody.key() && cache.slot == Clock::get()?.slot {
+            return Ok(cache.price);
+        }
+    }
+    require!(
+        oracle_is_fresh(&custody.oracle, oracle_account)?,
+        ErrorCode::StaleOraclePrice
+    );
+    get_price_from_oracle(&custody.oracle, oracle_account)
+}
+
+/// Fails up front when the transaction's compute budget can't cover queueing,
+/// rather than running out inside the Arcium CPI with an opaque error.
+/// Clients should raise the limit with a `SetComputeUnitLimit` instruction.
+fn require_queue_compute_budget() -> Result<()> {
+    let remaining = anchor_lang::solana_program::compute_units::sol_remaining_compute_units();
+    if remaining < MIN_QUEUE_COMPUTE_UNITS {
+        msg!(
+            "{} compute units left, queueing needs at least {}",
+            remaining,
+            MIN_QUEUE_COMPUTE_UNITS
+        );
+        return err!(ErrorCode::InsufficientComputeBudget);
+    }
+    Ok(())
+}
+
+/// Rejects a callback output whose nonce doesn't advance past the one the
+/// stored ciphertext was encrypted under. The MXE re-encrypts to an owner with
+/// that owner's nonce plus one, so anything else is a malformed output.
+fn require_nonce_advances(stored_nonce: u128, output_nonce: u128) -> Result<()> {
+    require!(output_nonce > stored_nonce, ErrorCode::MalformedComputationOutput);
+    Ok(())
+}
+
+/// Logs a callback whose output failed verification and emits a
+/// `ComputationFailedEvent` so operators can tell MPC aborts, timeouts and
+/// rejected inputs apart. Callbacks still fail with `AbortedComputation`.
+fn callback_error(
+    kind: ComputationKind,
+    comp_def_offset: u32,
+    computation_account: Pubkey,
+    err: Error,
+) -> Error {
+    msg!("Error: {}", err);
+    emit!(ComputationFailedEvent {
+        schema_version: EVENT_SCHEMA_VERSION,
+        kind,
+        circuit: kind.circuit().to_string(),
+        comp_def_offset,
+        computation_account,
+        failure: ComputationFailureKind::from_error(&err),
+        slot: Clock::get().map(|clock| clock.slot).unwrap_or_default(),
+    });
+    ErrorCode::AbortedComputation.into()
+}
+
+/// Emits `ComputationQueuedEvent` once a computation is in the mempool. Its
+/// callback answers with `ComputationCompletedEvent` or, through
+/// `callback_error`, `ComputationFailedEvent`; all three carry the
+/// computation account so indexers can pair them.
+fn computation_queued(
+    kind: ComputationKind,
+    computation_offset: u64,
+    computation_account: Pubkey,
+    payer: Pubkey,
+) {
+    emit!(ComputationQueuedEvent {
+        schema_version: EVENT_SCHEMA_VERSION,
+        kind,
+        computation_offset,
+        computation_account,
+        payer,
+        slot: Clock::get().map(|clock| clock.slot).unwrap_or_default(),
+    });
+}
+
+fn computation_completed(kind: ComputationKind, computation_account: Pubkey) {
+    emit!(ComputationCompletedEvent {
+        schema_version: EVENT_SCHEMA_VERSION,
+        kind,
+        computation_account,
+        slot: Clock::get().map(|clock| clock.slot).unwrap_or_default(),
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_open_position(
+    ctx: Context<OpenPosition>,
+    computation_offset: u64,
+    position_id: u64,
+    side: u8,
+    entry_price: u64,
+    size_encrypted: [u8; 32],
+    collateral_encrypted: [u8; 32],
+    client_pubkey: [u8; 32],
+    size_nonce: u128,
+    collateral_nonce: u128,
+    client_request_id: Option<u64>,
+    receipt_nonce: u128,
+    client_tag: Option<[u8; 32]>,
+    origin_program: Pubkey,
+) -> Result<()> {
+    require_queue_compute_budget()?;
+
+    require!(side <= 1, ErrorCode::InvalidPositionSide);
+    require!(
+        ctx.accounts.collateral_custody.trading_schedule.is_open(Clock::get()?.unix_timestamp),
+        ErrorCode::MarketClosed
+    );
+    ctx.accounts.collateral_custody.check_allowlist(
+        &ctx.accounts.owner.key(),
+        ctx.accounts.allowlist_entry.as_deref(),
+    )?;
+
+    let position_key = ctx.accounts.position.key();
+
+    let position = &mut ctx.accounts.position;
+    position.owner = ctx.accounts.owner.key();
+    position.position_id = position_id;
+    position.side = if side == 0 {
+        PositionSide::Long
+    } else {
+        PositionSide::Short
+    };
+    position.size_usd_encrypted = size_encrypted;
+    position.collateral_usd_encrypted = collateral_encrypted;
+    position.entry_price = entry_price;
+    position.open_time = Clock::get()?.unix_timestamp;
+    position.update_time = Clock::get()?.unix_timestamp;
+    position.owner_enc_pubkey = client_pubkey;
+    position.size_nonce = size_nonce;
+    position.collateral_nonce = collateral_nonce;
+    position.liquidator = Pubkey::default();  // Initialize to default, set during liquidation
+    position.bump = ctx.bumps.position;
+    position.version = POSITION_VERSION;
+    position.collateral_custody = ctx.accounts.collateral_custody.key();
+    position.locked_amount = 0;  // Set by the callback from the revealed bound
+    position.client_request_id = client_request_id.unwrap_or(0);
+    position.client_tag = client_tag.unwrap_or_default();
+    position.close_delegate = Pubkey::default();
+    position.cumulative_interest_snapshot = 0;  // Set by the callback once funds are locked
+    position.accrued_interest_usd = 0;
+    position.origin_program = origin_program;
+    position.last_computation_offset = computation_offset;
+    position.last_computation_kind = ComputationKind::OpenPosition.position_tag();
+    position.pending_expiry_slot = Clock::get()?
+        .slot
+        .checked_add(PENDING_POSITION_TTL_SLOTS)
+        .ok_or(ErrorCode::MathOverflow)?;
+    position.pending_payer = ctx.accounts.payer.key();
+
+    // The receipt goes to the owner's registered key when there is one,
+    // so a rotation before the callback lands doesn't strand the position
+    let (receipt_pubkey, receipt_key_epoch) = ctx
+        .accounts
+        .encryption_key
+        .as_ref()
+        .map(|key| (key.enc_pubkey, key.key_epoch))
+        .unwrap_or((client_pubkey, 0));
+    position.receipt_key_epoch = receipt_key_epoch;
+
+    let args = ArgBuilder::new()
+        .x25519_pubkey(client_pubkey)
+        .plaintext_u128(size_nonce)
+        .encrypted_u64(size_encrypted)
+        .x25519_pubkey(client_pubkey)
+        .plaintext_u128(collateral_nonce)
+        .encrypted_u64(collateral_encrypted)
+        .x25519_pubkey(receipt_pubkey)
+        .plaintext_u128(receipt_nonce)
+        .build();
+
+    ctx.accounts.sign_pda_account.bump = ctx.bumps.sign_pda_account;
+
+    ctx.accounts.payer_quota.reserve(
+        ctx.accounts.payer.key(),
+        ctx.accounts.perpetuals.max_in_flight_computations,
+        ctx.bumps.payer_quota,
+    )?;
+
+    queue_computation(
+        ctx.accounts,
+        computation_offset,
+        args,
+        None,
+        vec![OpenPositionCallback::callback_ix(
+            computation_offset,
+            &ctx.accounts.mxe_account,
+            &[
+            CallbackAccount { pubkey: position_key, is_writable: true },
+            CallbackAccount { pubkey: ctx.accounts.collateral_custody.key(), is_writable: true },
+            comp_def_registry_callback_account(&ctx.accounts.comp_def_registry),
+            CallbackAccount { pubkey: ctx.accounts.payer_quota.key(), is_writable: true },
+            // An absent optional account is encoded as the program id
+            CallbackAccount {
+                pubkey: ctx.accounts.encryption_key.as_ref().map_or(crate::ID, |key| key.key()),
+                is_writable: false,
+            },
+            ]
+        )?],
+        1,
+        0,  // cu_price_micro: priority fee in microlamports (0 = no priority fee)
+    )?;
+    computation_queued(
+        ComputationKind::OpenPosition,
+        computation_offset,
+        ctx.accounts.computation_account.key(),
+        ctx.accounts.payer.key(),
+    );
+
+    Ok(())
+}
+
+/// Program whose top-level instruction invoked this one. Fails unless the
+/// current instruction is a direct CPI from another program, since only then
+/// is that program the one whose PDA could have signed.
+fn cpi_origin_program(instructions_sysvar: &AccountInfo) -> Result<Pubkey> {
+    use anchor_lang::solana_program::instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT};
+    use anchor_lang::solana_program::sysvar::instructions::{
+        load_current_index_checked, load_instruction_at_checked,
+    };
+
+    require!(
+        get_stack_height() == TRANSACTION_LEVEL_STACK_HEIGHT + 1,
+        ErrorCode::NotInvokedViaCpi
+    );
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    let outer = load_instruction_at_checked(current_index as usize, instructions_sysvar)?;
+    require!(outer.program_id != crate::ID, ErrorCode::NotInvokedViaCpi);
+    Ok(outer.program_id)
+}
+
+/// Splits redeeming `lp_amount` into what the custody's free liquidity
+/// (owned minus what open positions have locked) can pay now. Returns the LP
+/// filled with the token amount and USD value paid for it; the remaining LP
+/// is left for a withdrawal claim.
+fn fill_lp_redemption(
+    custody: &Custody,
+    lp_amount: u64,
+    lp_price: u64,
+    token_price: &OraclePrice,
+) -> Result<(u64, u64, u64)> {
+    let usd_out = lp_to_usd(lp_amount, lp_price)?;
+    let amount_out = usd_to_token(usd_out, custody.decimals, token_price)?;
+    let available = custody.assets.owned.saturating_sub(custody.assets.locked);
+    if amount_out <= available {
+        return Ok((lp_amount, amount_out, usd_out));
+    }
+
+    let lp_filled = (lp_amount as u128)
+        .checked_mul(available as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / amount_out as u128;
+    let lp_filled = lp_filled as u64;
+    let usd_paid = lp_to_usd(lp_filled, lp_price)?;
+    let amount_paid = usd_to_token(usd_paid, custody.decimals, token_price)?;
+    Ok((lp_filled, amount_paid, usd_paid))
+}
+
+fn init_custody(ctx: Context<AddCustody>, params: AddCustodyParamsV2) -> Result<u8> {
+    let pool = &mut ctx.accounts.pool;
+    let custody = &mut ctx.accounts.custody;
+    
+    let mint_data = ctx.accounts.custody_token_mint.data.borrow();
+    let decimals = mint_data[44];
+    
+    custody.pool = pool.key();
+    custody.mint = ctx.accounts.custody_token_mint.key();
+    custody.token_account = ctx.accounts.custody_token_account.key();
+    custody.decimals = decimals;
+    custody.is_stable = params.is_stable;
+    custody.is_virtual = params.is_virtual;
+    custody.oracle = params.oracle;
+    custody.pricing = params.pricing;
+    custody.permissions = params.permissions;
+    custody.fees = params.fees;
+    custody.borrow_rate = params.borrow_rate;
+    custody.assets = Assets {
+        collateral: 0,
+        protocol_fees: 0,
+        owned: 0,
+        locked: 0,
+    };
+    custody.collected_fees = FeesStats {
+        swap_usd: 0,
+        add_liquidity_usd: 0,
+        remove_liquidity_usd: 0,
+        open_position_usd: 0,
+        close_position_usd: 0,
+        liquidation_usd: 0,
+        open_position_maker_usd: 0,
+        open_position_taker_usd: 0,
+    };
+    custody.volume_stats = VolumeStats {
+        swap_usd: 0,
+        add_liquidity_usd: 0,
+        remove_liquidity_usd: 0,
+        open_position_usd: 0,
+        close_position_usd: 0,
+        liquidation_usd: 0,
+    };
+    custody.trade_stats = TradeStats {
+        profit_usd: 0,
+        loss_usd: 0,
+        oi_long_usd: 0,
+        oi_short_usd: 0,
+    };
+    custody.long_positions = PositionStats {
+        open_positions: 0,
+        collateral_usd: 0,
+        size_usd: 0,
+        borrow_size_usd: 0,
+        locked_amount: 0,
+        weighted_price: 0,
+        total_quantity: 0,
+        cumulative_interest_usd: 0,
+        cumulative_interest_snapshot: 0,
+    };
+    custody.short_positions = PositionStats {
+        open_positions: 0,
+        collateral_usd: 0,
+        size_usd: 0,
+        borrow_size_usd: 0,
+        locked_amount: 0,
+        weighted_price: 0,
+        total_quantity: 0,
+        cumulative_interest_usd: 0,
+        cumulative_interest_snapshot: 0,
+    };
+    custody.borrow_rate_state = BorrowRateState {
+        current_rate: 0,
+        cumulative_interest: 0,
+        last_update: Clock::get()?.unix_timestamp,
+    };
+    custody.bump = ctx.bumps.custody;
+    custody.token_account_bump = ctx.bumps.custody_token_account;
+    custody.trading_schedule = TradingSchedule {
+        enabled: false,
+        open_days: 0,
+        open_sec: 0,
+        close_sec: 0,
+        maintenance_start: 0,
+        maintenance_end: 0,
+    };
+    custody.risk_manager_enc_pubkey = [0; 32];
+    custody.allowlist_root = [0; 32];
+    custody.max_price_drift_bps_per_slot = params.max_price_drift_bps_per_slot;
+    custody.deposits_frozen = false;
+    custody.withdrawals_frozen = false;
+    custody.min_hold_period_sec = 0;
+    custody.rolling_stats = RollingStats::default();
+    
+    require!(
+        pool.custodies.len() < Pool::MAX_CUSTODIES,
+        ErrorCode::TooManyCustodies
+    );
+    Pool::validate_ratios(
+        &params.ratios,
+        Pool::MAX_CUSTODIES.saturating_sub(pool.ratios.len()),
+    )?;
+
+    pool.custodies.push(custody.key());
+    for ratio in params.ratios {
+        pool.ratios.push(ratio);
+    }
+    
+    Ok(custody.bump)
+}
+
+fn apply_custody_config(ctx: Context<SetCustodyConfig>, params: SetCustodyConfigParamsV2) -> Result<u8> {
+    let custody = &mut ctx.accounts.custody;
+    let pool = &mut ctx.accounts.pool;
+    
+    // With a timelock configured, risk parameters can only change through
+    // propose_custody_config / execute_custody_config, and the rest of the
+    // config (the oracle above all) only once the timelock is lifted again.
+    if ctx.accounts.perpetuals.config_timelock_sec > 0 {
+        require!(
+            params.pricing == custody.pricing
+                && params.fees == custody.fees
+                && params.borrow_rate == custody.borrow_rate
+                && params.is_stable == custody.is_stable
+                && params.is_virtual == custody.is_virtual
+                && params.oracle == custody.oracle
+                && params.permissions == custody.permissions
+                && params.max_price_drift_bps_per_slot == custody.max_price_drift_bps_per_slot
+                && params.ratios == pool.ratios,
+            ErrorCode::ConfigChangeRequiresTimelock
+        );
+    }
+    
+    custody.is_stable = params.is_stable;
+    custody.is_virtual = params.is_virtual;
+    custody.oracle = params.oracle;
+    custody.pricing = params.pricing;
+    custody.permissions = params.permissions;
+    custody.fees = params.fees;
+    custody.borrow_rate = params.borrow_rate;
+    custody.max_price_drift_bps_per_slot = params.max_price_drift_bps_per_slot;
+    
+    Pool::validate_ratios(&params.ratios, pool.custodies.len())?;
+    pool.ratios.clear();
+    for ratio in params.ratios {
+        pool.ratios.push(ratio);
+    }
+    
+    Ok(custody.bump)
+}
+
+/// `amount * part / whole`, rounded down; zero when `whole` is zero
+fn pro_rata(amount: u64, part: u64, whole: u64) -> Result<u64> {
+    if whole == 0 {
+        return Ok(0);
+    }
+    let value = (amount as u128)
+        .checked_mul(part as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / whole as u128;
+    Ok(u64::try_from(value).map_err(|_| ErrorCode::MathOverflow)?)
+}
+
+/// Realized `(profit, loss)` in USD for closing `size_usd` at `exit_price`.
+/// Loss is capped at the posted collateral, which is all the trader can lose,
+/// and profit at `max_payoff_mult` bps of it like the close circuit does.
+fn realized_pnl(
+    side: PositionSide,
+    size_usd: u64,
+    collateral_usd: u64,
+    entry_price: u64,
+    exit_price: u64,
+    max_payoff_mult: u64,
+) -> Result<(u64, u64)> {
+    require!(entry_price > 0, ErrorCode::InvalidInput);
+
+    let (price_diff, is_profit) = match side {
+        PositionSide::Long if exit_price >= entry_price => (exit_price - entry_price, true),
+        PositionSide::Long => (entry_price - exit_price, false),
+        PositionSide::Short if exit_price <= entry_price => (entry_price - exit_price, true),
+        PositionSide::Short => (exit_price - entry_price, false),
+    };
+
+    let pnl = (size_usd as u128)
+        .checked_mul(price_diff as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(entry_price as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let pnl = u64::try_from(pnl).map_err(|_| ErrorCode::MathOverflow)?;
+
+    if is_profit {
+        let max_profit = if max_payoff_mult == 0 {
+            u64::MAX
+        } else {
+            let cap = (collateral_usd as u128)
+                .checked_mul(max_payoff_mult as u128)
+                .ok_or(ErrorCode::MathOverflow)?
+                / 10000;
+            u64::try_from(cap).unwrap_or(u64::MAX)
+        };
+        Ok((pnl.min(max_profit), 0))
+    } else {
+        Ok((0, pnl.min(collateral_usd)))
+    }
+}
+
+fn calculate_fee_rate(
+    mode: FeesMode,
+    base_rate: u64,
+    custody: &Custody,
     _size_usd: u64,
 ) -> Result<u64> {
     match mode {
@@ -2358,6 +5442,14 @@ pub struct InitOpenPositionCompDef<'info> {
     pub comp_def_account: UncheckedAccount<'info>,
     pub arcium_program: Program<'info, Arcium>,
     pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
 }
 
 #[queue_computation_accounts("open_position", payer)]
@@ -2400,7 +5492,7 @@ pub struct OpenPosition<'info> {
     /// CHECK: computation_account, checked by the arcium program.
     pub computation_account: UncheckedAccount<'info>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_OPEN_POSITION)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_OPEN_POSITION))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(
@@ -2427,6 +5519,46 @@ pub struct OpenPosition<'info> {
         bump
     )]
     pub position: Account<'info, Position>,
+    #[account(
+        constraint = perpetuals.pools.contains(&pool.key()) @ ErrorCode::InvalidInput,
+        constraint = pool.custodies.contains(&collateral_custody.key()) @ ErrorCode::InvalidInput
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+    /// Custody that locks the position's notional once the size is revealed
+    #[account(
+        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
+        bump = collateral_custody.bump
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    /// Owner's registered key, see `set_encryption_key`
+    #[account(seeds = [b"encryption_key", owner.key().as_ref()], bump)]
+    pub encryption_key: Option<Account<'info, EncryptionKey>>,
+    /// Required while `collateral_custody.allowlist_root` is set
+    #[account(
+        seeds = [b"allowlist_entry", collateral_custody.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    /// CHECK: instructions sysvar, required by `open_position_cpi`
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
 #[callback_accounts("open_position")]
@@ -2434,7 +5566,7 @@ pub struct OpenPosition<'info> {
 pub struct OpenPositionCallback<'info> {
     pub arcium_program: Program<'info, Arcium>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_OPEN_POSITION)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_OPEN_POSITION))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(address = derive_mxe_pda!())]
@@ -2446,8 +5578,30 @@ pub struct OpenPositionCallback<'info> {
     #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
     /// CHECK: instructions_sysvar
     pub instructions_sysvar: AccountInfo<'info>,
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"position", position.owner.as_ref(), position.position_id.to_le_bytes().as_ref()],
+        bump = position.bump
+    )]
     pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    /// Quota the pending open holds, see `Position::pending_payer`
+    #[account(
+        mut,
+        seeds = [b"payer_quota", position.pending_payer.as_ref()],
+        bump = payer_quota.bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+    /// Owner's registered key as of the queue, see `Position::receipt_key_epoch`
+    #[account(seeds = [b"encryption_key", position.owner.as_ref()], bump)]
+    pub encryption_key: Option<Account<'info, EncryptionKey>>,
 }
 
 #[derive(Accounts)]
@@ -2529,6 +5683,27 @@ pub struct OpenPositionPublic<'info> {
     
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>,
+
+    /// Optional trade history for the owner, see init_trade_log
+    #[account(
+        mut,
+        has_one = owner
+    )]
+    pub trade_log: Option<Box<Account<'info, TradeLog>>>,
+
+    /// Required while `custody.allowlist_root` is set
+    #[account(
+        seeds = [b"allowlist_entry", custody.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+
+    /// Owner's market maker registration, see `set_market_maker`
+    #[account(
+        seeds = [b"market_maker", owner.key().as_ref()],
+        bump
+    )]
+    pub market_maker: Option<Account<'info, MarketMaker>>,
 }
 
 #[init_computation_definition_accounts("calculate_position_value", payer)]
@@ -2546,6 +5721,14 @@ pub struct InitCalculatePositionValueCompDef<'info> {
     pub comp_def_account: UncheckedAccount<'info>,
     pub arcium_program: Program<'info, Arcium>,
     pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
 }
 
 #[queue_computation_accounts("calculate_position_value", payer)]
@@ -2586,7 +5769,7 @@ pub struct CalculatePositionValue<'info> {
     /// CHECK: computation_account, checked by the arcium program.
     pub computation_account: UncheckedAccount<'info>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(
@@ -2611,6 +5794,26 @@ pub struct CalculatePositionValue<'info> {
         bump = position.bump,
     )]
     pub position: Account<'info, Position>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    /// Supplies the risk manager key, see `Custody::risk_report_pubkey`
+    #[account(address = position.collateral_custody @ ErrorCode::InvalidInput)]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
 #[callback_accounts("calculate_position_value")]
@@ -2618,7 +5821,7 @@ pub struct CalculatePositionValue<'info> {
 pub struct CalculatePositionValueCallback<'info> {
     pub arcium_program: Program<'info, Arcium>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(address = derive_mxe_pda!())]
@@ -2632,11 +5835,16 @@ pub struct CalculatePositionValueCallback<'info> {
     pub instructions_sysvar: AccountInfo<'info>,
     #[account(mut)]
     pub position: Account<'info, Position>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
-#[init_computation_definition_accounts("close_position", payer)]
+#[init_computation_definition_accounts("calculate_position_value_batch", payer)]
 #[derive(Accounts)]
-pub struct InitClosePositionCompDef<'info> {
+pub struct InitCalculatePositionValueBatchCompDef<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     #[account(
@@ -2649,14 +5857,20 @@ pub struct InitClosePositionCompDef<'info> {
     pub comp_def_account: UncheckedAccount<'info>,
     pub arcium_program: Program<'info, Arcium>,
     pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
 }
 
-#[queue_computation_accounts("close_position", payer)]
+#[queue_computation_accounts("calculate_position_value_batch", payer)]
 #[derive(Accounts)]
-#[instruction(computation_offset: u64, _position_id: u64)]
-pub struct ClosePosition<'info> {
-    #[account(mut)]
-    pub owner: Signer<'info>,
+#[instruction(computation_offset: u64)]
+pub struct CalculatePositionValueBatch<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     #[account(
@@ -2691,7 +5905,7 @@ pub struct ClosePosition<'info> {
     /// CHECK: computation_account, checked by the arcium program.
     pub computation_account: UncheckedAccount<'info>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_CLOSE_POSITION)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE_BATCH))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(
@@ -2710,20 +5924,34 @@ pub struct ClosePosition<'info> {
     pub clock_account: Account<'info, ClockAccount>,
     pub system_program: Program<'info, System>,
     pub arcium_program: Program<'info, Arcium>,
+    /// Custody shared by every position in the batch, supplies the risk
+    /// manager key, see `Custody::risk_report_pubkey`
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
     #[account(
-        mut,
-        seeds = [b"position", owner.key().as_ref(), _position_id.to_le_bytes().as_ref()],
-        bump = position.bump,
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
     )]
-    pub position: Account<'info, Position>,
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
-#[callback_accounts("close_position")]
+#[callback_accounts("calculate_position_value_batch")]
 #[derive(Accounts)]
-pub struct ClosePositionCallback<'info> {
+pub struct CalculatePositionValueBatchCallback<'info> {
     pub arcium_program: Program<'info, Arcium>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_CLOSE_POSITION)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_CALCULATE_POSITION_VALUE_BATCH))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(address = derive_mxe_pda!())]
@@ -2735,13 +5963,16 @@ pub struct ClosePositionCallback<'info> {
     #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
     /// CHECK: instructions_sysvar
     pub instructions_sysvar: AccountInfo<'info>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
     #[account(mut)]
-    pub position: Account<'info, Position>,
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
-#[init_computation_definition_accounts("add_collateral", payer)]
+#[init_computation_definition_accounts("request_position_summary", payer)]
 #[derive(Accounts)]
-pub struct InitAddCollateralCompDef<'info> {
+pub struct InitRequestPositionSummaryCompDef<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     #[account(
@@ -2754,14 +5985,20 @@ pub struct InitAddCollateralCompDef<'info> {
     pub comp_def_account: UncheckedAccount<'info>,
     pub arcium_program: Program<'info, Arcium>,
     pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
 }
 
-#[queue_computation_accounts("add_collateral", payer)]
+#[queue_computation_accounts("request_position_summary", payer)]
 #[derive(Accounts)]
 #[instruction(computation_offset: u64, _position_id: u64)]
-pub struct AddCollateral<'info> {
-    #[account(mut)]
-    pub owner: Signer<'info>,
+pub struct RequestPositionSummary<'info> {
     #[account(mut)]
     pub payer: Signer<'info>,
     #[account(
@@ -2796,7 +6033,7 @@ pub struct AddCollateral<'info> {
     /// CHECK: computation_account, checked by the arcium program.
     pub computation_account: UncheckedAccount<'info>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_ADD_COLLATERAL)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_REQUEST_POSITION_SUMMARY))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(
@@ -2815,20 +6052,48 @@ pub struct AddCollateral<'info> {
     pub clock_account: Account<'info, ClockAccount>,
     pub system_program: Program<'info, System>,
     pub arcium_program: Program<'info, Arcium>,
+    /// Only the owner may choose the key the summary is encrypted to
     #[account(
         mut,
-        seeds = [b"position", owner.key().as_ref(), _position_id.to_le_bytes().as_ref()],
+        seeds = [b"position", position.owner.as_ref(), _position_id.to_le_bytes().as_ref()],
         bump = position.bump,
+        constraint = position.owner == payer.key() @ ErrorCode::InvalidPositionOwner,
     )]
     pub position: Account<'info, Position>,
+    /// Market custody whose oracle values the position. Positions don't
+    /// record their market, so the summary is only as good as this choice;
+    /// it moves no funds.
+    pub custody: Box<Account<'info, Custody>>,
+    /// CHECK: oracle account for the position's custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
-#[callback_accounts("add_collateral")]
+#[callback_accounts("request_position_summary")]
 #[derive(Accounts)]
-pub struct AddCollateralCallback<'info> {
+pub struct RequestPositionSummaryCallback<'info> {
     pub arcium_program: Program<'info, Arcium>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_ADD_COLLATERAL)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_REQUEST_POSITION_SUMMARY))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(address = derive_mxe_pda!())]
@@ -2840,308 +6105,207 @@ pub struct AddCollateralCallback<'info> {
     #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
     /// CHECK: instructions_sysvar
     pub instructions_sysvar: AccountInfo<'info>,
-    #[account(mut)]
     pub position: Account<'info, Position>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
+#[init_computation_definition_accounts("close_position", payer)]
 #[derive(Accounts)]
-#[instruction(position_id: u64)]
-pub struct AddCollateralPublic<'info> {
+pub struct InitClosePositionCompDef<'info> {
     #[account(mut)]
-    pub owner: Signer<'info>,
-    
+    pub payer: Signer<'info>,
     #[account(
         mut,
-        constraint = funding_account.mint == collateral_custody.mint,
-        has_one = owner
+        address = derive_mxe_pda!()
     )]
-    pub funding_account: Box<Account<'info, TokenAccount>>,
-    
-    /// CHECK: Transfer authority PDA
+    pub mxe_account: Box<Account<'info, MXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
     #[account(
-        seeds = [b"transfer_authority"],
-        bump = perpetuals.transfer_authority_bump
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
     )]
-    pub transfer_authority: AccountInfo<'info>,
-    
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
+}
+
+#[queue_computation_accounts("close_position", payer)]
+#[derive(Accounts)]
+#[instruction(computation_offset: u64, _position_id: u64)]
+pub struct ClosePosition<'info> {
+    /// CHECK: position owner, tied to the position through its seeds
+    pub owner: UncheckedAccount<'info>,
+    /// Owner or the position's close delegate
     #[account(
-        seeds = [b"perpetuals"],
-        bump = perpetuals.perpetuals_bump
+        constraint = authority.key() == position.owner
+            || (position.close_delegate != Pubkey::default()
+                && authority.key() == position.close_delegate) @ ErrorCode::InvalidPositionOwner
     )]
-    pub perpetuals: Box<Account<'info, Perpetuals>>,
-    
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
     #[account(
-        mut,
-        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
-        bump = pool.bump
+        init_if_needed,
+        space = 9,
+        payer = payer,
+        seeds = [&SIGN_PDA_SEED],
+        bump,
+        address = derive_sign_pda!(),
     )]
-    pub pool: Box<Account<'info, Pool>>,
-    
+    pub sign_pda_account: Account<'info, SignerAccount>,
     #[account(
-        mut,
-        has_one = owner,
-        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
-        bump = position.bump
+        address = derive_mxe_pda!()
     )]
-    pub position: Account<'info, Position>,
-    
+    pub mxe_account: Account<'info, MXEAccount>,
     #[account(
         mut,
-        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
-        bump = custody.bump
-    )]
-    pub custody: Box<Account<'info, Custody>>,
-    
-    /// CHECK: oracle account for the position token
-    #[account(
-        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+        address = derive_mempool_pda!(mxe_account, ErrorCode::ClusterNotSet)
     )]
-    pub custody_oracle_account: AccountInfo<'info>,
-    
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
     #[account(
         mut,
-        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
-        bump = collateral_custody.bump
-    )]
-    pub collateral_custody: Box<Account<'info, Custody>>,
-    
-    /// CHECK: oracle account for the collateral token
-    #[account(
-        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+        address = derive_execpool_pda!(mxe_account, ErrorCode::ClusterNotSet)
     )]
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
-    
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
     #[account(
         mut,
-        seeds = [b"custody_token_account",
-                 pool.key().as_ref(),
-                 collateral_custody.mint.as_ref()],
-        bump = collateral_custody.token_account_bump
+        address = derive_comp_pda!(computation_offset, mxe_account, ErrorCode::ClusterNotSet)
     )]
-    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
-    
-    pub token_program: Program<'info, Token>,
-}
-
-/// Public accounts context for closing a position without Arcium.
-#[derive(Accounts)]
-#[instruction(position_id: u64)]
-pub struct ClosePositionPublic<'info> {
-    #[account(mut)]
-    pub owner: Signer<'info>,
-
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
     #[account(
-        seeds = [b"perpetuals"],
-        bump = perpetuals.perpetuals_bump
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_CLOSE_POSITION))
     )]
-    pub perpetuals: Box<Account<'info, Perpetuals>>,
-
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(
         mut,
-        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
-        bump = pool.bump
+        address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet)
     )]
-    pub pool: Box<Account<'info, Pool>>,
-
+    pub cluster_account: Account<'info, Cluster>,
     #[account(
         mut,
-        has_one = owner,
-        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
-        bump = position.bump
+        address = ARCIUM_FEE_POOL_ACCOUNT_ADDRESS,
     )]
-    pub position: Account<'info, Position>,
-
+    pub pool_account: Account<'info, FeePool>,
     #[account(
-        mut,
-        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
-        bump = custody.bump
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS,
     )]
-    pub custody: Box<Account<'info, Custody>>,
-
-    /// CHECK: oracle account for the position token
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
     #[account(
-        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+        mut,
+        seeds = [b"position", owner.key().as_ref(), _position_id.to_le_bytes().as_ref()],
+        bump = position.bump,
     )]
-    pub custody_oracle_account: AccountInfo<'info>,
-
+    pub position: Account<'info, Position>,
+    /// Custody holding the position's locked notional
     #[account(
         mut,
-        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
-        bump = collateral_custody.bump
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
     )]
     pub collateral_custody: Box<Account<'info, Custody>>,
-
-    /// CHECK: oracle account for the collateral token
+    /// CHECK: oracle account for the collateral token, prices the min hold check
     #[account(
         constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
     )]
     pub collateral_custody_oracle_account: AccountInfo<'info>,
-}
-
-/// Public accounts context for removing collateral without Arcium.
-#[derive(Accounts)]
-#[instruction(position_id: u64)]
-pub struct RemoveCollateralPublic<'info> {
-    #[account(mut)]
-    pub owner: Signer<'info>,
-
+    /// Last good price for the stale-oracle fallback, see refresh_price_cache
     #[account(
-        mut,
-        constraint = funding_account.mint == collateral_custody.mint,
-        has_one = owner
+        seeds = [b"price_cache", collateral_custody.key().as_ref()],
+        bump = price_cache.bump
     )]
-    pub funding_account: Box<Account<'info, TokenAccount>>,
-
+    pub price_cache: Option<Account<'info, PriceCache>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
     #[account(
         seeds = [b"perpetuals"],
         bump = perpetuals.perpetuals_bump
     )]
     pub perpetuals: Box<Account<'info, Perpetuals>>,
-
+    /// Payer's outstanding computations, released by the callback
     #[account(
-        mut,
-        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
-        bump = pool.bump
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
     )]
-    pub pool: Box<Account<'info, Pool>>,
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
 
+#[callback_accounts("close_position")]
+#[derive(Accounts)]
+pub struct ClosePositionCallback<'info> {
+    pub arcium_program: Program<'info, Arcium>,
     #[account(
-        mut,
-        has_one = owner,
-        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
-        bump = position.bump
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_CLOSE_POSITION))
     )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = derive_mxe_pda!())]
+    pub mxe_account: Account<'info, MXEAccount>,
+    /// CHECK: computation_account, checked by arcium program
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet))]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar
+    pub instructions_sysvar: AccountInfo<'info>,
+    #[account(mut)]
     pub position: Account<'info, Position>,
-
     #[account(
         mut,
-        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
-        bump = custody.bump
-    )]
-    pub custody: Box<Account<'info, Custody>>,
-
-    /// CHECK: oracle account for the position token
-    #[account(
-        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
     )]
-    pub custody_oracle_account: AccountInfo<'info>,
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
 
+#[init_computation_definition_accounts("add_collateral", payer)]
+#[derive(Accounts)]
+pub struct InitAddCollateralCompDef<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
     #[account(
         mut,
-        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
-        bump = collateral_custody.bump
+        address = derive_mxe_pda!()
     )]
-    pub collateral_custody: Box<Account<'info, Custody>>,
-
-    /// CHECK: oracle account for the collateral token
+    pub mxe_account: Box<Account<'info, MXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
     #[account(
-        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
     )]
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
+}
 
-    #[account(
-        mut,
-        seeds = [b"custody_token_account",
-                 pool.key().as_ref(),
-                 collateral_custody.mint.as_ref()],
-        bump = collateral_custody.token_account_bump
-    )]
-    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
-
-    pub token_program: Program<'info, Token>,
-}
-
-/// Public accounts context for liquidating a position without Arcium.
-#[derive(Accounts)]
-#[instruction(position_id: u64)]
-pub struct LiquidatePublic<'info> {
-    #[account(mut)]
-    pub owner: Signer<'info>,
-
-    /// Liquidator who triggers this public liquidation
-    #[account(mut)]
-    pub liquidator: Signer<'info>,
-
-    #[account(
-        seeds = [b"perpetuals"],
-        bump = perpetuals.perpetuals_bump
-    )]
-    pub perpetuals: Box<Account<'info, Perpetuals>>,
-
-    #[account(
-        mut,
-        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
-        bump = pool.bump
-    )]
-    pub pool: Box<Account<'info, Pool>>,
-
-    #[account(
-        mut,
-        has_one = owner,
-        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
-        bump = position.bump
-    )]
-    pub position: Account<'info, Position>,
-
-    #[account(
-        mut,
-        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
-        bump = custody.bump
-    )]
-    pub custody: Box<Account<'info, Custody>>,
-
-    /// CHECK: oracle account for the position token
-    #[account(
-        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
-    )]
-    pub custody_oracle_account: AccountInfo<'info>,
-
-    #[account(
-        mut,
-        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
-        bump = collateral_custody.bump
-    )]
-    pub collateral_custody: Box<Account<'info, Custody>>,
-
-    /// CHECK: oracle account for the collateral token
-    #[account(
-        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
-    )]
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
-
-    #[account(
-        mut,
-        seeds = [b"custody_token_account",
-                 pool.key().as_ref(),
-                 collateral_custody.mint.as_ref()],
-        bump = collateral_custody.token_account_bump
-    )]
-    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
-
-    pub token_program: Program<'info, Token>,
-}
-
-#[init_computation_definition_accounts("remove_collateral", payer)]
-#[derive(Accounts)]
-pub struct InitRemoveCollateralCompDef<'info> {
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    #[account(
-        mut,
-        address = derive_mxe_pda!()
-    )]
-    pub mxe_account: Box<Account<'info, MXEAccount>>,
-    #[account(mut)]
-    /// CHECK: comp_def_account, checked by arcium program.
-    pub comp_def_account: UncheckedAccount<'info>,
-    pub arcium_program: Program<'info, Arcium>,
-    pub system_program: Program<'info, System>,
-}
-
-#[queue_computation_accounts("remove_collateral", payer)]
+#[queue_computation_accounts("add_collateral", payer)]
 #[derive(Accounts)]
 #[instruction(computation_offset: u64, _position_id: u64)]
-pub struct RemoveCollateral<'info> {
+pub struct AddCollateral<'info> {
     #[account(mut)]
     pub owner: Signer<'info>,
     #[account(mut)]
@@ -3178,7 +6342,7 @@ pub struct RemoveCollateral<'info> {
     /// CHECK: computation_account, checked by the arcium program.
     pub computation_account: UncheckedAccount<'info>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_REMOVE_COLLATERAL)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_ADD_COLLATERAL))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(
@@ -3203,14 +6367,37 @@ pub struct RemoveCollateral<'info> {
         bump = position.bump,
     )]
     pub position: Account<'info, Position>,
+    /// Custody whose cumulative interest the position is snapshotted against
+    #[account(
+        mut,
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
-#[callback_accounts("remove_collateral")]
+#[callback_accounts("add_collateral")]
 #[derive(Accounts)]
-pub struct RemoveCollateralCallback<'info> {
+pub struct AddCollateralCallback<'info> {
     pub arcium_program: Program<'info, Arcium>,
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_REMOVE_COLLATERAL)
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_ADD_COLLATERAL))
     )]
     pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
     #[account(address = derive_mxe_pda!())]
@@ -3224,823 +6411,3047 @@ pub struct RemoveCollateralCallback<'info> {
     pub instructions_sysvar: AccountInfo<'info>,
     #[account(mut)]
     pub position: Account<'info, Position>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
 }
 
-#[init_computation_definition_accounts("liquidate", payer)]
 #[derive(Accounts)]
-pub struct InitLiquidateCompDef<'info> {
+#[instruction(position_id: u64)]
+pub struct AddCollateralPublic<'info> {
     #[account(mut)]
-    pub payer: Signer<'info>,
+    pub owner: Signer<'info>,
+    
     #[account(
         mut,
-        address = derive_mxe_pda!()
+        constraint = funding_account.mint == collateral_custody.mint,
+        has_one = owner
     )]
-    pub mxe_account: Box<Account<'info, MXEAccount>>,
-    #[account(mut)]
-    /// CHECK: comp_def_account, checked by arcium program.
-    pub comp_def_account: UncheckedAccount<'info>,
-    pub arcium_program: Program<'info, Arcium>,
-    pub system_program: Program<'info, System>,
-}
-
-#[queue_computation_accounts("liquidate", payer)]
-#[derive(Accounts)]
-#[instruction(computation_offset: u64, _position_id: u64)]
-pub struct Liquidate<'info> {
-    /// The liquidator (can be anyone)
-    #[account(mut)]
-    pub liquidator: Signer<'info>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
+    pub funding_account: Box<Account<'info, TokenAccount>>,
+    
+    /// CHECK: Transfer authority PDA
     #[account(
-        init_if_needed,
-        space = 9,
-        payer = payer,
-        seeds = [&SIGN_PDA_SEED],
-        bump,
-        address = derive_sign_pda!(),
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
     )]
-    pub sign_pda_account: Account<'info, SignerAccount>,
+    pub transfer_authority: AccountInfo<'info>,
+    
     #[account(
-        address = derive_mxe_pda!()
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
     )]
-    pub mxe_account: Account<'info, MXEAccount>,
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    
     #[account(
         mut,
-        address = derive_mempool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
+        bump = pool.bump
     )]
-    /// CHECK: mempool_account, checked by the arcium program.
-    pub mempool_account: UncheckedAccount<'info>,
+    pub pool: Box<Account<'info, Pool>>,
+    
     #[account(
         mut,
-        address = derive_execpool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+        has_one = owner,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump = position.bump
     )]
-    /// CHECK: executing_pool, checked by the arcium program.
-    pub executing_pool: UncheckedAccount<'info>,
+    pub position: Account<'info, Position>,
+    
     #[account(
         mut,
-        address = derive_comp_pda!(computation_offset, mxe_account, ErrorCode::ClusterNotSet)
+        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
+        bump = custody.bump
     )]
-    /// CHECK: computation_account, checked by the arcium program.
-    pub computation_account: UncheckedAccount<'info>,
+    pub custody: Box<Account<'info, Custody>>,
+    
+    /// CHECK: oracle account for the position token
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_LIQUIDATE)
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
     )]
-    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    pub custody_oracle_account: AccountInfo<'info>,
+    
     #[account(
         mut,
-        address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet)
+        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
+        bump = collateral_custody.bump
     )]
-    pub cluster_account: Account<'info, Cluster>,
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    
+    /// CHECK: oracle account for the collateral token
     #[account(
-        mut,
-        address = ARCIUM_FEE_POOL_ACCOUNT_ADDRESS,
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
     )]
-    pub pool_account: Account<'info, FeePool>,
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+    
     #[account(
-        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS,
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.token_account_bump
     )]
-    pub clock_account: Account<'info, ClockAccount>,
-    pub system_program: Program<'info, System>,
-    pub arcium_program: Program<'info, Arcium>,
+    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
+    
+    pub token_program: Program<'info, Token>,
+
+    /// Last good price for the stale-oracle fallback, see refresh_price_cache
     #[account(
-        mut,
-        seeds = [b"position", position.owner.as_ref(), _position_id.to_le_bytes().as_ref()],
-        bump = position.bump,
+        seeds = [b"price_cache", custody.key().as_ref()],
+        bump = price_cache.bump
     )]
-    pub position: Account<'info, Position>,
+    pub price_cache: Option<Account<'info, PriceCache>>,
 }
 
-#[callback_accounts("liquidate")]
+/// Public accounts context for closing a position without Arcium.
 #[derive(Accounts)]
-pub struct LiquidateCallback<'info> {
-    pub arcium_program: Program<'info, Arcium>,
+#[instruction(position_id: u64)]
+pub struct ClosePositionPublic<'info> {
+    /// CHECK: position owner, tied to the position through its seeds
+    pub owner: UncheckedAccount<'info>,
+    /// Owner or the position's close delegate
     #[account(
-        address = derive_comp_def_pda!(COMP_DEF_OFFSET_LIQUIDATE)
+        constraint = authority.key() == position.owner
+            || (position.close_delegate != Pubkey::default()
+                && authority.key() == position.close_delegate) @ ErrorCode::InvalidPositionOwner
     )]
-    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
-    #[account(address = derive_mxe_pda!())]
-    pub mxe_account: Account<'info, MXEAccount>,
-    /// CHECK: computation_account, checked by arcium program
-    pub computation_account: UncheckedAccount<'info>,
-    #[account(address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet))]
-    pub cluster_account: Account<'info, Cluster>,
-    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
-    /// CHECK: instructions_sysvar
-    pub instructions_sysvar: AccountInfo<'info>,
-    #[account(mut)]
-    pub position: Account<'info, Position>,
-}
+    pub authority: Signer<'info>,
 
-#[account]
-#[derive(InitSpace)]
-pub struct Position {
-    pub owner: Pubkey,
-    pub position_id: u64,
-    pub side: PositionSide,
-    pub size_usd_encrypted: [u8; 32],
-    pub collateral_usd_encrypted: [u8; 32],
-    pub entry_price: u64,
-    pub open_time: i64,
-    pub update_time: i64,
-    pub owner_enc_pubkey: [u8; 32],
-    pub size_nonce: u128,
-    pub collateral_nonce: u128,
-    pub liquidator: Pubkey,
-    pub bump: u8,
-}
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
 
-#[repr(u8)]
-#[derive(InitSpace, AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
-pub enum PositionSide {
-    Long = 0,
-    Short = 1,
-}
+    #[account(
+        mut,
+        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
 
-#[event]
-pub struct PositionOpenedEvent {
-    pub position_id: u64,
-    pub owner: Pubkey,
-    pub side: PositionSide,
-    pub entry_price: u64,
-    pub size_encrypted: [u8; 32],
-    pub size_nonce: u128,
-    pub collateral_encrypted: [u8; 32],
-    pub collateral_nonce: u128,
-}
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, Position>,
 
-#[event]
-pub struct PositionValueCalculatedEvent {
-    pub position_id: u64,
-    pub current_value_encrypted: [u8; 32],
-    pub pnl_encrypted: [u8; 32],
-    pub value_nonce: u128,
-}
+    #[account(
+        mut,
+        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
 
-#[event]
-pub struct PositionClosedEvent {
-    pub position_id: u64,
-    pub owner: Pubkey,
-    pub realized_pnl_encrypted: [u8; 32],
-    pub final_balance_encrypted: [u8; 32],
-    pub can_close_encrypted: [u8; 32],
-    pub nonce: u128,
-}
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
 
-#[event]
-pub struct CollateralAddedEvent {
-    pub position_id: u64,
-    pub owner: Pubkey,
-    pub new_collateral_encrypted: [u8; 32],
-    pub new_leverage_encrypted: [u8; 32],
-    pub nonce: u128,
-}
+    #[account(
+        mut,
+        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
+        bump = collateral_custody.bump
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
 
-#[event]
-pub struct CollateralRemovedEvent {
-    pub position_id: u64,
-    pub owner: Pubkey,
-    pub new_collateral_encrypted: [u8; 32],
-    pub removed_amount_encrypted: [u8; 32],
-    pub new_leverage_encrypted: [u8; 32],
-    pub nonce: u128,
-}
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
 
-#[event]
-pub struct PositionLiquidatedEvent {
-    pub position_id: u64,
-    pub owner: Pubkey,
-    pub liquidator: Pubkey,
-    pub is_liquidatable_encrypted: [u8; 32],
-    pub remaining_collateral_encrypted: [u8; 32],
-    pub penalty_encrypted: [u8; 32],
-    pub nonce: u128,
-}
+    /// Optional trade history for the owner, see init_trade_log
+    #[account(
+        mut,
+        has_one = owner
+    )]
+    pub trade_log: Option<Box<Account<'info, TradeLog>>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetEntryPriceAndFeeParams {
-    pub collateral: u64,
-    pub size: u64,
-    pub side: Side,
+    /// Last good price for the stale-oracle fallback, see refresh_price_cache
+    #[account(
+        seeds = [b"price_cache", custody.key().as_ref()],
+        bump = price_cache.bump
+    )]
+    pub price_cache: Option<Account<'info, PriceCache>>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetExitPriceAndFeeParams {}
-
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetPnlParams {}
+/// Public accounts context for removing collateral without Arcium.
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct RemoveCollateralPublic<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetLiquidationPriceParams {
-    pub add_collateral: u64,
-    pub remove_collateral: u64,
-}
+    #[account(
+        mut,
+        constraint = funding_account.mint == collateral_custody.mint,
+        has_one = owner
+    )]
+    pub funding_account: Box<Account<'info, TokenAccount>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetLiquidationStateParams {}
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetOraclePriceParams {
-    pub ema: bool,
-}
+    #[account(
+        mut,
+        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetSwapAmountAndFeesParams {
-    pub amount_in: u64,
-}
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, Position>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetAddLiquidityAmountAndFeeParams {
-    pub amount_in: u64,
-}
+    #[account(
+        mut,
+        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetRemoveLiquidityAmountAndFeeParams {
-    pub lp_amount_in: u64,
-}
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetAssetsUnderManagementParams {}
+    #[account(
+        mut,
+        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
+        bump = collateral_custody.bump
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct GetLpTokenPriceParams {}
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SwapParams {
-    pub amount_in: u64,
-    pub min_amount_out: u64,
-}
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.token_account_bump
+    )]
+    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct AddLiquidityParams {
-    pub amount_in: u64,
-    pub min_lp_amount_out: u64,
+    pub token_program: Program<'info, Token>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct RemoveLiquidityParams {
-    pub lp_amount_in: u64,
-    pub min_amount_out: u64,
-}
+/// Public accounts context for liquidating a position without Arcium.
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct LiquidatePublic<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct InitParams {
-    pub min_signatures: u8,
-    pub allow_swap: bool,
-    pub allow_add_liquidity: bool,
-    pub allow_remove_liquidity: bool,
-    pub allow_open_position: bool,
-    pub allow_close_position: bool,
-    pub allow_pnl_withdrawal: bool,
-    pub allow_collateral_withdrawal: bool,
-    pub allow_size_change: bool,
-}
+    /// Liquidator who triggers this public liquidation
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct AddPoolParams {
-    pub name: String,
-}
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct RemovePoolParams {}
+    #[account(
+        mut,
+        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Box<Account<'info, Pool>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        seeds = [b"custody", pool.key().as_ref(), custody.mint.as_ref()],
+        bump = custody.bump
+    )]
+    pub custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the position token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody", pool.key().as_ref(), collateral_custody.mint.as_ref()],
+        bump = collateral_custody.bump
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+
+    /// CHECK: oracle account for the collateral token
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 collateral_custody.mint.as_ref()],
+        bump = collateral_custody.token_account_bump
+    )]
+    pub collateral_custody_token_account: Box<Account<'info, TokenAccount>>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// Optional trade history for the owner, see init_trade_log
+    #[account(
+        mut,
+        has_one = owner
+    )]
+    pub trade_log: Option<Box<Account<'info, TradeLog>>>,
+
+    /// Optional per-slot price cache, see refresh_price_cache
+    #[account(
+        seeds = [b"price_cache", custody.key().as_ref()],
+        bump = price_cache.bump
+    )]
+    pub price_cache: Option<Account<'info, PriceCache>>,
+}
+
+#[init_computation_definition_accounts("remove_collateral", payer)]
+#[derive(Accounts)]
+pub struct InitRemoveCollateralCompDef<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Box<Account<'info, MXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
+}
+
+#[queue_computation_accounts("remove_collateral", payer)]
+#[derive(Accounts)]
+#[instruction(computation_offset: u64, _position_id: u64)]
+pub struct RemoveCollateral<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        space = 9,
+        payer = payer,
+        seeds = [&SIGN_PDA_SEED],
+        bump,
+        address = derive_sign_pda!(),
+    )]
+    pub sign_pda_account: Account<'info, SignerAccount>,
+    #[account(
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Account<'info, MXEAccount>,
+    #[account(
+        mut,
+        address = derive_mempool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_execpool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_comp_pda!(computation_offset, mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_REMOVE_COLLATERAL))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(
+        mut,
+        address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(
+        mut,
+        address = ARCIUM_FEE_POOL_ACCOUNT_ADDRESS,
+    )]
+    pub pool_account: Account<'info, FeePool>,
+    #[account(
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS,
+    )]
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        mut,
+        seeds = [b"position", owner.key().as_ref(), _position_id.to_le_bytes().as_ref()],
+        bump = position.bump,
+    )]
+    pub position: Account<'info, Position>,
+    /// Custody whose cumulative interest the position is snapshotted against
+    #[account(
+        mut,
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+#[callback_accounts("remove_collateral")]
+#[derive(Accounts)]
+pub struct RemoveCollateralCallback<'info> {
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_REMOVE_COLLATERAL))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = derive_mxe_pda!())]
+    pub mxe_account: Account<'info, MXEAccount>,
+    /// CHECK: computation_account, checked by arcium program
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet))]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar
+    pub instructions_sysvar: AccountInfo<'info>,
+    #[account(mut)]
+    pub position: Account<'info, Position>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+#[init_computation_definition_accounts("liquidate", payer)]
+#[derive(Accounts)]
+pub struct InitLiquidateCompDef<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Box<Account<'info, MXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
+}
+
+#[queue_computation_accounts("liquidate", payer)]
+#[derive(Accounts)]
+#[instruction(computation_offset: u64, _position_id: u64)]
+pub struct Liquidate<'info> {
+    /// The liquidator (can be anyone)
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        space = 9,
+        payer = payer,
+        seeds = [&SIGN_PDA_SEED],
+        bump,
+        address = derive_sign_pda!(),
+    )]
+    pub sign_pda_account: Account<'info, SignerAccount>,
+    #[account(
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Account<'info, MXEAccount>,
+    #[account(
+        mut,
+        address = derive_mempool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_execpool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_comp_pda!(computation_offset, mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_LIQUIDATE))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(
+        mut,
+        address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(
+        mut,
+        address = ARCIUM_FEE_POOL_ACCOUNT_ADDRESS,
+    )]
+    pub pool_account: Account<'info, FeePool>,
+    #[account(
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS,
+    )]
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        mut,
+        seeds = [b"position", position.owner.as_ref(), _position_id.to_le_bytes().as_ref()],
+        bump = position.bump,
+    )]
+    pub position: Account<'info, Position>,
+    /// Custody holding the position's locked notional
+    #[account(
+        mut,
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+/// Emits with `emit_cpi!`, so `event_authority` and `program` follow
+/// `payer_quota` in the callback accounts, see `event_cpi_callback_accounts`.
+#[callback_accounts("liquidate")]
+#[event_cpi]
+#[derive(Accounts)]
+pub struct LiquidateCallback<'info> {
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_LIQUIDATE))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = derive_mxe_pda!())]
+    pub mxe_account: Account<'info, MXEAccount>,
+    /// CHECK: computation_account, checked by arcium program
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet))]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar
+    pub instructions_sysvar: AccountInfo<'info>,
+    #[account(mut)]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
+    )]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+#[init_computation_definition_accounts("liquidate_batch", payer)]
+#[derive(Accounts)]
+pub struct InitLiquidateBatchCompDef<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Box<Account<'info, MXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
+}
+
+#[queue_computation_accounts("liquidate_batch", payer)]
+#[derive(Accounts)]
+#[instruction(computation_offset: u64)]
+pub struct LiquidateBatch<'info> {
+    /// The liquidator (can be anyone)
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        space = 9,
+        payer = payer,
+        seeds = [&SIGN_PDA_SEED],
+        bump,
+        address = derive_sign_pda!(),
+    )]
+    pub sign_pda_account: Account<'info, SignerAccount>,
+    #[account(
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Account<'info, MXEAccount>,
+    #[account(
+        mut,
+        address = derive_mempool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_execpool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_comp_pda!(computation_offset, mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_LIQUIDATE_BATCH))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(
+        mut,
+        address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(
+        mut,
+        address = ARCIUM_FEE_POOL_ACCOUNT_ADDRESS,
+    )]
+    pub pool_account: Account<'info, FeePool>,
+    #[account(
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS,
+    )]
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
+    /// Custody shared by every position in the batch, see `Position::collateral_custody`
+    #[account(mut)]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+/// Emits with `emit_cpi!`, so `event_authority` and `program` follow
+/// `payer_quota` in the callback accounts, ahead of the positions.
+#[callback_accounts("liquidate_batch")]
+#[event_cpi]
+#[derive(Accounts)]
+pub struct LiquidateBatchCallback<'info> {
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_LIQUIDATE_BATCH))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = derive_mxe_pda!())]
+    pub mxe_account: Account<'info, MXEAccount>,
+    /// CHECK: computation_account, checked by arcium program
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet))]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar
+    pub instructions_sysvar: AccountInfo<'info>,
+    #[account(mut)]
+    pub collateral_custody: Box<Account<'info, Custody>>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+#[init_computation_definition_accounts("transfer_position", payer)]
+#[derive(Accounts)]
+pub struct InitTransferPositionCompDef<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Box<Account<'info, MXEAccount>>,
+    #[account(mut)]
+    /// CHECK: comp_def_account, checked by arcium program.
+    pub comp_def_account: UncheckedAccount<'info>,
+    pub arcium_program: Program<'info, Arcium>,
+    pub system_program: Program<'info, System>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
+}
+
+#[queue_computation_accounts("transfer_position", payer)]
+#[derive(Accounts)]
+#[instruction(computation_offset: u64, _position_id: u64, new_position_id: u64)]
+pub struct TransferPosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// The receiving wallet must co-sign so positions can't be pushed onto
+    /// an unwilling owner.
+    pub new_owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init_if_needed,
+        space = 9,
+        payer = payer,
+        seeds = [&SIGN_PDA_SEED],
+        bump,
+        address = derive_sign_pda!(),
+    )]
+    pub sign_pda_account: Account<'info, SignerAccount>,
+    #[account(
+        address = derive_mxe_pda!()
+    )]
+    pub mxe_account: Account<'info, MXEAccount>,
+    #[account(
+        mut,
+        address = derive_mempool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: mempool_account, checked by the arcium program.
+    pub mempool_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_execpool_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: executing_pool, checked by the arcium program.
+    pub executing_pool: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        address = derive_comp_pda!(computation_offset, mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    /// CHECK: computation_account, checked by the arcium program.
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_TRANSFER_POSITION))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(
+        mut,
+        address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet)
+    )]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(
+        mut,
+        address = ARCIUM_FEE_POOL_ACCOUNT_ADDRESS,
+    )]
+    pub pool_account: Account<'info, FeePool>,
+    #[account(
+        address = ARCIUM_CLOCK_ACCOUNT_ADDRESS,
+    )]
+    pub clock_account: Account<'info, ClockAccount>,
+    pub system_program: Program<'info, System>,
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        mut,
+        seeds = [b"position", owner.key().as_ref(), _position_id.to_le_bytes().as_ref()],
+        bump = position.bump,
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Position::INIT_SPACE,
+        seeds = [b"position", new_owner.key().as_ref(), new_position_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub new_position: Account<'info, Position>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    /// Payer's outstanding computations, released by the callback
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PayerQuota>(),
+        seeds = [b"payer_quota", payer.key().as_ref()],
+        bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+#[callback_accounts("transfer_position")]
+#[derive(Accounts)]
+pub struct TransferPositionCallback<'info> {
+    pub arcium_program: Program<'info, Arcium>,
+    #[account(
+        address = derive_comp_def_pda!(resolve_comp_def_offset(&comp_def_registry, COMP_DEF_OFFSET_TRANSFER_POSITION))
+    )]
+    pub comp_def_account: Account<'info, ComputationDefinitionAccount>,
+    #[account(address = derive_mxe_pda!())]
+    pub mxe_account: Account<'info, MXEAccount>,
+    /// CHECK: computation_account, checked by arcium program
+    pub computation_account: UncheckedAccount<'info>,
+    #[account(address = derive_cluster_pda!(mxe_account, ErrorCode::ClusterNotSet))]
+    pub cluster_account: Account<'info, Cluster>,
+    #[account(address = ::anchor_lang::solana_program::sysvar::instructions::ID)]
+    /// CHECK: instructions_sysvar
+    pub instructions_sysvar: AccountInfo<'info>,
+    #[account(
+        mut,
+        has_one = owner,
+        close = owner,
+        constraint = position.transfer_pending @ ErrorCode::InvalidInput
+    )]
+    pub position: Account<'info, Position>,
+    #[account(
+        mut,
+        constraint = new_position.transfer_pending @ ErrorCode::InvalidInput
+    )]
+    pub new_position: Account<'info, Position>,
+    /// Overrides for re-registered circuits, see `set_comp_def_offset`
+    #[account(seeds = [b"comp_def_registry"], bump)]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    #[account(mut)]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+    /// CHECK: original owner, receives the closed position's rent
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct ReallocPosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: may predate the current Position layout, so it is deserialized
+    /// by hand after being resized
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub position: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ReallocCustody<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: may predate the current Custody layout, so it is deserialized
+    /// by hand after being resized
+    #[account(mut, owner = crate::ID)]
+    pub custody: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct SetCloseDelegate<'info> {
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, Position>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct SetPositionMetadata<'info> {
+    pub owner: Signer<'info>,
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, Position>,
+}
+
+#[derive(Accounts)]
+pub struct SetEncryptionKey<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + std::mem::size_of::<EncryptionKey>(),
+        seeds = [b"encryption_key", owner.key().as_ref()],
+        bump
+    )]
+    pub encryption_key: Account<'info, EncryptionKey>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct ExpirePendingPosition<'info> {
+    /// CHECK: receives the rent, must be the position owner
+    #[account(mut, address = position.owner)]
+    pub owner: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"position", owner.key().as_ref(), position_id.to_le_bytes().as_ref()],
+        bump = position.bump,
+        constraint = position.pending_expiry_slot != 0 @ ErrorCode::PositionNotPending,
+        constraint = !position.transfer_pending @ ErrorCode::PositionTransferPending
+    )]
+    pub position: Account<'info, Position>,
+    /// Quota the pending open still holds, see `Position::pending_payer`
+    #[account(
+        mut,
+        seeds = [b"payer_quota", position.pending_payer.as_ref()],
+        bump = payer_quota.bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelPositionTransfer<'info> {
+    #[account(
+        mut,
+        constraint = position.transfer_pending @ ErrorCode::InvalidInput,
+        constraint = position.pending_expiry_slot == 0 @ ErrorCode::InvalidInput
+    )]
+    pub position: Account<'info, Position>,
+    /// Destination `transfer_position` created for `position`, closed to the
+    /// payer that funded it
+    #[account(
+        mut,
+        close = payer,
+        constraint = new_position.transfer_pending @ ErrorCode::InvalidInput,
+        constraint = new_position.pending_expiry_slot != 0 @ ErrorCode::PositionNotPending,
+        constraint = new_position.last_computation_offset == position.last_computation_offset
+            @ ErrorCode::InvalidInput
+    )]
+    pub new_position: Account<'info, Position>,
+    /// CHECK: receives the rent, must be the payer of the transfer
+    #[account(mut, address = new_position.pending_payer)]
+    pub payer: UncheckedAccount<'info>,
+    /// Quota the pending transfer still holds
+    #[account(
+        mut,
+        seeds = [b"payer_quota", new_position.pending_payer.as_ref()],
+        bump = payer_quota.bump
+    )]
+    pub payer_quota: Box<Account<'info, PayerQuota>>,
+}
+
+#[derive(Accounts)]
+pub struct InitTradeLog<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + TradeLog::INIT_SPACE,
+        seeds = [b"trade_log", owner.key().as_ref()],
+        bump
+    )]
+    pub trade_log: Account<'info, TradeLog>,
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Position {
+    pub owner: Pubkey,
+    pub position_id: u64,
+    pub side: PositionSide,
+    pub size_usd_encrypted: [u8; 32],
+    pub collateral_usd_encrypted: [u8; 32],
+    pub entry_price: u64,
+    pub open_time: i64,
+    pub update_time: i64,
+    pub owner_enc_pubkey: [u8; 32],
+    pub size_nonce: u128,
+    pub collateral_nonce: u128,
+    pub liquidator: Pubkey,
+    pub bump: u8,
+    /// Layout version, see `realloc_position`. New fields must be appended
+    /// after this one so the ciphertext offsets used by the circuits hold.
+    pub version: u8,
+    /// Custody whose `assets.locked` carries this position's notional
+    pub collateral_custody: Pubkey,
+    /// Plaintext upper bound on size locked at open, released on close
+    pub locked_amount: u64,
+    /// Last client-supplied request id accepted for this position, echoed in
+    /// events so wallets can tell whether a timed-out transaction landed
+    pub client_request_id: u64,
+    /// Slot after which an encrypted open or the destination of a transfer
+    /// still waiting on its callback can be expired; zero once the position
+    /// is live
+    pub pending_expiry_slot: u64,
+    /// May close the position on the owner's behalf; default when unset
+    pub close_delegate: Pubkey,
+    /// Collateral custody's `cumulative_interest` when interest was last
+    /// accrued into `accrued_interest_usd`
+    pub cumulative_interest_snapshot: u128,
+    /// Borrow interest owed up to the snapshot, charged at close
+    pub accrued_interest_usd: u64,
+    /// `EncryptionKey::key_epoch` the open receipt was encrypted under, 0
+    /// when it went to the submitting key
+    pub receipt_key_epoch: u64,
+    /// Program that opened the position through `open_position_cpi` for a
+    /// PDA owner; default for positions opened directly
+    pub origin_program: Pubkey,
+    /// Offset of the last computation queued against this position, see
+    /// `record_computation`
+    pub last_computation_offset: u64,
+    /// Opaque tag supplied at open, e.g. a bot's internal order id, echoed in
+    /// the open and close events; zeroes when unset
+    pub client_tag: [u8; 32],
+    /// Owner's private annotation (strategy tag, bot id), encrypted
+    /// client-side to a key only the owner holds; see `set_position_metadata`
+    pub metadata: [u8; POSITION_METADATA_MAX_LEN],
+    pub metadata_len: u8,
+    pub metadata_nonce: u128,
+    /// Set on both sides of a `transfer_position` until its callback lands,
+    /// which closes the source and clears it on the destination, or until
+    /// `cancel_position_transfer` clears it on the source
+    pub transfer_pending: bool,
+    /// Payer whose `PayerQuota` a pending open or transfer holds, released by
+    /// `expire_pending_position` or `cancel_position_transfer` if the callback
+    /// never lands; default once the position is live
+    pub pending_payer: Pubkey,
+    /// `ComputationKind::position_tag` of the computation at
+    /// `last_computation_offset`, 0 when none was recorded
+    pub last_computation_kind: u8,
+}
+
+/// Current `Position` layout version. Accounts created before the `version`
+/// field existed read as 0 once reallocated.
+pub const POSITION_VERSION: u8 = 15;
+
+pub const POSITION_METADATA_MAX_LEN: usize = 128;
+
+impl Position {
+    /// Rejects changes to either side of a pending `transfer_position`
+    pub fn require_not_transferring(&self) -> Result<()> {
+        require!(!self.transfer_pending, ErrorCode::PositionTransferPending);
+        Ok(())
+    }
+
+    /// Rejects reusing the offset of the previous computation on this
+    /// position, whose computation account may still be in flight, and
+    /// queueing against a position that is being transferred
+    pub fn record_computation(
+        &mut self,
+        computation_offset: u64,
+        kind: ComputationKind,
+    ) -> Result<()> {
+        self.require_not_transferring()?;
+        require!(
+            computation_offset != self.last_computation_offset,
+            ErrorCode::ComputationOffsetReused
+        );
+        self.last_computation_offset = computation_offset;
+        self.last_computation_kind = kind.position_tag();
+        Ok(())
+    }
+
+    /// Interest owed as of `cumulative_interest`, charged on the plaintext
+    /// `locked_amount` since that is the only size every flow can see.
+    pub fn pending_interest(&self, cumulative_interest: u128) -> Result<u64> {
+        let delta = cumulative_interest.saturating_sub(self.cumulative_interest_snapshot);
+        let interest = (self.locked_amount as u128)
+            .checked_mul(delta)
+            .ok_or(ErrorCode::MathOverflow)?
+            / INTEREST_RATE_DIVISOR;
+        let total = interest
+            .checked_add(self.accrued_interest_usd as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(u64::try_from(total).map_err(|_| ErrorCode::MathOverflow)?)
+    }
+
+    /// Moves interest owed so far into `accrued_interest_usd` and re-snapshots.
+    pub fn accrue_interest(&mut self, cumulative_interest: u128) -> Result<()> {
+        self.accrued_interest_usd = self.pending_interest(cumulative_interest)?;
+        self.cumulative_interest_snapshot = cumulative_interest;
+        Ok(())
+    }
+}
+
+#[repr(u8)]
+#[derive(InitSpace, AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PositionSide {
+    Long = 0,
+    Short = 1,
+}
+
+pub const TRADE_LOG_CAPACITY: usize = 32;
+
+/// Per-owner ring buffer of recent position events, kept so portfolio UIs
+/// can show history without an external indexer. Only the public flows
+/// record entries; encrypted opens, closes and liquidations settle in
+/// callbacks that don't carry the log and are left to their events.
+#[account]
+#[derive(InitSpace)]
+pub struct TradeLog {
+    pub owner: Pubkey,
+    /// Index the next entry will be written to
+    pub head: u16,
+    pub len: u16,
+    pub entries: [TradeLogEntry; TRADE_LOG_CAPACITY],
+    pub bump: u8,
+}
+
+impl TradeLog {
+    pub fn record(&mut self, entry: TradeLogEntry) {
+        self.entries[self.head as usize] = entry;
+        self.head = ((self.head as usize + 1) % TRADE_LOG_CAPACITY) as u16;
+        if (self.len as usize) < TRADE_LOG_CAPACITY {
+            self.len += 1;
+        }
+    }
+}
+
+#[derive(InitSpace, AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct TradeLogEntry {
+    pub position_id: u64,
+    pub action: TradeAction,
+    pub timestamp: i64,
+    /// Open fee or accrued interest charged; zero for liquidations, which
+    /// forfeit the collateral without a separate fee
+    pub fee_usd: u64,
+    /// Realized PnL ciphertext as emitted with the close/liquidation event
+    pub pnl_encrypted: [u8; 32],
+    pub pnl_nonce: u128,
+}
+
+#[repr(u8)]
+#[derive(InitSpace, AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TradeAction {
+    Open = 0,
+    Close = 1,
+    Liquidation = 2,
+}
+
+#[event]
+pub struct PositionOpenedEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub side: PositionSide,
+    pub entry_price: u64,
+    pub size_encrypted: [u8; 32],
+    pub size_nonce: u128,
+    pub collateral_encrypted: [u8; 32],
+    pub collateral_nonce: u128,
+    pub client_request_id: u64,
+    /// Opaque client-supplied tag, see `Position::client_tag`
+    pub client_tag: [u8; 32],
+    /// Size and collateral re-encrypted to the owner's latest registered key
+    pub receipt_enc_pubkey: [u8; 32],
+    pub receipt_size_encrypted: [u8; 32],
+    pub receipt_collateral_encrypted: [u8; 32],
+    pub receipt_nonce: u128,
+    pub key_epoch: u64,
+}
+
+#[event]
+pub struct PositionValueCalculatedEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub current_value_encrypted: [u8; 32],
+    pub pnl_encrypted: [u8; 32],
+    pub value_nonce: u128,
+}
+
+/// Output of `request_position_summary`, encrypted to the client key and
+/// nonce the owner passed with the request
+#[event]
+pub struct PositionSummaryEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub size_usd_encrypted: [u8; 32],
+    pub collateral_usd_encrypted: [u8; 32],
+    pub leverage_bps_encrypted: [u8; 32],
+    pub liquidation_price_encrypted: [u8; 32],
+    pub pnl_encrypted: [u8; 32],
+    pub nonce: u128,
+}
+
+/// Position health encrypted to the market's risk manager key, see
+/// `Custody::risk_report_pubkey`
+#[event]
+pub struct PositionRiskReportEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub collateral_custody: Pubkey,
+    pub current_value_encrypted: [u8; 32],
+    pub is_liquidatable_encrypted: [u8; 32],
+    pub nonce: u128,
+}
+
+#[event]
+pub struct PendingPositionExpiredEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub expiry_slot: u64,
+}
+
+#[event]
+pub struct PositionTransferCancelledEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub new_position_id: u64,
+    pub owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub expiry_slot: u64,
+}
+
+#[event]
+pub struct CustodyBalanceMismatchEvent {
+    pub schema_version: u8,
+    pub custody: Pubkey,
+    /// `assets.owned + assets.collateral`
+    pub expected: u64,
+    /// Custody token account balance
+    pub actual: u64,
+    pub halted: bool,
+}
+
+#[event]
+pub struct PositionClosedEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub realized_pnl_encrypted: [u8; 32],
+    pub final_balance_encrypted: [u8; 32],
+    pub can_close_encrypted: [u8; 32],
+    pub nonce: u128,
+    pub client_request_id: u64,
+    pub client_tag: [u8; 32],
+}
+
+#[event]
+pub struct CollateralAddedEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub new_collateral_encrypted: [u8; 32],
+    pub new_leverage_encrypted: [u8; 32],
+    pub nonce: u128,
+}
+
+#[event]
+pub struct CollateralRemovedEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub new_collateral_encrypted: [u8; 32],
+    pub removed_amount_encrypted: [u8; 32],
+    pub new_leverage_encrypted: [u8; 32],
+    pub nonce: u128,
+}
+
+#[event]
+pub struct PositionLiquidatedEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub owner: Pubkey,
+    pub liquidator: Pubkey,
+    pub is_liquidatable_encrypted: [u8; 32],
+    pub remaining_collateral_encrypted: [u8; 32],
+    pub penalty_encrypted: [u8; 32],
+    pub nonce: u128,
+}
+
+#[event]
+pub struct PositionTransferredEvent {
+    pub schema_version: u8,
+    pub position_id: u64,
+    pub new_position_id: u64,
+    pub previous_owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub size_encrypted: [u8; 32],
+    pub size_nonce: u128,
+    pub collateral_encrypted: [u8; 32],
+    pub collateral_nonce: u128,
+}
+
+/// Circuit a computation runs, carried by the queue, completion and failure
+/// events so audits can count each kind queued versus settled.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum ComputationKind {
+    OpenPosition,
+    CalculatePositionValue,
+    CalculatePositionValueBatch,
+    RequestPositionSummary,
+    ClosePosition,
+    AddCollateral,
+    RemoveCollateral,
+    Liquidate,
+    LiquidateBatch,
+    TransferPosition,
+    MixPositions,
+}
+
+impl ComputationKind {
+    /// Name of the encrypted instruction
+    pub fn circuit(self) -> &'static str {
+        match self {
+            Self::OpenPosition => "open_position",
+            Self::CalculatePositionValue => "calculate_position_value",
+            Self::CalculatePositionValueBatch => "calculate_position_value_batch",
+            Self::RequestPositionSummary => "request_position_summary",
+            Self::ClosePosition => "close_position",
+            Self::AddCollateral => "add_collateral",
+            Self::RemoveCollateral => "remove_collateral",
+            Self::Liquidate => "liquidate",
+            Self::LiquidateBatch => "liquidate_batch",
+            Self::TransferPosition => "transfer_position",
+            Self::MixPositions => "mix_positions",
+        }
+    }
+
+    /// Encoding in `Position::last_computation_kind`, which keeps 0 for a
+    /// position with no computation recorded
+    pub fn position_tag(self) -> u8 {
+        self as u8 + 1
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComputationFailureKind {
+    /// The cluster aborted the computation
+    Aborted,
+    /// The computation expired before the cluster produced an output
+    Timeout,
+    /// Inputs or the signed output were rejected
+    InvalidInput,
+    Unknown,
+}
+
+impl ComputationFailureKind {
+    /// Classifies the error returned by `verify_output` by its Arcium error name.
+    fn from_error(err: &Error) -> Self {
+        let Error::AnchorError(anchor_err) = err else {
+            return Self::Unknown;
+        };
+        let name = anchor_err.error_name.as_str();
+        if name.contains("Abort") {
+            Self::Aborted
+        } else if name.contains("Timeout") || name.contains("Expired") {
+            Self::Timeout
+        } else if name.contains("Invalid") {
+            Self::InvalidInput
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+#[event]
+pub struct ComputationQueuedEvent {
+    pub schema_version: u8,
+    pub kind: ComputationKind,
+    pub computation_offset: u64,
+    pub computation_account: Pubkey,
+    pub payer: Pubkey,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ComputationCompletedEvent {
+    pub schema_version: u8,
+    pub kind: ComputationKind,
+    pub computation_account: Pubkey,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ComputationFailedEvent {
+    pub schema_version: u8,
+    pub kind: ComputationKind,
+    /// Name of the encrypted instruction, e.g. `close_position`
+    pub circuit: String,
+    pub comp_def_offset: u32,
+    pub computation_account: Pubkey,
+    pub failure: ComputationFailureKind,
+    pub slot: u64,
+}
+
+#[event]
+pub struct CustodyConfigProposedEvent {
+    pub schema_version: u8,
+    pub custody: Pubkey,
+    pub pricing: PricingParams,
+    pub fees: Fees,
+    pub borrow_rate: BorrowRateParams,
+    pub execute_after: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetEntryPriceAndFeeParams {
+    pub collateral: u64,
+    pub size: u64,
+    pub side: Side,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetExitPriceAndFeeParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetPnlParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetLiquidationPriceParams {
+    pub add_collateral: u64,
+    pub remove_collateral: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetLiquidationStateParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetOraclePriceParams {
+    pub ema: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetSwapAmountAndFeesParams {
+    pub amount_in: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetAddLiquidityAmountAndFeeParams {
+    pub amount_in: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetRemoveLiquidityAmountAndFeeParams {
+    pub lp_amount_in: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetAssetsUnderManagementParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetLpTokenPriceParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct GetCustodyStatsParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SwapParams {
+    pub amount_in: u64,
+    pub min_amount_out: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ReconcileCustodyParams {
+    pub halt_on_shortfall: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SeedPoolParams {
+    pub amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AddLiquidityParams {
+    pub amount_in: u64,
+    pub min_lp_amount_out: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RemoveLiquidityParams {
+    pub lp_amount_in: u64,
+    pub min_amount_out: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct InitParams {
+    pub min_signatures: u8,
+    pub allow_swap: bool,
+    pub allow_add_liquidity: bool,
+    pub allow_remove_liquidity: bool,
+    pub allow_open_position: bool,
+    pub allow_close_position: bool,
+    pub allow_pnl_withdrawal: bool,
+    pub allow_collateral_withdrawal: bool,
+    pub allow_size_change: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AddPoolParams {
+    pub name: String,
+    /// LP token metadata, only used when the metadata accounts are passed
+    pub lp_token_symbol: String,
+    pub lp_token_uri: String,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetLpTokenMetadataParams {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RemovePoolParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AddCustodyParams {
+    pub is_stable: bool,
+    pub is_virtual: bool,
+    pub oracle: OracleParams,
+    pub pricing: PricingParams,
+    pub permissions: Permissions,
+    pub fees: Fees,
+    pub borrow_rate: BorrowRateParams,
+    pub ratios: Vec<TokenRatios>,
+}
+
+impl AddCustodyParams {
+    pub fn into_v2(self) -> AddCustodyParamsV2 {
+        AddCustodyParamsV2 {
+            is_stable: self.is_stable,
+            is_virtual: self.is_virtual,
+            oracle: self.oracle,
+            pricing: self.pricing,
+            permissions: self.permissions,
+            fees: self.fees,
+            borrow_rate: self.borrow_rate,
+            ratios: self.ratios,
+            max_price_drift_bps_per_slot: 0,
+        }
+    }
+}
+
+/// Params of `add_custody_v2`. `add_custody` keeps taking the original
+/// layout, so clients built against it keep working.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AddCustodyParamsV2 {
+    pub is_stable: bool,
+    pub is_virtual: bool,
+    pub oracle: OracleParams,
+    pub pricing: PricingParams,
+    pub permissions: Permissions,
+    pub fees: Fees,
+    pub borrow_rate: BorrowRateParams,
+    pub ratios: Vec<TokenRatios>,
+    pub max_price_drift_bps_per_slot: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RemoveCustodyParams {
+    pub ratios: Vec<TokenRatios>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetCustodyConfigParams {
+    pub is_stable: bool,
+    pub is_virtual: bool,
+    pub oracle: OracleParams,
+    pub pricing: PricingParams,
+    pub permissions: Permissions,
+    pub fees: Fees,
+    pub borrow_rate: BorrowRateParams,
+    pub ratios: Vec<TokenRatios>,
+}
+
+impl SetCustodyConfigParams {
+    /// Fields this layout lacks keep the custody's current values
+    pub fn into_v2(self, max_price_drift_bps_per_slot: u64) -> SetCustodyConfigParamsV2 {
+        SetCustodyConfigParamsV2 {
+            is_stable: self.is_stable,
+            is_virtual: self.is_virtual,
+            oracle: self.oracle,
+            pricing: self.pricing,
+            permissions: self.permissions,
+            fees: self.fees,
+            borrow_rate: self.borrow_rate,
+            ratios: self.ratios,
+            max_price_drift_bps_per_slot,
+        }
+    }
+}
+
+/// Params of `set_custody_config_v2`, see `AddCustodyParamsV2`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetCustodyConfigParamsV2 {
+    pub is_stable: bool,
+    pub is_virtual: bool,
+    pub oracle: OracleParams,
+    pub pricing: PricingParams,
+    pub permissions: Permissions,
+    pub fees: Fees,
+    pub borrow_rate: BorrowRateParams,
+    pub ratios: Vec<TokenRatios>,
+    pub max_price_drift_bps_per_slot: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ProposeCustodyConfigParams {
+    pub pricing: PricingParams,
+    pub fees: Fees,
+    pub borrow_rate: BorrowRateParams,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetConfigTimelockParams {
+    pub timelock_sec: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetTradingScheduleParams {
+    pub schedule: TradingSchedule,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetEncryptionKeyParams {
+    pub enc_pubkey: [u8; 32],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetRiskManagerParams {
+    pub risk_manager_enc_pubkey: [u8; 32],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetMarketMakerParams {
+    pub open_position_fee: u64,
+    pub is_active: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetAllowlistRootParams {
+    pub allowlist_root: [u8; 32],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetPriceFallbackParams {
+    pub max_price_drift_bps_per_slot: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetCustodyFreezeParams {
+    pub deposits_frozen: bool,
+    pub withdrawals_frozen: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetMinHoldPeriodParams {
+    pub min_hold_period_sec: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct JoinAllowlistParams {
+    pub proof: Vec<[u8; 32]>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetGovernanceParams {
+    pub governance_program: Pubkey,
+    pub governance_realm: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ApproveGovernanceChangeParams {
+    pub change_hash: [u8; 32],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetPermissionsParams {
+    pub allow_swap: bool,
+    pub allow_add_liquidity: bool,
+    pub allow_remove_liquidity: bool,
+    pub allow_open_position: bool,
+    pub allow_close_position: bool,
+    pub allow_pnl_withdrawal: bool,
+    pub allow_collateral_withdrawal: bool,
+    pub allow_size_change: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetPriorityFeesParams {
+    pub priority_fees: PriorityFees,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetComputationQuotaParams {
+    pub max_in_flight_computations: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetLiquidationPriorityParams {
+    pub liquidation_priority: LiquidationPriority,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetCompDefOffsetParams {
+    pub default_offset: u32,
+    pub offset: u32,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SetAdminSignersParams {
+    pub min_signatures: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct WithdrawFeesParams {
+    pub amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct WithdrawSolFeesParams {
+    pub amount: u64,
+}
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct AddCustodyParams {
-    pub is_stable: bool,
-    pub is_virtual: bool,
-    pub oracle: OracleParams,
-    pub pricing: PricingParams,
-    pub permissions: Permissions,
-    pub fees: Fees,
-    pub borrow_rate: BorrowRateParams,
-    pub ratios: Vec<TokenRatios>,
+pub struct SetCustomOraclePriceParams {
+    pub price: u64,
+    pub expo: i32,
+    pub conf: u64,
+    pub ema: u64,
+    pub publish_time: i64,
+    pub override_price_band: bool,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct RemoveCustodyParams {
-    pub ratios: Vec<TokenRatios>,
+pub struct SetTestTimeParams {
+    pub time: i64,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SetCustodyConfigParams {
-    pub is_stable: bool,
-    pub is_virtual: bool,
-    pub oracle: OracleParams,
-    pub pricing: PricingParams,
-    pub permissions: Permissions,
-    pub fees: Fees,
-    pub borrow_rate: BorrowRateParams,
-    pub ratios: Vec<TokenRatios>,
+pub struct UpgradeCustodyParams {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct NewPositionPricesAndFee {
+    pub entry_price: u64,
+    pub liquidation_price: u64,
+    pub fee: u64,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SetPermissionsParams {
-    pub allow_swap: bool,
-    pub allow_add_liquidity: bool,
-    pub allow_remove_liquidity: bool,
-    pub allow_open_position: bool,
-    pub allow_close_position: bool,
-    pub allow_pnl_withdrawal: bool,
-    pub allow_collateral_withdrawal: bool,
-    pub allow_size_change: bool,
+pub struct PriceAndFee {
+    pub price: u64,
+    pub fee: u64,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SetAdminSignersParams {
-    pub min_signatures: u8,
+pub struct ProfitAndLoss {
+    pub profit: u64,
+    pub loss: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AmountAndFee {
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SwapAmountAndFees {
+    pub amount_out: u64,
+    pub fee_in: u64,
+    pub fee_out: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PendingInterest {
+    pub interest_usd: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LiquidationPrice {
+    pub liquidation_price: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LiquidationState {
+    pub current_price: u64,
+    pub liquidation_price: u64,
+    pub is_liquidatable: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AssetsUnderManagement {
+    pub aum_usd: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LpTokenPrice {
+    pub price: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct CustodyStats {
+    pub utilization_bps: u64,
+    pub borrow_rate: u64,
+    pub locked: u64,
+    pub owned: u64,
+    pub oi_long_usd: u64,
+    pub oi_short_usd: u64,
+    pub volume_24h_usd: u64,
+    pub fees_24h_usd: u64,
+}
+
+#[derive(Accounts)]
+pub struct GetEntryPriceAndFee<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    pub collateral_custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by collateral custody
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetExitPriceAndFee<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub position: Account<'info, Position>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    pub collateral_custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by collateral custody
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetPendingInterest<'info> {
+    pub position: Account<'info, Position>,
+    #[account(
+        constraint = collateral_custody.key() == position.collateral_custody @ ErrorCode::InvalidInput
+    )]
+    pub collateral_custody: Account<'info, Custody>,
+}
+
+#[derive(Accounts)]
+pub struct GetPnl<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub position: Account<'info, Position>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    pub collateral_custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by collateral custody
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetLiquidationPrice<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub position: Account<'info, Position>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    pub collateral_custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by collateral custody
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetLiquidationState<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub position: Account<'info, Position>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    pub collateral_custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by collateral custody
+    #[account(
+        constraint = collateral_custody_oracle_account.key() == collateral_custody.oracle.oracle_account
+    )]
+    pub collateral_custody_oracle_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetOraclePrice<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetSwapAmountAndFees<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub receiving_custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by receiving custody
+    #[account(
+        constraint = receiving_custody_oracle_account.key() == receiving_custody.oracle.oracle_account
+    )]
+    pub receiving_custody_oracle_account: AccountInfo<'info>,
+    pub dispensing_custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by dispensing custody
+    #[account(
+        constraint = dispensing_custody_oracle_account.key() == dispensing_custody.oracle.oracle_account
+    )]
+    pub dispensing_custody_oracle_account: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetAddLiquidityAmountAndFee<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct GetRemoveLiquidityAmountAndFee<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Oracle account verified by custody
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct GetAssetsUnderManagement<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct WithdrawFeesParams {
-    pub amount: u64,
+#[derive(Accounts)]
+pub struct GetVersion {}
+
+#[derive(Accounts)]
+pub struct GetInitializedCompDefs<'info> {
+    #[account(
+        seeds = [b"comp_def_init_log"],
+        bump = comp_def_init_log.bump
+    )]
+    pub comp_def_init_log: Account<'info, CompDefInitLog>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct WithdrawSolFeesParams {
-    pub amount: u64,
+#[derive(Accounts)]
+pub struct GetPriorityFees<'info> {
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Account<'info, Perpetuals>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SetCustomOraclePriceParams {
-    pub price: u64,
-    pub expo: i32,
-    pub conf: u64,
-    pub ema: u64,
-    pub publish_time: i64,
+#[derive(Accounts)]
+pub struct GetLpTokenPrice<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    #[account(
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SetTestTimeParams {
-    pub time: i64,
+#[derive(Accounts)]
+pub struct RefreshLpPriceOracle<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub pool: Account<'info, Pool>,
+    #[account(
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<LpPriceOracle>(),
+        seeds = [b"lp_price_oracle", pool.key().as_ref()],
+        bump
+    )]
+    pub lp_price_oracle: Account<'info, LpPriceOracle>,
+    pub system_program: Program<'info, System>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct UpgradeCustodyParams {}
+#[derive(Accounts)]
+pub struct ReconcileCustody<'info> {
+    /// Multisig signer, only needed to halt the custody on a shortfall
+    pub admin: Option<Signer<'info>>,
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
+    )]
+    pub multisig: Option<Account<'info, Multisig>>,
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        constraint = custody.pool == pool.key() @ ErrorCode::InvalidInput
+    )]
+    pub custody: Account<'info, Custody>,
+    #[account(
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+}
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct NewPositionPricesAndFee {
-    pub entry_price: u64,
-    pub liquidation_price: u64,
-    pub fee: u64,
+#[derive(Accounts)]
+pub struct RefreshPriceCache<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub custody: Account<'info, Custody>,
+    /// CHECK: oracle account for the custody token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PriceCache>(),
+        seeds = [b"price_cache", custody.key().as_ref()],
+        bump
+    )]
+    pub price_cache: Account<'info, PriceCache>,
+    pub system_program: Program<'info, System>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct PriceAndFee {
-    pub price: u64,
-    pub fee: u64,
+#[derive(Accounts)]
+pub struct GetCustodyStats<'info> {
+    pub perpetuals: Account<'info, Perpetuals>,
+    pub pool: Account<'info, Pool>,
+    pub custody: Account<'info, Custody>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct ProfitAndLoss {
-    pub profit: u64,
-    pub loss: u64,
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(mut)]
+    pub receiving_custody: Account<'info, Custody>,
+    /// CHECK: oracle account for the receiving token
+    #[account(
+        constraint = receiving_custody_oracle_account.key() == receiving_custody.oracle.oracle_account
+    )]
+    pub receiving_custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 receiving_custody.mint.as_ref()],
+        bump = receiving_custody.token_account_bump
+    )]
+    pub receiving_custody_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub dispensing_custody: Account<'info, Custody>,
+    /// CHECK: oracle account for the dispensing token
+    #[account(
+        constraint = dispensing_custody_oracle_account.key() == dispensing_custody.oracle.oracle_account
+    )]
+    pub dispensing_custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 dispensing_custody.mint.as_ref()],
+        bump = dispensing_custody.token_account_bump
+    )]
+    pub dispensing_custody_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = funding_account.mint == receiving_custody.mint,
+        has_one = owner
+    )]
+    pub funding_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = receiving_account.mint == dispensing_custody.mint
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct AmountAndFee {
-    pub amount: u64,
-    pub fee: u64,
+#[derive(Accounts)]
+pub struct SeedPool<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        constraint = custody.pool == pool.key() @ ErrorCode::InvalidInput
+    )]
+    pub custody: Account<'info, Custody>,
+    /// CHECK: oracle account for the seeded token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+    /// LP tokens backing the seed, owned by the transfer authority and
+    /// never moved by the program
+    #[account(
+        init_if_needed,
+        payer = admin,
+        token::mint = lp_token_mint,
+        token::authority = transfer_authority,
+        seeds = [b"lp_treasury", pool.key().as_ref()],
+        bump
+    )]
+    pub lp_treasury_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = funding_account.mint == custody.mint,
+        constraint = funding_account.owner == admin.key()
+    )]
+    pub funding_account: Box<Account<'info, TokenAccount>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct SwapAmountAndFees {
-    pub amount_out: u64,
-    pub fee_in: u64,
-    pub fee_out: u64,
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(mut)]
+    pub custody: Account<'info, Custody>,
+    /// CHECK: oracle account for the receiving token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
+    pub custody_oracle_account: AccountInfo<'info>,
+    /// CHECK: Custody token account - validate as token account for CPI
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+    /// CHECK: Funding account - validate as token account for CPI
+    #[account(
+        mut,
+        constraint = funding_account.mint == custody.mint,
+        has_one = owner
+    )]
+    pub funding_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: LP token account
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == lp_token_mint.key(),
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+    /// Owner's cost basis in this pool
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + std::mem::size_of::<DepositReceipt>(),
+        seeds = [b"deposit_receipt", pool.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub deposit_receipt: Box<Account<'info, DepositReceipt>>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct GetEntryPriceAndFee<'info> {
-    pub perpetuals: Account<'info, Perpetuals>,
+pub struct RemoveLiquidity<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    #[account(mut)]
     pub pool: Account<'info, Pool>,
+    #[account(
+        mut,
+        constraint = custody.pool == pool.key() @ ErrorCode::InvalidInput
+    )]
     pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
+    /// CHECK: oracle account for the receiving token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
     pub custody_oracle_account: AccountInfo<'info>,
-    pub collateral_custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by collateral custody
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
+    /// CHECK: Custody token account - validate as token account for CPI
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+    /// CHECK: LP token account
+    #[account(
+        mut,
+        constraint = lp_token_account.mint == lp_token_mint.key(),
+        has_one = owner
+    )]
+    pub lp_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: Receiving account
+    #[account(
+        mut,
+        constraint = receiving_account.mint == custody.mint,
+        has_one = owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+    /// Owner's cost basis in this pool
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + std::mem::size_of::<DepositReceipt>(),
+        seeds = [b"deposit_receipt", pool.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub deposit_receipt: Box<Account<'info, DepositReceipt>>,
+    /// Required only when free liquidity can't cover the withdrawal; the
+    /// LP it can't redeem is escrowed under this claim
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + std::mem::size_of::<WithdrawalClaim>(),
+        seeds = [b"withdrawal_claim", custody.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_claim: Option<Box<Account<'info, WithdrawalClaim>>>,
+    #[account(
+        init_if_needed,
+        payer = owner,
+        token::mint = lp_token_mint,
+        token::authority = transfer_authority,
+        seeds = [b"withdrawal_escrow", pool.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_escrow: Option<Box<Account<'info, TokenAccount>>>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct GetExitPriceAndFee<'info> {
-    pub perpetuals: Account<'info, Perpetuals>,
+pub struct FillWithdrawalClaim<'info> {
+    /// CHECK: claim owner, refunded the claim's rent once it is filled
+    #[account(mut)]
+    pub owner: AccountInfo<'info>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    #[account(mut)]
     pub pool: Account<'info, Pool>,
-    pub position: Account<'info, Position>,
+    #[account(mut)]
     pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
+    /// CHECK: oracle account for the claimed token
+    #[account(
+        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
+    )]
     pub custody_oracle_account: AccountInfo<'info>,
-    pub collateral_custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by collateral custody
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"withdrawal_escrow", pool.key().as_ref()],
+        bump
+    )]
+    pub withdrawal_escrow: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"withdrawal_claim", custody.key().as_ref(), owner.key().as_ref()],
+        bump = withdrawal_claim.bump,
+        has_one = owner,
+        has_one = pool,
+        has_one = custody
+    )]
+    pub withdrawal_claim: Box<Account<'info, WithdrawalClaim>>,
+    #[account(
+        mut,
+        constraint = receiving_account.mint == custody.mint,
+        has_one = owner
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [b"deposit_receipt", pool.key().as_ref(), owner.key().as_ref()],
+        bump = deposit_receipt.bump
+    )]
+    pub deposit_receipt: Box<Account<'info, DepositReceipt>>,
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct GetPnl<'info> {
+pub struct Init<'info> {
+    #[account(mut)]
+    pub upgrade_authority: Signer<'info>,
+    #[account(
+        init,
+        payer = upgrade_authority,
+        space = 8 + std::mem::size_of::<Multisig>(),
+        seeds = [b"multisig"],
+        bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = upgrade_authority,
+        space = 8 + std::mem::size_of::<Perpetuals>() + 256,
+        seeds = [b"perpetuals"],
+        bump
+    )]
+    pub perpetuals: Account<'info, Perpetuals>,
+    /// Created empty here so every queue and callback resolves its circuit
+    /// through it, see `set_comp_def_offset`
+    #[account(
+        init,
+        payer = upgrade_authority,
+        space = 8 + std::mem::size_of::<CompDefRegistry>(),
+        seeds = [b"comp_def_registry"],
+        bump
+    )]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    /// CHECK: Program data account
+    pub perpetuals_program_data: AccountInfo<'info>,
+    /// CHECK: Perpetuals program
+    pub perpetuals_program: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AddPool<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
     pub perpetuals: Account<'info, Perpetuals>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + std::mem::size_of::<Pool>() + 512,
+        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
+        bump
+    )]
     pub pool: Account<'info, Pool>,
-    pub position: Account<'info, Position>,
-    pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
-    pub custody_oracle_account: AccountInfo<'info>,
-    pub collateral_custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by collateral custody
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        mint::authority = transfer_authority,
+        mint::freeze_authority = transfer_authority,
+        mint::decimals = 6,
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: Rent sysvar
+    pub rent: AccountInfo<'info>,
+    /// CHECK: LP mint metadata, created by the token metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", mpl_token_metadata::ID.as_ref(), lp_token_mint.key().as_ref()],
+        seeds::program = mpl_token_metadata::ID,
+        bump
+    )]
+    pub lp_token_metadata: Option<UncheckedAccount<'info>>,
+    pub metadata_program: Option<Program<'info, Metadata>>,
 }
 
 #[derive(Accounts)]
-pub struct GetLiquidationPrice<'info> {
+pub struct SetLpTokenMetadata<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: Transfer authority PDA, update authority of the metadata
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
     pub perpetuals: Account<'info, Perpetuals>,
     pub pool: Account<'info, Pool>,
-    pub position: Account<'info, Position>,
-    pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
-    pub custody_oracle_account: AccountInfo<'info>,
-    pub collateral_custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by collateral custody
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        seeds = [b"lp_token_mint", pool.key().as_ref()],
+        bump = pool.lp_token_bump
+    )]
+    pub lp_token_mint: Account<'info, Mint>,
+    /// CHECK: LP mint metadata, validated by the token metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", mpl_token_metadata::ID.as_ref(), lp_token_mint.key().as_ref()],
+        seeds::program = mpl_token_metadata::ID,
+        bump
+    )]
+    pub lp_token_metadata: UncheckedAccount<'info>,
+    pub metadata_program: Program<'info, Metadata>,
 }
 
 #[derive(Accounts)]
-pub struct GetLiquidationState<'info> {
+pub struct RemovePool<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: Transfer authority PDA
+    #[account(mut)]
+    pub transfer_authority: AccountInfo<'info>,
     pub perpetuals: Account<'info, Perpetuals>,
+    #[account(
+        mut,
+        close = admin
+    )]
     pub pool: Account<'info, Pool>,
-    pub position: Account<'info, Position>,
-    pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
-    pub custody_oracle_account: AccountInfo<'info>,
-    pub collateral_custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by collateral custody
-    pub collateral_custody_oracle_account: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct GetOraclePrice<'info> {
+pub struct AddCustody<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump
+    )]
+    pub transfer_authority: AccountInfo<'info>,
     pub perpetuals: Account<'info, Perpetuals>,
+    #[account(mut)]
     pub pool: Account<'info, Pool>,
+    #[account(
+        init,
+        payer = admin,
+        space = Custody::SPACE,
+        seeds = [b"custody", pool.key().as_ref(), custody_token_mint.key().as_ref()],
+        bump
+    )]
     pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
-    pub custody_oracle_account: AccountInfo<'info>,
+    /// CHECK: Custody token account PDA
+    #[account(
+        init_if_needed,
+        payer = admin,
+        token::mint = custody_token_mint,
+        token::authority = transfer_authority,
+        seeds = [b"custody_token_account",
+                 pool.key().as_ref(),
+                 custody_token_mint.key().as_ref()],
+        bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: Custody token mint
+    pub custody_token_mint: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    /// CHECK: Rent sysvar
+    pub rent: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct GetSwapAmountAndFees<'info> {
+pub struct RemoveCustody<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: Transfer authority PDA
+    #[account(mut)]
+    pub transfer_authority: AccountInfo<'info>,
     pub perpetuals: Account<'info, Perpetuals>,
+    #[account(mut)]
     pub pool: Account<'info, Pool>,
-    pub receiving_custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by receiving custody
-    pub receiving_custody_oracle_account: AccountInfo<'info>,
-    pub dispensing_custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by dispensing custody
-    pub dispensing_custody_oracle_account: AccountInfo<'info>,
+    #[account(
+        mut,
+        close = admin
+    )]
+    pub custody: Account<'info, Custody>,
+    /// CHECK: Custody token account
+    #[account(mut)]
+    pub custody_token_account: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-pub struct GetAddLiquidityAmountAndFee<'info> {
-    pub perpetuals: Account<'info, Perpetuals>,
-    pub pool: Account<'info, Pool>,
-    pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
-    pub custody_oracle_account: AccountInfo<'info>,
-    /// CHECK: LP token mint account
-    pub lp_token_mint: AccountInfo<'info>,
+pub struct SetAdminSigners<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
 }
 
 #[derive(Accounts)]
-pub struct GetRemoveLiquidityAmountAndFee<'info> {
-    pub perpetuals: Account<'info, Perpetuals>,
-    pub pool: Account<'info, Pool>,
-    pub custody: Account<'info, Custody>,
-    /// CHECK: Oracle account verified by custody
-    pub custody_oracle_account: AccountInfo<'info>,
-    /// CHECK: LP token mint account
-    pub lp_token_mint: AccountInfo<'info>,
+pub struct SetCompDefOffset<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + std::mem::size_of::<CompDefRegistry>(),
+        seeds = [b"comp_def_registry"],
+        bump
+    )]
+    pub comp_def_registry: Account<'info, CompDefRegistry>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct GetAssetsUnderManagement<'info> {
+pub struct SetPriorityFees<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    #[account(
+        mut,
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
     pub perpetuals: Account<'info, Perpetuals>,
-    pub pool: Account<'info, Pool>,
 }
 
 #[derive(Accounts)]
-pub struct GetLpTokenPrice<'info> {
+pub struct SetComputationQuota<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub multisig: Account<'info, Multisig>,
+    #[account(
+        mut,
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
     pub perpetuals: Account<'info, Perpetuals>,
-    pub pool: Account<'info, Pool>,
-    /// CHECK: LP token mint account
-    pub lp_token_mint: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct Swap<'info> {
+pub struct SetLiquidationPriority<'info> {
+    pub admin: Signer<'info>,
     #[account(mut)]
-    pub owner: Signer<'info>,
-    /// CHECK: Transfer authority PDA
-    pub transfer_authority: AccountInfo<'info>,
+    pub multisig: Account<'info, Multisig>,
+    #[account(
+        mut,
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
     pub perpetuals: Account<'info, Perpetuals>,
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
-    #[account(mut)]
-    pub receiving_custody: Account<'info, Custody>,
-    /// CHECK: Receiving custody token account
-    pub receiving_custody_token_account: AccountInfo<'info>,
-    #[account(mut)]
-    pub dispensing_custody: Account<'info, Custody>,
-    /// CHECK: Dispensing custody token account
-    pub dispensing_custody_token_account: AccountInfo<'info>,
-    /// CHECK: Funding account
-    pub funding_account: AccountInfo<'info>,
-    /// CHECK: Receiving account
-    pub receiving_account: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct AddLiquidity<'info> {
+pub struct SetCustodyConfig<'info> {
     #[account(mut)]
-    pub owner: Signer<'info>,
-    /// CHECK: Transfer authority PDA
+    pub admin: Signer<'info>,
     #[account(
-        seeds = [b"transfer_authority"],
-        bump = perpetuals.transfer_authority_bump
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
     )]
-    pub transfer_authority: AccountInfo<'info>,
+    pub multisig: Account<'info, Multisig>,
     #[account(
         seeds = [b"perpetuals"],
         bump = perpetuals.perpetuals_bump
     )]
-    pub perpetuals: Box<Account<'info, Perpetuals>>,
+    pub perpetuals: Account<'info, Perpetuals>,
     #[account(mut)]
     pub pool: Account<'info, Pool>,
     #[account(mut)]
     pub custody: Account<'info, Custody>,
-    /// CHECK: oracle account for the receiving token
-    #[account(
-        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
-    )]
-    pub custody_oracle_account: AccountInfo<'info>,
-    /// CHECK: Custody token account - validate as token account for CPI
-    #[account(
-        mut,
-        seeds = [b"custody_token_account",
-                 pool.key().as_ref(),
-                 custody.mint.as_ref()],
-        bump = custody.token_account_bump
-    )]
-    pub custody_token_account: Box<Account<'info, TokenAccount>>,
-    #[account(
-        mut,
-        seeds = [b"lp_token_mint", pool.key().as_ref()],
-        bump = pool.lp_token_bump
-    )]
-    pub lp_token_mint: Account<'info, Mint>,
-    /// CHECK: Funding account - validate as token account for CPI
-    #[account(
-        mut,
-        constraint = funding_account.mint == custody.mint,
-        has_one = owner
-    )]
-    pub funding_account: Box<Account<'info, TokenAccount>>,
-    /// CHECK: LP token account
-    #[account(
-        mut,
-        constraint = lp_token_account.mint == lp_token_mint.key(),
-        has_one = owner
-    )]
-    pub lp_token_account: Box<Account<'info, TokenAccount>>,
-    pub token_program: Program<'info, Token>,
+    /// Approval from `approve_governance_change`, closed once used
+    #[account(mut)]
+    pub governance_approval: Option<Account<'info, GovernanceApproval>>,
 }
 
 #[derive(Accounts)]
-pub struct RemoveLiquidity<'info> {
+pub struct ProposeCustodyConfig<'info> {
     #[account(mut)]
-    pub owner: Signer<'info>,
-    /// CHECK: Transfer authority PDA
+    pub admin: Signer<'info>,
     #[account(
-        seeds = [b"transfer_authority"],
-        bump = perpetuals.transfer_authority_bump
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
     )]
-    pub transfer_authority: AccountInfo<'info>,
+    pub multisig: Account<'info, Multisig>,
     #[account(
         seeds = [b"perpetuals"],
         bump = perpetuals.perpetuals_bump
     )]
-    pub perpetuals: Box<Account<'info, Perpetuals>>,
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
-    #[account(mut)]
+    pub perpetuals: Account<'info, Perpetuals>,
     pub custody: Account<'info, Custody>,
-    /// CHECK: oracle account for the receiving token
-    #[account(
-        constraint = custody_oracle_account.key() == custody.oracle.oracle_account
-    )]
-    pub custody_oracle_account: AccountInfo<'info>,
-    /// CHECK: Custody token account - validate as token account for CPI
     #[account(
-        mut,
-        seeds = [b"custody_token_account",
-                 pool.key().as_ref(),
-                 custody.mint.as_ref()],
-        bump = custody.token_account_bump
+        init_if_needed,
+        payer = admin,
+        space = 8 + std::mem::size_of::<PendingCustodyConfig>(),
+        seeds = [b"pending_custody_config", custody.key().as_ref()],
+        bump
     )]
-    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+    pub pending_config: Account<'info, PendingCustodyConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteCustodyConfig<'info> {
+    #[account(mut)]
+    pub executor: Signer<'info>,
     #[account(
-        mut,
-        seeds = [b"lp_token_mint", pool.key().as_ref()],
-        bump = pool.lp_token_bump
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
     )]
-    pub lp_token_mint: Account<'info, Mint>,
-    /// CHECK: LP token account
+    pub perpetuals: Account<'info, Perpetuals>,
+    /// CHECK: receives the rent of the closed proposal
     #[account(
         mut,
-        constraint = lp_token_account.mint == lp_token_mint.key(),
-        has_one = owner
+        constraint = proposer.key() == pending_config.proposer
     )]
-    pub lp_token_account: Box<Account<'info, TokenAccount>>,
-    /// CHECK: Receiving account
+    pub proposer: AccountInfo<'info>,
+    #[account(mut)]
+    pub custody: Account<'info, Custody>,
     #[account(
         mut,
-        constraint = receiving_account.mint == custody.mint,
-        has_one = owner
+        seeds = [b"pending_custody_config", custody.key().as_ref()],
+        bump = pending_config.bump,
+        close = proposer
     )]
-    pub receiving_account: Box<Account<'info, TokenAccount>>,
-    /// CHECK: Token program
-    pub token_program: AccountInfo<'info>,
+    pub pending_config: Account<'info, PendingCustodyConfig>,
+    /// Approval from `approve_governance_change`, closed once used
+    #[account(mut)]
+    pub governance_approval: Option<Account<'info, GovernanceApproval>>,
 }
 
 #[derive(Accounts)]
-pub struct Init<'info> {
+pub struct SetConfigTimelock<'info> {
     #[account(mut)]
-    pub upgrade_authority: Signer<'info>,
+    pub admin: Signer<'info>,
     #[account(
-        init,
-        payer = upgrade_authority,
-        space = 8 + std::mem::size_of::<Multisig>(),
+        mut,
         seeds = [b"multisig"],
-        bump
+        bump = multisig.bump
     )]
     pub multisig: Account<'info, Multisig>,
-    /// CHECK: Transfer authority PDA
-    #[account(
-        seeds = [b"transfer_authority"],
-        bump
-    )]
-    pub transfer_authority: AccountInfo<'info>,
     #[account(
-        init,
-        payer = upgrade_authority,
-        space = 8 + std::mem::size_of::<Perpetuals>() + 256,
+        mut,
         seeds = [b"perpetuals"],
-        bump
+        bump = perpetuals.perpetuals_bump
     )]
     pub perpetuals: Account<'info, Perpetuals>,
-    /// CHECK: Program data account
-    pub perpetuals_program_data: AccountInfo<'info>,
-    /// CHECK: Perpetuals program
-    pub perpetuals_program: AccountInfo<'info>,
-    pub system_program: Program<'info, System>,
-    /// CHECK: Token program
-    pub token_program: AccountInfo<'info>,
+    /// Approval from `approve_governance_change`, closed once used
+    #[account(mut)]
+    pub governance_approval: Option<Account<'info, GovernanceApproval>>,
 }
 
 #[derive(Accounts)]
-pub struct AddPool<'info> {
+pub struct SetTradingSchedule<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
-    #[account(mut)]
-    pub multisig: Account<'info, Multisig>,
-    /// CHECK: Transfer authority PDA
     #[account(
-        seeds = [b"transfer_authority"],
-        bump
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
     )]
-    pub transfer_authority: AccountInfo<'info>,
-    pub perpetuals: Account<'info, Perpetuals>,
+    pub multisig: Account<'info, Multisig>,
     #[account(
-        init,
-        payer = admin,
-        space = 8 + std::mem::size_of::<Pool>() + 512,
-        seeds = [b"pool", perpetuals.pools.len().to_le_bytes().as_ref()],
-        bump
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
     )]
-    pub pool: Account<'info, Pool>,
+    pub perpetuals: Account<'info, Perpetuals>,
+    #[account(mut)]
+    pub custody: Account<'info, Custody>,
+    /// Approval from `approve_governance_change`, closed once used
+    #[account(mut)]
+    pub governance_approval: Option<Account<'info, GovernanceApproval>>,
+}
+
+#[derive(Accounts)]
+pub struct SetRiskManager<'info> {
+    pub admin: Signer<'info>,
     #[account(
-        init_if_needed,
-        payer = admin,
-        mint::authority = transfer_authority,
-        mint::freeze_authority = transfer_authority,
-        mint::decimals = 6,
-        seeds = [b"lp_token_mint", pool.key().as_ref()],
-        bump
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
     )]
-    pub lp_token_mint: Account<'info, Mint>,
-    pub system_program: Program<'info, System>,
-    /// CHECK: Token program
-    pub token_program: AccountInfo<'info>,
-    /// CHECK: Rent sysvar
-    pub rent: AccountInfo<'info>,
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut)]
+    pub custody: Account<'info, Custody>,
 }
 
 #[derive(Accounts)]
-pub struct RemovePool<'info> {
-    #[account(mut)]
+pub struct SetAllowlistRoot<'info> {
     pub admin: Signer<'info>,
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
+    )]
     pub multisig: Account<'info, Multisig>,
-    /// CHECK: Transfer authority PDA
     #[account(mut)]
-    pub transfer_authority: AccountInfo<'info>,
-    pub perpetuals: Account<'info, Perpetuals>,
+    pub custody: Account<'info, Custody>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceFallback<'info> {
+    pub admin: Signer<'info>,
     #[account(
         mut,
-        close = admin
+        seeds = [b"multisig"],
+        bump = multisig.bump
     )]
-    pub pool: Account<'info, Pool>,
-    pub system_program: Program<'info, System>,
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut)]
+    pub custody: Account<'info, Custody>,
 }
 
 #[derive(Accounts)]
-pub struct AddCustody<'info> {
-    #[account(mut)]
+pub struct SetCustodyFreeze<'info> {
     pub admin: Signer<'info>,
-    #[account(mut)]
-    pub multisig: Account<'info, Multisig>,
-    /// CHECK: Transfer authority PDA
     #[account(
-        seeds = [b"transfer_authority"],
-        bump
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
     )]
-    pub transfer_authority: AccountInfo<'info>,
-    pub perpetuals: Account<'info, Perpetuals>,
+    pub multisig: Account<'info, Multisig>,
     #[account(mut)]
-    pub pool: Account<'info, Pool>,
+    pub custody: Account<'info, Custody>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinHoldPeriod<'info> {
+    pub admin: Signer<'info>,
     #[account(
-        init,
-        payer = admin,
-        space = 8 + std::mem::size_of::<Custody>() + 256,
-        seeds = [b"custody", pool.key().as_ref(), custody_token_mint.key().as_ref()],
-        bump
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
     )]
+    pub multisig: Account<'info, Multisig>,
+    #[account(mut)]
+    pub custody: Account<'info, Custody>,
+}
+
+#[derive(Accounts)]
+pub struct JoinAllowlist<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
     pub custody: Account<'info, Custody>,
-    /// CHECK: Custody token account PDA
     #[account(
         init_if_needed,
-        payer = admin,
-        token::mint = custody_token_mint,
-        token::authority = transfer_authority,
-        seeds = [b"custody_token_account",
-                 pool.key().as_ref(),
-                 custody_token_mint.key().as_ref()],
+        payer = owner,
+        space = 8 + std::mem::size_of::<AllowlistEntry>(),
+        seeds = [b"allowlist_entry", custody.key().as_ref(), owner.key().as_ref()],
         bump
     )]
-    pub custody_token_account: Box<Account<'info, TokenAccount>>,
-    /// CHECK: Custody token mint
-    pub custody_token_mint: AccountInfo<'info>,
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    /// CHECK: Rent sysvar
-    pub rent: AccountInfo<'info>,
 }
 
 #[derive(Accounts)]
-pub struct RemoveCustody<'info> {
+pub struct SetGovernance<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
+    )]
     pub multisig: Account<'info, Multisig>,
-    /// CHECK: Transfer authority PDA
-    #[account(mut)]
-    pub transfer_authority: AccountInfo<'info>,
-    pub perpetuals: Account<'info, Perpetuals>,
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
     #[account(
         mut,
-        close = admin
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
     )]
-    pub custody: Account<'info, Custody>,
-    /// CHECK: Custody token account
-    #[account(mut)]
-    pub custody_token_account: AccountInfo<'info>,
-    pub system_program: Program<'info, System>,
-    /// CHECK: Token program
-    pub token_program: AccountInfo<'info>,
-}
-
-#[derive(Accounts)]
-pub struct SetAdminSigners<'info> {
-    pub admin: Signer<'info>,
+    pub perpetuals: Account<'info, Perpetuals>,
+    /// Approval from `approve_governance_change`, closed once used
     #[account(mut)]
-    pub multisig: Account<'info, Multisig>,
+    pub governance_approval: Option<Account<'info, GovernanceApproval>>,
 }
 
 #[derive(Accounts)]
-pub struct SetCustodyConfig<'info> {
-    pub admin: Signer<'info>,
-    #[account(mut)]
-    pub multisig: Account<'info, Multisig>,
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
+#[instruction(params: ApproveGovernanceChangeParams)]
+pub struct ApproveGovernanceChange<'info> {
     #[account(mut)]
-    pub custody: Account<'info, Custody>,
+    pub payer: Signer<'info>,
+    /// spl-governance account of the configured realm, signing through the
+    /// governance program's proposal execution
+    pub governance: Signer<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Account<'info, Perpetuals>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<GovernanceApproval>(),
+        seeds = [b"governance_approval", params.change_hash.as_ref()],
+        bump
+    )]
+    pub governance_approval: Account<'info, GovernanceApproval>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -4056,16 +9467,39 @@ pub struct SetPermissions<'info> {
 pub struct WithdrawFees<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
     /// CHECK: Transfer authority PDA
+    #[account(
+        seeds = [b"transfer_authority"],
+        bump = perpetuals.transfer_authority_bump
+    )]
     pub transfer_authority: AccountInfo<'info>,
+    #[account(
+        seeds = [b"perpetuals"],
+        bump = perpetuals.perpetuals_bump
+    )]
+    pub perpetuals: Box<Account<'info, Perpetuals>>,
     #[account(mut)]
     pub custody: Account<'info, Custody>,
-    /// CHECK: Custody token account
-    pub custody_token_account: AccountInfo<'info>,
-    /// CHECK: Receiving account
-    pub receiving_account: AccountInfo<'info>,
-    /// CHECK: Token program
-    pub token_program: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"custody_token_account",
+                 custody.pool.as_ref(),
+                 custody.mint.as_ref()],
+        bump = custody.token_account_bump
+    )]
+    pub custody_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = receiving_account.mint == custody.mint
+    )]
+    pub receiving_account: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
@@ -4083,6 +9517,12 @@ pub struct WithdrawSolFees<'info> {
 pub struct SetCustomOraclePrice<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
     #[account(
         init_if_needed,
         payer = admin,
@@ -4091,10 +9531,41 @@ pub struct SetCustomOraclePrice<'info> {
         bump
     )]
     pub custom_oracle: Account<'info, CustomOracle>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + std::mem::size_of::<CustomOracleOverride>(),
+        seeds = [b"custom_oracle_override", custody.key().as_ref()],
+        bump
+    )]
+    pub custom_oracle_override: Account<'info, CustomOracleOverride>,
     pub custody: Account<'info, Custody>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct SetMarketMaker<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"multisig"],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, Multisig>,
+    /// CHECK: wallet being registered, only used as a seed
+    pub market_maker_owner: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + std::mem::size_of::<MarketMaker>(),
+        seeds = [b"market_maker", market_maker_owner.key().as_ref()],
+        bump
+    )]
+    pub market_maker: Account<'info, MarketMaker>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct SetTestTime<'info> {
     pub admin: Signer<'info>,
@@ -4118,7 +9589,22 @@ pub struct CustomOracle {
     pub publish_time: i64,
 }
 
+/// Out-of-band price scheduled by `set_custom_oracle_price`, applied once
+/// `unlock_time` has passed. Kept apart from `CustomOracle` so oracles
+/// created before overrides existed keep their layout.
+#[account]
+#[derive(Default, Debug)]
+pub struct CustomOracleOverride {
+    pub price: u64,
+    pub unlock_time: i64,
+    pub bump: u8,
+}
+
 impl CustomOracle {
+    /// Serialized length including the discriminator, which is all that
+    /// `read_oracle_price` and `oracle_is_fresh` read
+    pub const LEN: usize = 8 + 8 + 4 + 8 + 8 + 8;
+
     pub fn set(&mut self, price: u64, expo: i32, conf: u64, ema: u64, publish_time: i64) {
         self.price = price;
         self.expo = expo;
@@ -4211,13 +9697,16 @@ impl CustomOracle {
         Ok(())
     }
 
-    /// Initialize trader state
+    /// Initialize trader state for one of the trader's sub-accounts.
+    /// Each sub-account has its own risk state and confidential collateral account.
     pub fn initialize_trader_state(
         ctx: Context<InitializeTraderState>,
+        sub_account_id: u16,
         margin_mode: MarginMode,
     ) -> Result<()> {
         let trader_state = &mut ctx.accounts.trader_state;
         trader_state.trader = ctx.accounts.trader.key();
+        trader_state.sub_account_id = sub_account_id;
         trader_state.risk_state_ciphertext = Vec::new();
         trader_state.risk_state_version = 0;
         trader_state.margin_mode = margin_mode;
@@ -4383,6 +9872,12 @@ impl CustomOracle {
             })),
             None,
         )?;
+        log_comp_def_init(
+            &mut ctx.accounts.comp_def_init_log,
+            ctx.bumps.comp_def_init_log,
+            COMP_DEF_OFFSET_MIX_POSITIONS,
+            ctx.accounts.payer.key(),
+        )?;
         Ok(())
     }
 
@@ -4449,6 +9944,8 @@ impl CustomOracle {
         ctx: Context<MixPositions>,
         computation_offset: u64,
     ) -> Result<()> {
+        require_queue_compute_budget()?;
+
         let mixer_pool = &mut ctx.accounts.mixer_pool;
         let current_slot = Clock::get()?.slot;
         
@@ -4496,6 +9993,12 @@ impl CustomOracle {
             1,
             0,
         )?;
+        computation_queued(
+            ComputationKind::MixPositions,
+            computation_offset,
+            ctx.accounts.computation_account.key(),
+            ctx.accounts.sign_pda_account.key(),
+        );
 
         Ok(())
     }
@@ -4512,11 +10015,14 @@ impl CustomOracle {
             &ctx.accounts.computation_account
         ) {
             Ok(result) => result,
-            Err(e) => {
-                msg!("Error: {}", e);
-                return Err(ErrorCode::AbortedComputation.into())
-            },
+            Err(e) => return Err(callback_error(
+                ComputationKind::MixPositions,
+                COMP_DEF_OFFSET_MIX_POSITIONS,
+                ctx.accounts.computation_account.key(),
+                e,
+            )),
         };
+        computation_completed(ComputationKind::MixPositions, ctx.accounts.computation_account.key());
 
         let mixer_pool = &mut ctx.accounts.mixer_pool;
         
@@ -4624,6 +10130,7 @@ pub struct UpdateMarketPrices<'info> {
 }
 
 #[derive(Accounts)]
+#[instruction(sub_account_id: u16)]
 pub struct InitializeTraderState<'info> {
     #[account(mut)]
     pub trader: Signer<'info>,
@@ -4632,7 +10139,7 @@ pub struct InitializeTraderState<'info> {
         init,
         payer = trader,
         space = 8 + std::mem::size_of::<TraderState>(),
-        seeds = [b"trader", trader.key().as_ref()],
+        seeds = [b"trader", trader.key().as_ref(), &sub_account_id.to_le_bytes()],
         bump
     )]
     pub trader_state: Account<'info, TraderState>,
@@ -4641,7 +10148,7 @@ pub struct InitializeTraderState<'info> {
         init,
         payer = trader,
         space = 8 + 32,  // Simplified for simulation
-        seeds = [b"confidential_account", trader.key().as_ref()],
+        seeds = [b"confidential_account", trader.key().as_ref(), &sub_account_id.to_le_bytes()],
         bump
     )]
     pub confidential_account: AccountInfo<'info>,
@@ -4656,7 +10163,7 @@ pub struct DepositCollateralConfidential<'info> {
     
     #[account(
         mut,
-        seeds = [b"trader", trader.key().as_ref()],
+        seeds = [b"trader", trader.key().as_ref(), &trader_state.sub_account_id.to_le_bytes()],
         bump = trader_state.bump
     )]
     pub trader_state: Account<'info, TraderState>,
@@ -4677,7 +10184,7 @@ pub struct WithdrawCollateralConfidential<'info> {
     
     #[account(
         mut,
-        seeds = [b"trader", trader.key().as_ref()],
+        seeds = [b"trader", trader.key().as_ref(), &trader_state.sub_account_id.to_le_bytes()],
         bump = trader_state.bump
     )]
     pub trader_state: Account<'info, TraderState>,
@@ -4697,7 +10204,7 @@ pub struct SubmitOrder<'info> {
     pub trader: Signer<'info>,
     
     #[account(
-        seeds = [b"trader", trader.key().as_ref()],
+        seeds = [b"trader", trader.key().as_ref(), &trader_state.sub_account_id.to_le_bytes()],
         bump = trader_state.bump
     )]
     pub trader_state: Account<'info, TraderState>,
@@ -4809,6 +10316,14 @@ pub struct InitMixPositionsCompDef<'info> {
     
     pub system_program: Program<'info, System>,
     pub arcium_program: Program<'info, arcium_client::idl::arcium::Arcium>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + std::mem::size_of::<CompDefInitLog>(),
+        seeds = [b"comp_def_init_log"],
+        bump
+    )]
+    pub comp_def_init_log: Box<Account<'info, CompDefInitLog>>,
 }
 
 #[derive(Accounts)]
@@ -4927,4 +10442,72 @@ pub enum ErrorCode {
     EpochNotEnded,
     #[msg("Invalid order size")]
     InvalidOrderSize,
+    #[msg("Signer is not authorized for this multisig")]
+    MultisigAccountNotAuthorized,
+    #[msg("Signer has already approved this instruction")]
+    MultisigAlreadySigned,
+    #[msg("Price change exceeds the allowed band")]
+    PriceJumpTooLarge,
+    #[msg("Timelock has not expired")]
+    TimelockNotExpired,
+    #[msg("Risk parameter changes must be proposed and executed after the timelock")]
+    ConfigChangeRequiresTimelock,
+    #[msg("A governance approval is required for this change")]
+    GovernanceApprovalMissing,
+    #[msg("Governance account or approval does not belong to the configured realm")]
+    InvalidGovernanceProposal,
+    #[msg("Governance approval does not cover this change")]
+    GovernanceProposalNotApproved,
+    #[msg("Payer has too many computations in flight")]
+    TooManyInFlightComputations,
+    #[msg("Computation offset was already used for this position")]
+    ComputationOffsetReused,
+    #[msg("Market is outside its trading hours")]
+    MarketClosed,
+    #[msg("Position would exceed the custody's max utilization")]
+    MaxUtilizationExceeded,
+    #[msg("Pool name must be 1-32 bytes without control characters")]
+    InvalidPoolName,
+    #[msg("Pool already holds the maximum number of custodies")]
+    TooManyCustodies,
+    #[msg("Token ratios exceed the custody count or are not ordered min <= target <= max")]
+    InvalidTokenRatios,
+    #[msg("Signer key must be a valid ed25519 public key")]
+    InvalidSignerKey,
+    #[msg("Client request id was already used for this position")]
+    DuplicateClientRequest,
+    #[msg("Computation definition registry is full")]
+    CompDefRegistryFull,
+    #[msg("Position is not waiting on an open callback")]
+    PositionNotPending,
+    #[msg("Pending position has not reached its expiry slot")]
+    PendingPositionNotExpired,
+    #[msg("Pool already has LP supply or was seeded before")]
+    PoolAlreadySeeded,
+    #[msg("Withdrawal would take the custody below its min pool ratio")]
+    TokenRatioOutOfRange,
+    #[msg("Owner is not on the custody's allowlist")]
+    NotAllowlisted,
+    #[msg("Instruction must be invoked through CPI from another program")]
+    NotInvokedViaCpi,
+    #[msg("Oracle price is stale and no fallback price is available")]
+    StaleOraclePrice,
+    #[msg("Computation output has an unexpected ciphertext count or nonce")]
+    MalformedComputationOutput,
+    #[msg("Custody has no free liquidity for this withdrawal")]
+    InsufficientLiquidity,
+    #[msg("Deposits or withdrawals are frozen on this custody")]
+    CustodyFrozen,
+    #[msg("Position is within the minimum holding period and not at a loss")]
+    MinHoldPeriodNotElapsed,
+    #[msg("Not enough compute units left to queue the computation")]
+    InsufficientComputeBudget,
+    #[msg("Withdrawal claim would be paid out below its minimum amount")]
+    ClaimBelowMinAmountOut,
+    #[msg("Position is being transferred")]
+    PositionTransferPending,
+    #[msg("Pending position expired before its open callback landed")]
+    PendingPositionExpired,
+    #[msg("Encryption key was rotated before the open callback landed")]
+    ReceiptKeyRotated,
 }
@@ -1,4 +1,7 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hashv;
+
+use crate::{ErrorCode, PositionSide};
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Side {
@@ -29,7 +32,7 @@ pub enum AumCalcMode {
     EMA,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Permissions {
     pub allow_swap: bool,
     pub allow_add_liquidity: bool,
@@ -41,7 +44,80 @@ pub struct Permissions {
     pub allow_size_change: bool,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub const PROGRAM_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// `git describe` of the tree the program was built from, set by build.rs
+pub const PROGRAM_GIT_DESCRIBE: &str = env!("PERPETUALS_GIT_DESCRIBE");
+
+/// Crate version and git describe string, zero padded.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct ProgramVersion {
+    pub version: [u8; 16],
+    pub git_describe: [u8; 48],
+}
+
+impl ProgramVersion {
+    /// Version of the running build; longer strings are truncated
+    pub fn current() -> Self {
+        let mut version = [0u8; 16];
+        let len = PROGRAM_VERSION.len().min(version.len());
+        version[..len].copy_from_slice(&PROGRAM_VERSION.as_bytes()[..len]);
+
+        let mut git_describe = [0u8; 48];
+        let len = PROGRAM_GIT_DESCRIBE.len().min(git_describe.len());
+        git_describe[..len].copy_from_slice(&PROGRAM_GIT_DESCRIBE.as_bytes()[..len]);
+
+        Self {
+            version,
+            git_describe,
+        }
+    }
+}
+
+/// Recommended compute unit price, in microlamports per CU, that keepers and
+/// frontends attach to each kind of instruction. Advisory only.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct PriorityFees {
+    pub open_position: u64,
+    pub close_position: u64,
+    pub update_collateral: u64,
+    pub liquidate: u64,
+    pub swap: u64,
+    pub liquidity: u64,
+}
+
+/// Priority for liquidation computations of positions the public price move
+/// already marks as critical, so their callbacks land ahead of the backlog.
+/// Zero `cu_price_micro` disables the channel.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct LiquidationPriority {
+    /// Adverse move from entry, in bps, at which a position is critical
+    pub critical_move_bps: u64,
+    /// Compute unit price, in microlamports, of critical callbacks
+    pub cu_price_micro: u64,
+}
+
+impl LiquidationPriority {
+    /// Callback compute unit price for liquidating a position at
+    /// `current_price`. The price is the liquidator's, who also pays for the
+    /// priority, so overstating the move only costs them.
+    pub fn cu_price_micro(&self, side: PositionSide, entry_price: u64, current_price: u64) -> u64 {
+        if self.cu_price_micro == 0 || entry_price == 0 {
+            return 0;
+        }
+        let adverse_move = match side {
+            PositionSide::Long => entry_price.saturating_sub(current_price),
+            PositionSide::Short => current_price.saturating_sub(entry_price),
+        };
+        let move_bps = (adverse_move as u128) * 10_000 / entry_price as u128;
+        if move_bps >= self.critical_move_bps as u128 {
+            self.cu_price_micro
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct OracleParams {
     pub oracle_account: Pubkey,
     pub oracle_type: OracleType,
@@ -50,7 +126,7 @@ pub struct OracleParams {
     pub max_price_age_sec: u32,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct PricingParams {
     pub use_ema: bool,
     pub use_unrealized_pnl_in_aum: bool,
@@ -60,13 +136,18 @@ pub struct PricingParams {
     pub min_initial_leverage: u64,
     pub max_initial_leverage: u64,
     pub max_leverage: u64,
+    /// Cap on a position's profit as a multiple of its collateral in bps
+    /// (10_000 = 1x); 0 disables the cap
     pub max_payoff_mult: u64,
+    /// Cap on `assets.locked / assets.owned` in bps; 0 disables the check
     pub max_utilization: u64,
     pub max_position_locked_usd: u64,
     pub max_total_locked_usd: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+/// Fee rates in basis points. `open_position` is the taker rate; approved
+/// market makers pay their own rate instead, see `MarketMaker`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Fees {
     pub mode: FeesMode,
     pub ratio_mult: u64,
@@ -85,7 +166,7 @@ pub struct Fees {
     pub fee_optimal: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BorrowRateParams {
     pub base_rate: u64,
     pub slope1: u64,
@@ -93,9 +174,270 @@ pub struct BorrowRateParams {
     pub optimal_utilization: u64,
 }
 
+/// Weekly trading window for opening positions and adding to them, in UTC.
+/// Closing is never restricted by the schedule.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TradingSchedule {
+    pub enabled: bool,
+    /// Bit `n` allows trading on weekday `n`, where 0 is Monday.
+    pub open_days: u8,
+    /// Daily window in seconds since midnight. Equal values mean all day;
+    /// `open_sec > close_sec` wraps past midnight.
+    pub open_sec: u32,
+    pub close_sec: u32,
+    /// One-off maintenance pause, `[start, end)` in unix seconds.
+    pub maintenance_start: i64,
+    pub maintenance_end: i64,
+}
+
+/// `BorrowRateState::cumulative_interest` is in rate-seconds of a rate in
+/// bps per hour, so `size * delta / INTEREST_RATE_DIVISOR` is the interest.
+pub const INTEREST_RATE_DIVISOR: u128 = 10_000 * 3_600;
+
+impl Custody {
+    /// Account size allocated by `add_custody`, with slack for new fields.
+    /// Custodies created before a field outgrew the slack are grown to it by
+    /// `realloc_custody`.
+    pub const SPACE: usize = 8 + std::mem::size_of::<Custody>() + 256;
+
+    /// Price a risk-reducing action may use while the oracle is stale: the
+    /// last good `cached_price`, moved against the trader by the drift allowed
+    /// since `cached_slot`. Fails once that drift reaches 100%.
+    pub fn fallback_price(
+        &self,
+        cached_price: u64,
+        cached_slot: u64,
+        slot: u64,
+        side: PositionSide,
+    ) -> Result<u64> {
+        require!(
+            self.max_price_drift_bps_per_slot > 0 && cached_price > 0,
+            ErrorCode::StaleOraclePrice
+        );
+        let drift_bps = slot
+            .saturating_sub(cached_slot)
+            .saturating_mul(self.max_price_drift_bps_per_slot);
+        require!(drift_bps < 10_000, ErrorCode::StaleOraclePrice);
+
+        let factor_bps = match side {
+            PositionSide::Long => 10_000 - drift_bps,
+            PositionSide::Short => 10_000 + drift_bps,
+        };
+        let price = (cached_price as u128)
+            .checked_mul(factor_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / 10_000;
+        Ok(u64::try_from(price).map_err(|_| ErrorCode::MathOverflow)?)
+    }
+
+    /// Rejects closing a position within `min_hold_period_sec` of opening
+    /// unless it closes at a loss, so quick round trips can't farm fee
+    /// rebates.
+    pub fn check_min_hold(
+        &self,
+        side: PositionSide,
+        open_time: i64,
+        entry_price: u64,
+        exit_price: u64,
+        now: i64,
+    ) -> Result<()> {
+        if now.saturating_sub(open_time) >= self.min_hold_period_sec {
+            return Ok(());
+        }
+        let at_loss = match side {
+            PositionSide::Long => exit_price < entry_price,
+            PositionSide::Short => exit_price > entry_price,
+        };
+        require!(at_loss, ErrorCode::MinHoldPeriodNotElapsed);
+        Ok(())
+    }
+
+    /// `borrow_rate_state.cumulative_interest` advanced to `now` at the
+    /// current rate, without writing it back.
+    pub fn get_cumulative_interest(&self, now: i64) -> Result<u128> {
+        let state = &self.borrow_rate_state;
+        let elapsed = now.saturating_sub(state.last_update).max(0) as u128;
+        Ok(state.cumulative_interest
+            .checked_add(
+                (state.current_rate as u128)
+                    .checked_mul(elapsed)
+                    .ok_or(ErrorCode::MathOverflow)?
+            )
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    /// Accrues borrow interest up to `now` and returns the new cumulative
+    /// total for positions to snapshot.
+    pub fn update_cumulative_interest(&mut self, now: i64) -> Result<u128> {
+        let cumulative_interest = self.get_cumulative_interest(now)?;
+        self.borrow_rate_state.cumulative_interest = cumulative_interest;
+        self.borrow_rate_state.last_update = self.borrow_rate_state.last_update.max(now);
+        Ok(cumulative_interest)
+    }
+
+    pub fn lock_funds(&mut self, amount: u64) -> Result<()> {
+        self.assets.locked = self.assets.locked
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn unlock_funds(&mut self, amount: u64) {
+        self.assets.locked = self.assets.locked.saturating_sub(amount);
+    }
+
+    pub fn position_stats_mut(&mut self, side: PositionSide) -> &mut PositionStats {
+        if side == PositionSide::Long {
+            &mut self.long_positions
+        } else {
+            &mut self.short_positions
+        }
+    }
+
+    /// Counts a newly opened position. Encrypted opens only know the
+    /// revealed lock bound, so they pass it as size and 0 as collateral.
+    pub fn add_position_stats(
+        &mut self,
+        side: PositionSide,
+        size_usd: u64,
+        collateral_usd: u64,
+        locked_amount: u64,
+    ) -> Result<()> {
+        let stats = self.position_stats_mut(side);
+        stats.open_positions = stats.open_positions
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stats.size_usd = stats.size_usd
+            .checked_add(size_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stats.collateral_usd = stats.collateral_usd
+            .checked_add(collateral_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stats.locked_amount = stats.locked_amount
+            .checked_add(locked_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Accumulates a settled position's realized trader profit or loss and
+    /// drops its open interest.
+    pub fn settle_trade_stats(
+        &mut self,
+        side: PositionSide,
+        size_usd: u64,
+        profit_usd: u64,
+        loss_usd: u64,
+    ) -> Result<()> {
+        self.trade_stats.profit_usd = self.trade_stats.profit_usd
+            .checked_add(profit_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.trade_stats.loss_usd = self.trade_stats.loss_usd
+            .checked_add(loss_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if side == PositionSide::Long {
+            self.trade_stats.oi_long_usd = self.trade_stats.oi_long_usd.saturating_sub(size_usd);
+        } else {
+            self.trade_stats.oi_short_usd = self.trade_stats.oi_short_usd.saturating_sub(size_usd);
+        }
+        Ok(())
+    }
+
+    /// Reverses `add_position_stats` when a position closes or is liquidated.
+    pub fn remove_position_stats(
+        &mut self,
+        side: PositionSide,
+        size_usd: u64,
+        collateral_usd: u64,
+        locked_amount: u64,
+    ) {
+        let stats = self.position_stats_mut(side);
+        stats.open_positions = stats.open_positions.saturating_sub(1);
+        stats.size_usd = stats.size_usd.saturating_sub(size_usd);
+        stats.collateral_usd = stats.collateral_usd.saturating_sub(collateral_usd);
+        stats.locked_amount = stats.locked_amount.saturating_sub(locked_amount);
+    }
+
+    /// Fails if locking `amount` more would push utilization past
+    /// `pricing.max_utilization`, keeping LP withdrawal capacity available.
+    pub fn check_utilization(&self, amount: u64) -> Result<()> {
+        if self.pricing.max_utilization == 0 {
+            return Ok(());
+        }
+
+        let locked = (self.assets.locked as u128)
+            .checked_add(amount as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let capacity = (self.assets.owned as u128)
+            .checked_mul(self.pricing.max_utilization as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        require!(
+            locked.checked_mul(10000).ok_or(ErrorCode::MathOverflow)? <= capacity,
+            ErrorCode::MaxUtilizationExceeded
+        );
+        Ok(())
+    }
+
+    /// Key the health copy of a valuation is encrypted to: the risk manager's,
+    /// or the caller's own when none is set, who already receives the full
+    /// valuation.
+    pub fn risk_report_pubkey(&self, client_pubkey: [u8; 32]) -> [u8; 32] {
+        if self.risk_manager_enc_pubkey == [0; 32] {
+            client_pubkey
+        } else {
+            self.risk_manager_enc_pubkey
+        }
+    }
+
+    /// Fails while `allowlist_root` is set unless `owner` has joined under
+    /// the current root, see `join_allowlist`.
+    pub fn check_allowlist(&self, owner: &Pubkey, entry: Option<&AllowlistEntry>) -> Result<()> {
+        if self.allowlist_root == [0; 32] {
+            return Ok(());
+        }
+
+        let entry = entry.ok_or(ErrorCode::NotAllowlisted)?;
+        require!(
+            entry.owner == *owner && entry.root == self.allowlist_root,
+            ErrorCode::NotAllowlisted
+        );
+        Ok(())
+    }
+}
+
+impl TradingSchedule {
+    const SECONDS_PER_DAY: i64 = 86_400;
+
+    pub fn is_open(&self, timestamp: i64) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        if timestamp >= self.maintenance_start && timestamp < self.maintenance_end {
+            return false;
+        }
+
+        // 1970-01-01 was a Thursday, weekday 3 when Monday is 0
+        let days = timestamp.div_euclid(Self::SECONDS_PER_DAY);
+        let weekday = (days + 3).rem_euclid(7) as u8;
+        if self.open_days & (1 << weekday) == 0 {
+            return false;
+        }
+
+        let second_of_day = timestamp.rem_euclid(Self::SECONDS_PER_DAY) as u32;
+        if self.open_sec == self.close_sec {
+            true
+        } else if self.open_sec < self.close_sec {
+            second_of_day >= self.open_sec && second_of_day < self.close_sec
+        } else {
+            second_of_day >= self.open_sec || second_of_day < self.close_sec
+        }
+    }
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
 pub struct Assets {
     pub collateral: u64,
+    /// Part of `owned` due to the protocol, paid out by `withdraw_fees`
     pub protocol_fees: u64,
     pub owned: u64,
     pub locked: u64,
@@ -109,6 +451,9 @@ pub struct FeesStats {
     pub open_position_usd: u64,
     pub close_position_usd: u64,
     pub liquidation_usd: u64,
+    /// Split of `open_position_usd` between market maker and taker opens
+    pub open_position_maker_usd: u64,
+    pub open_position_taker_usd: u64,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
@@ -121,6 +466,49 @@ pub struct VolumeStats {
     pub liquidation_usd: u64,
 }
 
+pub const ROLLING_STATS_BUCKETS: usize = 24;
+pub const ROLLING_STATS_BUCKET_SEC: i64 = 3_600;
+
+/// Hourly trade volume and fees over the last day, so fee tiers and
+/// dashboards can read rolling windows without an indexer. Each bucket is
+/// reused once its hour is a day old.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct RollingStats {
+    /// Hour (`unix_timestamp / 3600`) each bucket currently covers
+    pub bucket_hours: [i64; ROLLING_STATS_BUCKETS],
+    pub volume_usd: [u64; ROLLING_STATS_BUCKETS],
+    pub fees_usd: [u64; ROLLING_STATS_BUCKETS],
+}
+
+impl RollingStats {
+    pub fn record(&mut self, now: i64, volume_usd: u64, fee_usd: u64) {
+        let hour = now.div_euclid(ROLLING_STATS_BUCKET_SEC);
+        let idx = hour.rem_euclid(ROLLING_STATS_BUCKETS as i64) as usize;
+        if self.bucket_hours[idx] != hour {
+            self.bucket_hours[idx] = hour;
+            self.volume_usd[idx] = 0;
+            self.fees_usd[idx] = 0;
+        }
+        self.volume_usd[idx] = self.volume_usd[idx].saturating_add(volume_usd);
+        self.fees_usd[idx] = self.fees_usd[idx].saturating_add(fee_usd);
+    }
+
+    /// `(volume, fees)` over the current hour and the 23 before it
+    pub fn totals(&self, now: i64) -> (u64, u64) {
+        let hour = now.div_euclid(ROLLING_STATS_BUCKET_SEC);
+        (0..ROLLING_STATS_BUCKETS)
+            .filter(|&idx| hour - self.bucket_hours[idx] < ROLLING_STATS_BUCKETS as i64)
+            .fold((0u64, 0u64), |(volume, fees), idx| {
+                (
+                    volume.saturating_add(self.volume_usd[idx]),
+                    fees.saturating_add(self.fees_usd[idx]),
+                )
+            })
+    }
+}
+
+/// Realized trader profit and loss accumulate only from settlements with a
+/// plaintext payout; encrypted closes keep their PnL private.
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
 pub struct TradeStats {
     pub profit_usd: u64,
@@ -149,7 +537,7 @@ pub struct BorrowRateState {
     pub last_update: i64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct TokenRatios {
     pub target: u64,
     pub min: u64,
@@ -201,6 +589,22 @@ pub struct Perpetuals {
     pub transfer_authority_bump: u8,
     pub perpetuals_bump: u8,
     pub inception_time: i64,
+    /// Delay between proposing and executing risk-parameter changes.
+    /// Zero lets `set_custody_config` apply them immediately.
+    pub config_timelock_sec: i64,
+    /// spl-governance program and realm whose passed proposals must approve
+    /// config changes. A default realm disables the check.
+    pub governance_program: Pubkey,
+    pub governance_realm: Pubkey,
+    pub priority_fees: PriorityFees,
+    /// Build that initialized this config
+    pub init_version: ProgramVersion,
+    /// Cap on computations a single payer may have queued at once, see
+    /// `PayerQuota`. Zero disables the cap.
+    pub max_in_flight_computations: u16,
+    /// Callback priority for critical liquidations, set through
+    /// `set_liquidation_priority`. Default disables it.
+    pub liquidation_priority: LiquidationPriority,
 }
 
 #[account]
@@ -212,6 +616,376 @@ pub struct Pool {
     pub bump: u8,
     pub lp_token_bump: u8,
     pub inception_time: i64,
+    /// AUM deposited by `seed_pool`, zero until the pool is seeded
+    pub genesis_aum_usd: u128,
+    pub genesis_time: i64,
+}
+
+impl Pool {
+    pub const MAX_NAME_LEN: usize = 32;
+    /// Bounded by the fixed space allocated in `AddPool`.
+    pub const MAX_CUSTODIES: usize = 8;
+
+    pub fn validate_name(name: &str) -> Result<()> {
+        require!(
+            !name.is_empty()
+                && name.len() <= Self::MAX_NAME_LEN
+                && !name.chars().any(char::is_control),
+            ErrorCode::InvalidPoolName
+        );
+        Ok(())
+    }
+
+    pub fn validate_ratios(ratios: &[TokenRatios], max_len: usize) -> Result<()> {
+        require!(ratios.len() <= max_len, ErrorCode::InvalidTokenRatios);
+        for ratio in ratios {
+            require!(
+                ratio.min <= ratio.target && ratio.target <= ratio.max,
+                ErrorCode::InvalidTokenRatios
+            );
+        }
+        Ok(())
+    }
+
+    /// Rejects a withdrawal that would leave `custody` holding less than its
+    /// min share (bps) of the pool's AUM. Emptying the pool is always allowed,
+    /// and so is any withdrawal from a custody without a configured ratio.
+    pub fn check_min_ratio(
+        &self,
+        custody: &Pubkey,
+        custody_usd_after: u64,
+        aum_usd_after: u128,
+    ) -> Result<()> {
+        let idx = self.custodies
+            .iter()
+            .position(|key| key == custody)
+            .ok_or(ErrorCode::InvalidInput)?;
+        let Some(ratio) = self.ratios.get(idx) else {
+            return Ok(());
+        };
+        if aum_usd_after == 0 {
+            return Ok(());
+        }
+
+        let share = (custody_usd_after as u128)
+            .checked_mul(10000)
+            .ok_or(ErrorCode::MathOverflow)?
+            / aum_usd_after;
+        require!(share >= ratio.min as u128, ErrorCode::TokenRatioOutOfRange);
+        Ok(())
+    }
+
+    /// LP token price in USD with 6 decimals, 1.0 while the pool is empty.
+    pub fn get_lp_token_price(&self, lp_supply: u64) -> Result<u64> {
+        Self::lp_token_price(self.aum_usd, lp_supply)
+    }
+
+    /// LP token price for a given AUM, see `get_lp_token_price`.
+    pub fn lp_token_price(aum_usd: u128, lp_supply: u64) -> Result<u64> {
+        if lp_supply == 0 {
+            return Ok(1_000000);
+        }
+
+        let price = aum_usd
+            .checked_mul(1_000000)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(lp_supply as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(u64::try_from(price).map_err(|_| ErrorCode::MathOverflow)?)
+    }
+}
+
+/// Last published LP token price of a pool, refreshed by
+/// `refresh_lp_price_oracle` so lending protocols can value the LP token
+/// without reading pool internals.
+#[account]
+pub struct LpPriceOracle {
+    pub pool: Pubkey,
+    pub price: u64,
+    pub lp_supply: u64,
+    pub aum_usd: u128,
+    pub publish_time: i64,
+    pub bump: u8,
+}
+
+/// Oracle price of a custody as of `slot`, written by `refresh_price_cache`
+/// so crank-heavy flows can skip re-parsing the oracle within a slot.
+#[account]
+pub struct PriceCache {
+    pub custody: Pubkey,
+    pub price: u64,
+    pub slot: u64,
+    pub bump: u8,
+}
+
+/// Proof that `owner` is in a custody's allowlist under `root`, written by
+/// `join_allowlist`. Goes stale once the admin replaces the root.
+#[account]
+pub struct AllowlistEntry {
+    pub custody: Pubkey,
+    pub owner: Pubkey,
+    pub root: [u8; 32],
+    pub bump: u8,
+}
+
+impl AllowlistEntry {
+    /// Folds a merkle proof for `owner` into a root. Leaves are
+    /// `hashv([owner])` and each level hashes the sorted pair, so proofs
+    /// carry no left/right flags.
+    pub fn compute_root(owner: &Pubkey, proof: &[[u8; 32]]) -> [u8; 32] {
+        proof.iter().fold(hashv(&[owner.as_ref()]).to_bytes(), |node, sibling| {
+            if node <= *sibling {
+                hashv(&[&node, sibling]).to_bytes()
+            } else {
+                hashv(&[sibling, &node]).to_bytes()
+            }
+        })
+    }
+}
+
+/// An LP's cost basis in one pool, kept current by `add_liquidity` and
+/// `remove_liquidity` so rebates and tax exports needn't replay history. LP
+/// tokens moved outside the program are not seen here.
+#[account]
+pub struct DepositReceipt {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    /// LP tokens minted to the owner and not yet burned
+    pub lp_amount: u64,
+    /// USD paid in for `lp_amount`, released pro rata on withdrawal
+    pub cost_basis_usd: u64,
+    pub total_deposited_usd: u64,
+    pub total_withdrawn_usd: u64,
+    pub update_time: i64,
+    pub bump: u8,
+}
+
+impl DepositReceipt {
+    pub fn record_deposit(&mut self, deposit_usd: u64, lp_amount: u64, now: i64) -> Result<()> {
+        self.lp_amount = self
+            .lp_amount
+            .checked_add(lp_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.cost_basis_usd = self
+            .cost_basis_usd
+            .checked_add(deposit_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_deposited_usd = self
+            .total_deposited_usd
+            .checked_add(deposit_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.update_time = now;
+        Ok(())
+    }
+
+    /// Releases the cost basis of `lp_amount` at the average cost; burns of
+    /// LP the receipt never saw release nothing.
+    pub fn record_withdrawal(&mut self, withdrawn_usd: u64, lp_amount: u64, now: i64) -> Result<()> {
+        let lp_amount = lp_amount.min(self.lp_amount);
+        if lp_amount > 0 {
+            let released = (self.cost_basis_usd as u128)
+                .checked_mul(lp_amount as u128)
+                .ok_or(ErrorCode::MathOverflow)?
+                / self.lp_amount as u128;
+            self.cost_basis_usd = self.cost_basis_usd.saturating_sub(released as u64);
+            self.lp_amount -= lp_amount;
+        }
+        self.total_withdrawn_usd = self
+            .total_withdrawn_usd
+            .checked_add(withdrawn_usd)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.update_time = now;
+        Ok(())
+    }
+}
+
+/// LP that `remove_liquidity` couldn't redeem for lack of free custody
+/// liquidity. The tokens sit in the pool's withdrawal escrow and are paid
+/// out at the then-current LP price by `fill_withdrawal_claim`, bounded
+/// below by `min_amount_out`.
+#[account]
+pub struct WithdrawalClaim {
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub custody: Pubkey,
+    /// Escrowed LP tokens still to redeem
+    pub lp_amount: u64,
+    /// Least the remaining `lp_amount` may be redeemed for, carried over from
+    /// the withdrawal's `min_amount_out`
+    pub min_amount_out: u64,
+    pub update_time: i64,
+    pub bump: u8,
+}
+
+/// Approved market maker, managed by the multisig through
+/// `set_market_maker`. While active its `open_position_fee` (bps) replaces
+/// the custody's taker rate on `open_position_public`.
+#[account]
+pub struct MarketMaker {
+    pub owner: Pubkey,
+    pub open_position_fee: u64,
+    pub is_active: bool,
+    pub bump: u8,
+}
+
+/// Slots after which a payer's outstanding count is presumed stale, since
+/// computations whose callback failed never release their slot.
+pub const PAYER_QUOTA_RESET_SLOTS: u64 = 1_500;
+
+/// Computations a payer has queued whose callback hasn't landed yet, so one
+/// client can't monopolize the shared Arcium mempool.
+#[account]
+pub struct PayerQuota {
+    pub payer: Pubkey,
+    pub in_flight: u16,
+    pub last_queue_slot: u64,
+    pub bump: u8,
+}
+
+impl PayerQuota {
+    /// Counts a newly queued computation, failing once `max_in_flight` are
+    /// outstanding.
+    pub fn reserve(&mut self, payer: Pubkey, max_in_flight: u16, bump: u8) -> Result<()> {
+        let slot = Clock::get()?.slot;
+        if slot.saturating_sub(self.last_queue_slot) >= PAYER_QUOTA_RESET_SLOTS {
+            self.in_flight = 0;
+        }
+        require!(
+            max_in_flight == 0 || self.in_flight < max_in_flight,
+            ErrorCode::TooManyInFlightComputations
+        );
+
+        self.payer = payer;
+        self.in_flight = self.in_flight.saturating_add(1);
+        self.last_queue_slot = slot;
+        self.bump = bump;
+        Ok(())
+    }
+
+    /// Frees the slot taken by `reserve` once the callback lands
+    pub fn release(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+}
+
+/// Trader's current x25519 key, written by `set_encryption_key`.
+/// `key_epoch` increases on every rotation.
+#[account]
+pub struct EncryptionKey {
+    pub owner: Pubkey,
+    pub enc_pubkey: [u8; 32],
+    pub key_epoch: u64,
+    pub bump: u8,
+}
+
+/// Config change approved by an executed spl-governance proposal, keyed by
+/// `governance_change_hash`. Created by `approve_governance_change` and
+/// closed by the change it approves.
+#[account]
+pub struct GovernanceApproval {
+    pub realm: Pubkey,
+    pub governance: Pubkey,
+    pub change_hash: [u8; 32],
+    pub bump: u8,
+}
+
+pub const COMP_DEF_REGISTRY_CAPACITY: usize = 16;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct CompDefOverride {
+    /// Offset derived from the circuit name the program was compiled against
+    pub default_offset: u32,
+    /// Offset of the computation definition to use instead
+    pub offset: u32,
+}
+
+/// Computation definitions re-registered under a new name, so a fixed
+/// circuit can be swapped in without a program upgrade.
+#[account]
+pub struct CompDefRegistry {
+    pub overrides: [CompDefOverride; COMP_DEF_REGISTRY_CAPACITY],
+    pub len: u8,
+    pub bump: u8,
+}
+
+impl CompDefRegistry {
+    pub fn resolve(&self, default_offset: u32) -> u32 {
+        self.overrides[..self.len as usize]
+            .iter()
+            .find(|entry| entry.default_offset == default_offset)
+            .map_or(default_offset, |entry| entry.offset)
+    }
+
+    /// Setting an offset back to its default removes the override
+    pub fn set(&mut self, default_offset: u32, offset: u32) -> Result<()> {
+        let len = self.len as usize;
+        let existing = self.overrides[..len]
+            .iter()
+            .position(|entry| entry.default_offset == default_offset);
+
+        match existing {
+            Some(idx) if offset == default_offset => {
+                self.overrides[idx] = self.overrides[len - 1];
+                self.overrides[len - 1] = CompDefOverride::default();
+                self.len -= 1;
+            }
+            Some(idx) => self.overrides[idx].offset = offset,
+            None if offset == default_offset => {}
+            None => {
+                require!(len < COMP_DEF_REGISTRY_CAPACITY, ErrorCode::CompDefRegistryFull);
+                self.overrides[len] = CompDefOverride {
+                    default_offset,
+                    offset,
+                };
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct CompDefInit {
+    pub comp_def_offset: u32,
+    pub initializer: Pubkey,
+    pub init_time: i64,
+}
+
+/// Who initialized each computation definition and when, so a deployment
+/// can be checked against the expected circuit set.
+#[account]
+pub struct CompDefInitLog {
+    pub entries: [CompDefInit; COMP_DEF_REGISTRY_CAPACITY],
+    pub len: u8,
+    pub bump: u8,
+}
+
+impl CompDefInitLog {
+    pub fn initialized(&self) -> &[CompDefInit] {
+        &self.entries[..self.len as usize]
+    }
+
+    /// Re-initializing an offset overwrites its entry
+    pub fn record(&mut self, comp_def_offset: u32, initializer: Pubkey, now: i64) -> Result<()> {
+        let len = self.len as usize;
+        let entry = CompDefInit {
+            comp_def_offset,
+            initializer,
+            init_time: now,
+        };
+        match self.entries[..len]
+            .iter()
+            .position(|entry| entry.comp_def_offset == comp_def_offset)
+        {
+            Some(idx) => self.entries[idx] = entry,
+            None => {
+                require!(len < COMP_DEF_REGISTRY_CAPACITY, ErrorCode::CompDefRegistryFull);
+                self.entries[len] = entry;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[account]
@@ -236,6 +1010,42 @@ pub struct Custody {
     pub borrow_rate_state: BorrowRateState,
     pub bump: u8,
     pub token_account_bump: u8,
+    pub trading_schedule: TradingSchedule,
+    /// x25519 key of the risk desk that receives position health from
+    /// valuations in this market. While all zeroes the health copy goes back
+    /// to the caller instead, see `risk_report_pubkey`.
+    pub risk_manager_enc_pubkey: [u8; 32],
+    /// Merkle root of the owners allowed to open positions against this
+    /// custody during a gated launch. All zeroes disables the gate.
+    pub allowlist_root: [u8; 32],
+    /// Largest move per slot, in bps, assumed for the last cached price while
+    /// the oracle is stale, see `fallback_price`. Zero disables the fallback.
+    pub max_price_drift_bps_per_slot: u64,
+    /// Blocks tokens entering the custody (add_liquidity, swaps into it)
+    /// without touching the rest of the pool
+    pub deposits_frozen: bool,
+    /// Blocks tokens leaving the custody (remove_liquidity, withdrawal
+    /// claims, swaps out of it)
+    pub withdrawals_frozen: bool,
+    /// Seconds a position must stay open before it may close at a profit,
+    /// see `check_min_hold`. Zero disables the check.
+    pub min_hold_period_sec: i64,
+    /// Last 24 hours of trade volume and fees, see `RollingStats`
+    pub rolling_stats: RollingStats,
+}
+
+/// Risk-parameter change queued for a custody, applied by
+/// `execute_custody_config` once `execute_after` has passed.
+#[account]
+pub struct PendingCustodyConfig {
+    pub custody: Pubkey,
+    pub proposer: Pubkey,
+    pub pricing: PricingParams,
+    pub fees: Fees,
+    pub borrow_rate: BorrowRateParams,
+    pub proposed_time: i64,
+    pub execute_after: i64,
+    pub bump: u8,
 }
 
 // Legacy position layout kept for documentation/reference only.
@@ -276,6 +1086,109 @@ pub struct Multisig {
     pub bump: u8,
 }
 
+impl Multisig {
+    pub const MAX_SIGNERS: usize = 6;
+
+    /// Accounts of the instruction being signed, including remaining accounts,
+    /// in the order they were passed.
+    pub fn get_account_infos<'info, T: ToAccountInfos<'info> + Bumps>(
+        ctx: &Context<'_, '_, '_, 'info, T>,
+    ) -> Vec<AccountInfo<'info>> {
+        let mut infos = ctx.accounts.to_account_infos();
+        infos.extend_from_slice(ctx.remaining_accounts);
+        infos
+    }
+
+    pub fn get_instruction_data<T: AnchorSerialize>(params: &T) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        params.serialize(&mut data)?;
+        Ok(data)
+    }
+
+    pub fn set_signers(&mut self, admin_signers: &[AccountInfo], min_signatures: u8) -> Result<()> {
+        require!(
+            !admin_signers.is_empty() && admin_signers.len() <= Self::MAX_SIGNERS,
+            ErrorCode::InvalidInput
+        );
+        require!(
+            min_signatures > 0 && min_signatures as usize <= admin_signers.len(),
+            ErrorCode::InvalidInput
+        );
+
+        let mut signers = [Pubkey::default(); Self::MAX_SIGNERS];
+        for (idx, signer) in admin_signers.iter().enumerate() {
+            require!(!signers[..idx].contains(signer.key), ErrorCode::InvalidInput);
+            // A PDA can never sign, it would make the multisig unreachable
+            require!(signer.key.is_on_curve(), ErrorCode::InvalidSignerKey);
+            signers[idx] = *signer.key;
+        }
+
+        self.num_signers = admin_signers.len() as u8;
+        self.min_signatures = min_signatures;
+        self.signers = signers;
+        self.clear_signatures();
+
+        Ok(())
+    }
+
+    /// Records `signer_account`'s approval of the given instruction and
+    /// returns the number of signatures still required. A different
+    /// instruction resets any approvals collected so far.
+    pub fn sign_multisig(
+        &mut self,
+        signer_account: &AccountInfo,
+        instruction_accounts: &[AccountInfo],
+        instruction_data: &[u8],
+    ) -> Result<u8> {
+        require!(signer_account.is_signer, ErrorCode::MultisigAccountNotAuthorized);
+        let signer_idx = self.signers[..self.num_signers as usize]
+            .iter()
+            .position(|key| key == signer_account.key)
+            .ok_or(ErrorCode::MultisigAccountNotAuthorized)?;
+
+        let instruction_hash = Self::get_instruction_hash(instruction_accounts, instruction_data);
+        if instruction_hash != self.instruction_hash
+            || instruction_accounts.len() != self.instruction_accounts_len as usize
+            || instruction_data.len() != self.instruction_data_len as usize
+        {
+            self.clear_signatures();
+            self.instruction_accounts_len = instruction_accounts.len() as u8;
+            self.instruction_data_len = instruction_data.len() as u16;
+            self.instruction_hash = instruction_hash;
+        } else {
+            require!(self.signed[signer_idx] == 0, ErrorCode::MultisigAlreadySigned);
+        }
+
+        self.signed[signer_idx] = 1;
+        self.num_signed += 1;
+
+        if self.num_signed >= self.min_signatures {
+            self.clear_signatures();
+            Ok(0)
+        } else {
+            Ok(self.min_signatures - self.num_signed)
+        }
+    }
+
+    fn get_instruction_hash(instruction_accounts: &[AccountInfo], instruction_data: &[u8]) -> u64 {
+        let mut slices = Vec::with_capacity(instruction_accounts.len() + 1);
+        for account in instruction_accounts {
+            slices.push(account.key.as_ref());
+        }
+        slices.push(instruction_data);
+        let hash = hashv(&slices);
+        u64::from_le_bytes(hash.to_bytes()[..8].try_into().unwrap())
+    }
+
+    fn clear_signatures(&mut self) {
+        self.num_signed = 0;
+        self.signed = [0; Self::MAX_SIGNERS];
+        self.instruction_accounts_len = 0;
+        self.instruction_data_len = 0;
+        self.instruction_hash = 0;
+    }
+}
+
 #[account]
 pub struct CustomOracle {
     pub price: u64,
@@ -291,6 +1204,8 @@ pub struct OpenPositionPublicParams {
     pub collateral: u64,
     pub size: u64,
     pub side: u8,
+    /// Opaque tag stored on the position, see `Position::client_tag`
+    pub client_tag: Option<[u8; 32]>,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
@@ -382,7 +1297,7 @@ pub struct MarketState {
     pub bump: u8,
 }
 
-/// TraderState account - Per-trader encrypted risk state and Confidential SPL account references
+/// TraderState account - Per-trader (per sub-account) encrypted risk state and Confidential SPL account references
 #[account]
 pub struct TraderState {
     pub trader: Pubkey,
@@ -401,6 +1316,11 @@ pub struct TraderState {
     pub isolated_margin_accounts: Vec<Pubkey>, // Per-market isolated margin (optional)
     
     pub bump: u8,
+    
+    // Appended: accounts created before sub-accounts were sized by
+    // `size_of::<TraderState>()`, which leaves zeroed bytes past the
+    // serialized fields, so they read back as sub-account 0
+    pub sub_account_id: u16,            // Isolated sub-account index (0 = default)
 }
 
 /// EpochState account - Per-epoch order batch and settlement status
@@ -110,13 +110,14 @@ pub fn unwrap_from_confidential(
     Ok(())
 }
 
-/// Helper to get the Confidential SPL account PDA for a trader
+/// Helper to get the Confidential SPL account PDA for a trader sub-account
 pub fn get_confidential_account_pda(
     program_id: &Pubkey,
     trader: &Pubkey,
+    sub_account_id: u16,
 ) -> (Pubkey, u8) {
     Pubkey::find_program_address(
-        &[b"confidential_account", trader.as_ref()],
+        &[b"confidential_account", trader.as_ref(), &sub_account_id.to_le_bytes()],
         program_id,
     )
 }
@@ -0,0 +1,205 @@
+//! Amount conversions between custodies
+//!
+//! Token amounts are integers in the mint's native units and oracle prices
+//! are `price * 10^exponent` USD per whole token. Every conversion goes
+//! through a single power of ten so that pairing, say, a 6-decimals and a
+//! 9-decimals asset loses no more precision than the final division.
+
+use anchor_lang::prelude::*;
+
+use crate::{state::OraclePrice, ErrorCode};
+
+/// Decimals of USD values (`aum_usd`, position sizes) and of the LP token
+pub const USD_DECIMALS: u8 = 6;
+
+/// Value of a token amount in USD with `USD_DECIMALS` decimals
+pub fn token_to_usd(amount: u64, decimals: u8, price: &OraclePrice) -> Result<u64> {
+    // usd = amount * price * 10^(exponent + USD_DECIMALS - decimals)
+    let exponent = price.exponent + USD_DECIMALS as i32 - decimals as i32;
+    scale(
+        (amount as u128)
+            .checked_mul(price.price as u128)
+            .ok_or(ErrorCode::MathOverflow)?,
+        1,
+        exponent,
+    )
+}
+
+/// Token amount worth a USD value with `USD_DECIMALS` decimals
+pub fn usd_to_token(usd: u64, decimals: u8, price: &OraclePrice) -> Result<u64> {
+    require!(price.price > 0, ErrorCode::InvalidInput);
+    // amount = usd / price * 10^(decimals - exponent - USD_DECIMALS)
+    let exponent = decimals as i32 - price.exponent - USD_DECIMALS as i32;
+    scale(usd as u128, price.price as u128, exponent)
+}
+
+/// Amount of the dispensed token worth `amount` of the received token
+pub fn convert_token_amount(
+    amount: u64,
+    from_decimals: u8,
+    from_price: &OraclePrice,
+    to_decimals: u8,
+    to_price: &OraclePrice,
+) -> Result<u64> {
+    require!(to_price.price > 0, ErrorCode::InvalidInput);
+    // out = amount * from_price / to_price
+    //       * 10^(from_exponent - to_exponent + to_decimals - from_decimals)
+    let exponent = from_price.exponent - to_price.exponent + to_decimals as i32
+        - from_decimals as i32;
+    scale(
+        (amount as u128)
+            .checked_mul(from_price.price as u128)
+            .ok_or(ErrorCode::MathOverflow)?,
+        to_price.price as u128,
+        exponent,
+    )
+}
+
+/// LP tokens minted for a USD value at `lp_price` (see `Pool::get_lp_token_price`)
+pub fn usd_to_lp(usd: u64, lp_price: u64) -> Result<u64> {
+    require!(lp_price > 0, ErrorCode::InvalidInput);
+    scale(usd as u128, lp_price as u128, USD_DECIMALS as i32)
+}
+
+/// USD value of an LP token amount at `lp_price`
+pub fn lp_to_usd(lp_amount: u64, lp_price: u64) -> Result<u64> {
+    scale(
+        (lp_amount as u128)
+            .checked_mul(lp_price as u128)
+            .ok_or(ErrorCode::MathOverflow)?,
+        1,
+        -(USD_DECIMALS as i32),
+    )
+}
+
+/// `numerator / denominator * 10^exponent`, rounded down
+fn scale(numerator: u128, denominator: u128, exponent: i32) -> Result<u64> {
+    let factor = 10u128
+        .checked_pow(exponent.unsigned_abs())
+        .ok_or(ErrorCode::MathOverflow)?;
+    let value = if exponent >= 0 {
+        numerator
+            .checked_mul(factor)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(denominator)
+    } else {
+        numerator.checked_div(
+            denominator
+                .checked_mul(factor)
+                .ok_or(ErrorCode::MathOverflow)?,
+        )
+    }
+    .ok_or(ErrorCode::MathOverflow)?;
+    Ok(u64::try_from(value).map_err(|_| ErrorCode::MathOverflow)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DECIMALS: [u8; 6] = [0, 2, 6, 8, 9, 12];
+    const EXPONENTS: [i32; 5] = [0, -2, -6, -8, -10];
+
+    fn price(usd: u64, exponent: i32) -> OraclePrice {
+        OraclePrice {
+            price: usd * 10u64.pow(exponent.unsigned_abs()),
+            exponent,
+        }
+    }
+
+    fn whole(tokens: u64, decimals: u8) -> u64 {
+        tokens * 10u64.pow(decimals as u32)
+    }
+
+    #[test]
+    fn token_to_usd_all_combinations() {
+        for decimals in DECIMALS {
+            for exponent in EXPONENTS {
+                // 3 tokens at $25
+                let usd = token_to_usd(whole(3, decimals), decimals, &price(25, exponent)).unwrap();
+                assert_eq!(usd, 75_000000, "decimals {} exponent {}", decimals, exponent);
+            }
+        }
+    }
+
+    #[test]
+    fn usd_to_token_all_combinations() {
+        for decimals in DECIMALS {
+            for exponent in EXPONENTS {
+                let amount = usd_to_token(75_000000, decimals, &price(25, exponent)).unwrap();
+                assert_eq!(amount, whole(3, decimals), "decimals {} exponent {}", decimals, exponent);
+            }
+        }
+    }
+
+    #[test]
+    fn convert_all_combinations() {
+        for from_decimals in DECIMALS {
+            for to_decimals in DECIMALS {
+                for from_exponent in EXPONENTS {
+                    for to_exponent in EXPONENTS {
+                        // 4 tokens at $50 buy 10 tokens at $20
+                        let out = convert_token_amount(
+                            whole(4, from_decimals),
+                            from_decimals,
+                            &price(50, from_exponent),
+                            to_decimals,
+                            &price(20, to_exponent),
+                        )
+                        .unwrap();
+                        assert_eq!(
+                            out,
+                            whole(10, to_decimals),
+                            "decimals {} -> {}, exponents {} -> {}",
+                            from_decimals,
+                            to_decimals,
+                            from_exponent,
+                            to_exponent
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn convert_matches_usd_round_trip() {
+        for from_decimals in DECIMALS {
+            for to_decimals in DECIMALS {
+                let from_price = price(2, -8);
+                let to_price = price(1, -6);
+                let amount = whole(7, from_decimals);
+                let direct =
+                    convert_token_amount(amount, from_decimals, &from_price, to_decimals, &to_price)
+                        .unwrap();
+                let usd = token_to_usd(amount, from_decimals, &from_price).unwrap();
+                let via_usd = usd_to_token(usd, to_decimals, &to_price).unwrap();
+                assert_eq!(direct, via_usd, "decimals {} -> {}", from_decimals, to_decimals);
+            }
+        }
+    }
+
+    #[test]
+    fn sub_unit_amounts_round_down() {
+        // 1 base unit of a 9-decimals token at $1 is below one micro-dollar
+        assert_eq!(token_to_usd(1, 9, &price(1, -8)).unwrap(), 0);
+        // $0.000001 of a $3 6-decimals token
+        assert_eq!(usd_to_token(1, 6, &price(3, -8)).unwrap(), 0);
+    }
+
+    #[test]
+    fn lp_round_trip() {
+        for lp_price in [1_000000, 1_500000, 250_000000] {
+            let lp = usd_to_lp(300_000000, lp_price).unwrap();
+            assert_eq!(lp_to_usd(lp, lp_price).unwrap(), 300_000000);
+        }
+    }
+
+    #[test]
+    fn rejects_zero_price_and_overflow() {
+        assert!(usd_to_token(1, 6, &price(0, -8)).is_err());
+        assert!(convert_token_amount(1, 6, &price(1, -8), 6, &price(0, -8)).is_err());
+        assert!(usd_to_lp(1, 0).is_err());
+        assert!(token_to_usd(u64::MAX, 0, &price(1_000, 0)).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use anchor_lang::{prelude::*, solana_program::hash::hashv};
+
+use crate::{ErrorCode, GovernanceApproval, Perpetuals};
+
+/// Offset into spl-governance `GovernanceV2` accounts, which start with a
+/// one-byte account type discriminator.
+const GOVERNANCE_REALM_OFFSET: usize = 1;
+
+/// `GovernanceAccountType::GovernanceV2`
+const GOVERNANCE_V2_ACCOUNT_TYPE: u8 = 18;
+
+/// Hash a governance approval is keyed by: the approved instruction's
+/// discriminator followed by its serialized arguments.
+pub fn governance_change_hash(discriminator: &[u8], data: &[u8]) -> [u8; 32] {
+    hashv(&[discriminator, data]).to_bytes()
+}
+
+/// Checks that `governance` is a `GovernanceV2` account of the configured
+/// realm, owned by the configured governance program.
+pub fn check_governance_account(perpetuals: &Perpetuals, governance: &AccountInfo) -> Result<()> {
+    require_keys_eq!(
+        *governance.owner,
+        perpetuals.governance_program,
+        ErrorCode::InvalidGovernanceProposal
+    );
+
+    let governance_data = governance.try_borrow_data()?;
+    require!(
+        governance_data.len() >= GOVERNANCE_REALM_OFFSET + 32
+            && governance_data[0] == GOVERNANCE_V2_ACCOUNT_TYPE,
+        ErrorCode::InvalidGovernanceProposal
+    );
+    let realm = Pubkey::try_from(&governance_data[GOVERNANCE_REALM_OFFSET..GOVERNANCE_REALM_OFFSET + 32])
+        .map_err(|_| ErrorCode::InvalidGovernanceProposal)?;
+    require_keys_eq!(realm, perpetuals.governance_realm, ErrorCode::InvalidGovernanceProposal);
+
+    Ok(())
+}
+
+/// Checks that an approval recorded by `approve_governance_change` for the
+/// configured realm covers exactly this change, then closes it so it can't
+/// approve anything else. A no-op when no realm is configured.
+pub fn require_governance_approval<'info>(
+    perpetuals: &Perpetuals,
+    approval: Option<&Account<'info, GovernanceApproval>>,
+    change_hash: [u8; 32],
+    rent_receiver: AccountInfo<'info>,
+) -> Result<()> {
+    if perpetuals.governance_realm == Pubkey::default() {
+        return Ok(());
+    }
+
+    let approval = approval.ok_or(ErrorCode::GovernanceApprovalMissing)?;
+    require_keys_eq!(
+        approval.realm,
+        perpetuals.governance_realm,
+        ErrorCode::InvalidGovernanceProposal
+    );
+    require!(
+        approval.change_hash == change_hash,
+        ErrorCode::GovernanceProposalNotApproved
+    );
+
+    approval.close(rent_receiver)
+}
@@ -1,8 +1,12 @@
 pub mod confidential_spl;
+pub mod conversion;
 pub mod order_matching;
 pub mod encryption;
+pub mod governance;
 
 pub use confidential_spl::*;
+pub use conversion::*;
 pub use order_matching::*;
 pub use encryption::*;
+pub use governance::*;
 
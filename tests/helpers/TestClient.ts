@@ -139,7 +139,11 @@ export class TestClient {
     }
 
     await this.program.methods
-      .addPool({ name: params.name })
+      .addPool({
+        name: params.name,
+        lpTokenSymbol: params.lpTokenSymbol || "",
+        lpTokenUri: params.lpTokenUri || "",
+      })
       .accountsPartial({
         admin: this.admin.publicKey,
         multisig: this.multisigAccount,
@@ -150,6 +154,9 @@ export class TestClient {
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
         rent: SYSVAR_RENT_PUBKEY,
+        // The local validator doesn't load the token metadata program
+        lpTokenMetadata: null,
+        metadataProgram: null,
       })
       .signers([this.admin])
       .rpc();
@@ -234,8 +241,9 @@ export class TestClient {
           minInitialLeverage: new anchor.BN(10000),
           maxInitialLeverage: new anchor.BN(100000),
           maxLeverage: new anchor.BN(100000),
-          maxPayoffMult: new anchor.BN(10000),
-          maxUtilization: new anchor.BN(800000),
+          // Profit cap in bps of collateral; 10x keeps test closes uncapped
+          maxPayoffMult: new anchor.BN(params.maxPayoffMult || 100000),
+          maxUtilization: new anchor.BN(params.maxUtilization ?? 800000),
           maxPositionLockedUsd: new anchor.BN("18446744073709551615"),
           maxTotalLockedUsd: new anchor.BN("18446744073709551615"),
         },
@@ -321,28 +329,90 @@ export class TestClient {
       this.program.programId
     )[0];
 
+    // Updates are limited to a 20% move per call, so walk large changes in
+    // smaller steps from the currently stored price.
+    let currentPrice = new anchor.BN(0);
+    try {
+      const oracleAccount = await this.program.account.customOracle.fetch(customOracle);
+      currentPrice = oracleAccount.price;
+    } catch (e) {
+      // Oracle not created yet; the first price is accepted as-is
+    }
+
+    const steps: anchor.BN[] = [];
+    while (!currentPrice.isZero() && !currentPrice.eq(params.price)) {
+      const maxStep = currentPrice.muln(15).divn(100);
+      const diff = params.price.sub(currentPrice);
+      if (diff.abs().lte(maxStep)) {
+        break;
+      }
+      currentPrice = diff.isNeg() ? currentPrice.sub(maxStep) : currentPrice.add(maxStep);
+      steps.push(currentPrice);
+    }
+    steps.push(params.price);
+
+    for (const price of steps) {
+      await this.program.methods
+        .setCustomOraclePrice({
+          price,
+          expo: params.expo || -8,
+          conf: params.conf || new anchor.BN(0),
+          ema: price,
+          publishTime: params.publishTime || new anchor.BN(Date.now() / 1000),
+          overridePriceBand: false,
+        })
+        .accountsPartial({
+          admin: this.admin.publicKey,
+          multisig: this.multisigAccount,
+          customOracle: customOracle,
+          custody: custodyInfo.account,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([this.admin])
+        .rpc();
+    }
+
+    // Update the custodyInfo to point to the new custom oracle
+    custodyInfo.oracleAccount = customOracle;
+    this.custodies.set(`${params.poolName}-${params.symbol}`, custodyInfo);
+
+    console.log(`Oracle price set for ${params.symbol}: ${params.price.toString()}`);
+  }
+
+  async useCustomOracle(poolName: string, symbol: string): Promise<void> {
+    const poolInfo = this.pools.get(poolName);
+    const custodyInfo = this.custodies.get(`${poolName}-${symbol}`);
+    if (!poolInfo || !custodyInfo) {
+      throw new Error(`Custody ${poolName}-${symbol} not found`);
+    }
+
+    const custody = await this.program.account.custody.fetch(custodyInfo.account);
+
     await this.program.methods
-      .setCustomOraclePrice({
-        price: params.price,
-        expo: params.expo || -8,
-        conf: params.conf || new anchor.BN(0),
-        ema: params.price,
-        publishTime: params.publishTime || new anchor.BN(Date.now() / 1000),
+      .setCustodyConfig({
+        isStable: custody.isStable,
+        isVirtual: custody.isVirtual,
+        oracle: {
+          oracleAccount: custodyInfo.oracleAccount,
+          oracleType: custody.oracle.oracleType,
+          oracleAuthority: custody.oracle.oracleAuthority,
+          maxPriceError: custody.oracle.maxPriceError,
+          maxPriceAgeSec: custody.oracle.maxPriceAgeSec,
+        },
+        pricing: custody.pricing,
+        permissions: custody.permissions,
+        fees: custody.fees,
+        borrowRate: custody.borrowRate,
+        ratios: [],
       })
       .accountsPartial({
         admin: this.admin.publicKey,
-        customOracle: customOracle,
+        multisig: this.multisigAccount,
+        pool: poolInfo.account,
         custody: custodyInfo.account,
-        systemProgram: SystemProgram.programId,
       })
       .signers([this.admin])
-      .rpc();
-
-    // Update the custodyInfo to point to the new custom oracle
-    custodyInfo.oracleAccount = customOracle;
-    this.custodies.set(`${params.poolName}-${params.symbol}`, custodyInfo);
-
-    console.log(`Oracle price set for ${params.symbol}: ${params.price.toString()}`);
+      .rpc({ commitment: "confirmed" });
   }
 
   async mintTokensToUser(
@@ -625,6 +695,8 @@ export interface InitParams {
 
 export interface AddPoolParams {
   name: string;
+  lpTokenSymbol?: string;
+  lpTokenUri?: string;
 }
 
 export interface AddCustodyParams {
@@ -634,6 +706,8 @@ export interface AddCustodyParams {
   decimals?: number;
   isStable?: boolean;
   oracleAccount?: PublicKey;
+  maxUtilization?: number;
+  maxPayoffMult?: number;
 }
 
 export interface SetOraclePriceParams {
@@ -0,0 +1,349 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { PublicKey, Connection } from "@solana/web3.js";
+import { Perpetuals } from "../target/types/perpetuals";
+import { expect } from "chai";
+import * as fs from "fs";
+import * as os from "os";
+import { TestClient, CustodyInfo, PoolInfo } from "./helpers/TestClient";
+import {
+  getAccount,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+
+/**
+ * Full Lifecycle Test Suite
+ *
+ * Drives a single pool through
+ *   init → add_pool → add_custody → add_liquidity → open_position
+ *   → add/remove collateral → close → withdraw_fees
+ * using the public instruction variants in place of the MPC computations,
+ * and checks custody asset and fee accounting after every step.
+ *
+ * To run tests:
+ *   arcium test
+ */
+
+function readKpJson(path: string) {
+  const kpJson = JSON.parse(fs.readFileSync(path, "utf-8"));
+  return anchor.web3.Keypair.fromSecretKey(new Uint8Array(kpJson));
+}
+
+function getPositionPDA(
+  programId: PublicKey,
+  owner: PublicKey,
+  positionId: anchor.BN
+): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("position"),
+      owner.toBuffer(),
+      positionId.toArrayLike(Buffer, "le", 8),
+    ],
+    programId
+  )[0];
+}
+
+function decodePlaintext(field: number[]): bigint {
+  return Buffer.from(field.slice(0, 8)).readBigUInt64LE(0);
+}
+
+interface CustodySnapshot {
+  owned: bigint;
+  locked: bigint;
+  collateral: bigint;
+  protocolFees: bigint;
+  tokenBalance: bigint;
+  openPositionFees: bigint;
+}
+
+describe("Perpetuals DEX - Full Lifecycle", () => {
+  const admin = readKpJson(`${os.homedir()}/.config/solana/id.json`);
+
+  const rpcUrl = process.env.ANCHOR_PROVIDER_URL ||
+                 process.env.RPC_URL ||
+                 "http://127.0.0.1:8899";
+
+  const connection = new Connection(rpcUrl, {
+    commitment: "confirmed",
+    confirmTransactionInitialTimeout: 60000,
+  });
+
+  const wallet = new anchor.Wallet(admin);
+  const provider = new anchor.AnchorProvider(connection, wallet, {
+    commitment: "confirmed",
+    skipPreflight: false,
+  });
+
+  anchor.setProvider(provider);
+  const program = anchor.workspace.Perpetuals as Program<Perpetuals>;
+
+  const poolName = "lifecyclepool";
+  const positionId = new anchor.BN(Date.now());
+  const collateral = new anchor.BN(2000_000000); // 2,000 USDC
+  const size = new anchor.BN(10000_000000); // $10,000, 5x leverage
+  const collateralDelta = new anchor.BN(1_000000); // 1 USDC
+
+  let testClient: TestClient;
+  let pool: PoolInfo;
+  let solCustody: CustodyInfo;
+  let usdcCustody: CustodyInfo;
+  let trader: anchor.web3.Keypair;
+  let traderUsdcAccount: PublicKey;
+  let traderLpAccount: PublicKey;
+  let positionPda: PublicKey;
+
+  async function snapshot(custody: CustodyInfo): Promise<CustodySnapshot> {
+    const data = await program.account.custody.fetch(custody.account);
+    const tokenAccount = await getAccount(provider.connection, custody.tokenAccount);
+    return {
+      owned: BigInt(data.assets.owned.toString()),
+      locked: BigInt(data.assets.locked.toString()),
+      collateral: BigInt(data.assets.collateral.toString()),
+      protocolFees: BigInt(data.assets.protocolFees.toString()),
+      tokenBalance: tokenAccount.amount,
+      openPositionFees: BigInt(data.collectedFees.openPositionUsd.toString()),
+    };
+  }
+
+  // Invariants that must hold for every custody after every instruction
+  function checkInvariants(s: CustodySnapshot) {
+    expect(s.tokenBalance >= s.owned + s.collateral, "custody is undercollateralized").to.be.true;
+    expect(s.protocolFees <= s.owned, "protocol fees exceed owned assets").to.be.true;
+  }
+
+  function positionAccounts() {
+    return {
+      owner: trader.publicKey,
+      perpetuals: testClient.perpetualsAccount,
+      pool: pool.account,
+      position: positionPda,
+      custody: solCustody.account,
+      custodyOracleAccount: solCustody.oracleAccount,
+      collateralCustody: usdcCustody.account,
+      collateralCustodyOracleAccount: usdcCustody.oracleAccount,
+    };
+  }
+
+  before(async () => {
+    testClient = new TestClient(program, provider, admin);
+
+    await testClient.init();
+    pool = await testClient.addPool({ name: poolName });
+    solCustody = await testClient.addCustody({
+      poolName,
+      symbol: "SOL",
+      decimals: 9,
+      isStable: false,
+    });
+    usdcCustody = await testClient.addCustody({
+      poolName,
+      symbol: "USDC",
+      decimals: 6,
+      isStable: true,
+      maxUtilization: 8000,
+    });
+
+    await testClient.setCustomOraclePrice({
+      poolName,
+      symbol: "SOL",
+      price: new anchor.BN(50000_00000000),
+    });
+    await testClient.setCustomOraclePrice({
+      poolName,
+      symbol: "USDC",
+      price: new anchor.BN(1_00000000),
+    });
+    await testClient.useCustomOracle(poolName, "SOL");
+    await testClient.useCustomOracle(poolName, "USDC");
+    solCustody = testClient.custodies.get(`${poolName}-SOL`)!;
+    usdcCustody = testClient.custodies.get(`${poolName}-USDC`)!;
+
+    trader = anchor.web3.Keypair.generate();
+    await testClient.airdrop(trader.publicKey, 5_000_000_000);
+
+    traderUsdcAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      trader,
+      usdcCustody.mint,
+      trader.publicKey
+    )).address;
+    traderLpAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      trader,
+      pool.lpTokenMint,
+      trader.publicKey
+    )).address;
+
+    await mintTo(
+      provider.connection,
+      admin,
+      usdcCustody.mint,
+      traderUsdcAccount,
+      admin,
+      100_000_000000 // 100,000 USDC
+    );
+
+    positionPda = getPositionPDA(program.programId, trader.publicKey, positionId);
+
+    checkInvariants(await snapshot(usdcCustody));
+  });
+
+  it("Adds liquidity", async () => {
+    const amountIn = new anchor.BN(50_000_000000);
+    const before = await snapshot(usdcCustody);
+
+    await program.methods
+      .addLiquidity({
+        amountIn,
+        minLpAmountOut: new anchor.BN(1),
+      })
+      .accountsPartial({
+        owner: trader.publicKey,
+        perpetuals: testClient.perpetualsAccount,
+        pool: pool.account,
+        custody: usdcCustody.account,
+        custodyOracleAccount: usdcCustody.oracleAccount,
+        custodyTokenAccount: usdcCustody.tokenAccount,
+        lpTokenMint: pool.lpTokenMint,
+        fundingAccount: traderUsdcAccount,
+        lpTokenAccount: traderLpAccount,
+      })
+      .signers([trader])
+      .rpc({ commitment: "confirmed" });
+
+    const after = await snapshot(usdcCustody);
+    checkInvariants(after);
+    expect(after.owned - before.owned).to.equal(BigInt(amountIn.toString()));
+    expect(after.tokenBalance - before.tokenBalance).to.equal(BigInt(amountIn.toString()));
+
+    const lpAccount = await getAccount(provider.connection, traderLpAccount);
+    expect(Number(lpAccount.amount)).to.be.greaterThan(0);
+  });
+
+  it("Opens a position", async () => {
+    const before = await snapshot(usdcCustody);
+
+    await program.methods
+      .openPositionPublic(positionId, {
+        price: new anchor.BN(50000_00000000),
+        collateral,
+        size,
+        side: 0,
+        clientTag: null,
+      })
+      .accountsPartial({
+        ...positionAccounts(),
+        fundingAccount: traderUsdcAccount,
+        collateralCustodyTokenAccount: usdcCustody.tokenAccount,
+      })
+      .signers([trader])
+      .rpc({ commitment: "confirmed" });
+
+    const after = await snapshot(usdcCustody);
+    checkInvariants(after);
+
+    const fee = after.openPositionFees - before.openPositionFees;
+    expect(fee > 0n).to.be.true;
+    expect(after.collateral - before.collateral).to.equal(BigInt(collateral.toString()));
+    expect(after.locked - before.locked).to.equal(BigInt(size.toString()));
+    expect(after.tokenBalance - before.tokenBalance).to.equal(BigInt(collateral.toString()) + fee);
+    expect(after.protocolFees > before.protocolFees).to.be.true;
+    expect(after.protocolFees - before.protocolFees <= fee).to.be.true;
+
+    const position = await program.account.position.fetch(positionPda);
+    expect(decodePlaintext(position.sizeUsdEncrypted)).to.equal(BigInt(size.toString()));
+    expect(decodePlaintext(position.collateralUsdEncrypted)).to.equal(BigInt(collateral.toString()));
+  });
+
+  it("Adds and removes collateral", async () => {
+    const before = await snapshot(usdcCustody);
+    const positionBefore = await program.account.position.fetch(positionPda);
+
+    await program.methods
+      .addCollateralPublic(positionId, { collateral: collateralDelta })
+      .accountsPartial({
+        ...positionAccounts(),
+        fundingAccount: traderUsdcAccount,
+        collateralCustodyTokenAccount: usdcCustody.tokenAccount,
+      })
+      .signers([trader])
+      .rpc({ commitment: "confirmed" });
+
+    const afterAdd = await snapshot(usdcCustody);
+    checkInvariants(afterAdd);
+    expect(afterAdd.collateral - before.collateral).to.equal(BigInt(collateralDelta.toString()));
+    expect(afterAdd.tokenBalance - before.tokenBalance).to.equal(BigInt(collateralDelta.toString()));
+    expect(afterAdd.protocolFees).to.equal(before.protocolFees);
+
+    await program.methods
+      .removeCollateralPublic(positionId, { collateral: collateralDelta })
+      .accountsPartial({
+        ...positionAccounts(),
+        fundingAccount: traderUsdcAccount,
+        collateralCustodyTokenAccount: usdcCustody.tokenAccount,
+      })
+      .signers([trader])
+      .rpc({ commitment: "confirmed" });
+
+    const afterRemove = await snapshot(usdcCustody);
+    checkInvariants(afterRemove);
+    expect(afterRemove.collateral).to.equal(before.collateral);
+    expect(afterRemove.protocolFees).to.equal(before.protocolFees);
+
+    // A matching add and remove leaves the position where it started
+    const positionAfter = await program.account.position.fetch(positionPda);
+    expect(decodePlaintext(positionAfter.collateralUsdEncrypted))
+      .to.equal(decodePlaintext(positionBefore.collateralUsdEncrypted));
+  });
+
+  it("Closes the position", async () => {
+    const before = await snapshot(usdcCustody);
+
+    await program.methods
+      .closePositionPublic(positionId)
+      .accountsPartial({ ...positionAccounts(), authority: trader.publicKey })
+      .signers([trader])
+      .rpc({ commitment: "confirmed" });
+
+    const after = await snapshot(usdcCustody);
+    checkInvariants(after);
+    expect(after.protocolFees).to.equal(before.protocolFees);
+    expect(after.owned).to.equal(before.owned);
+    expect(before.locked - after.locked).to.equal(BigInt(size.toString()));
+
+    const position = await program.account.position.fetch(positionPda);
+    expect(decodePlaintext(position.sizeUsdEncrypted)).to.equal(0n);
+    expect(decodePlaintext(position.collateralUsdEncrypted)).to.equal(0n);
+  });
+
+  it("Withdraws protocol fees", async () => {
+    const before = await snapshot(usdcCustody);
+    expect(before.protocolFees > 0n).to.be.true;
+    const receiverBefore = await getAccount(provider.connection, traderUsdcAccount);
+
+    await program.methods
+      .withdrawFees({ amount: new anchor.BN(0) })
+      .accountsPartial({
+        admin: admin.publicKey,
+        multisig: testClient.multisigAccount,
+        transferAuthority: testClient.transferAuthorityAccount,
+        custody: usdcCustody.account,
+        custodyTokenAccount: usdcCustody.tokenAccount,
+        receivingAccount: traderUsdcAccount,
+      })
+      .signers([admin])
+      .rpc({ commitment: "confirmed" });
+
+    const after = await snapshot(usdcCustody);
+    checkInvariants(after);
+    expect(after.protocolFees).to.equal(0n);
+    expect(before.owned - after.owned).to.equal(before.protocolFees);
+
+    // Fees leave the custody through the transfer authority signed transfer
+    const receiverAfter = await getAccount(provider.connection, traderUsdcAccount);
+    expect(before.tokenBalance - after.tokenBalance).to.equal(before.protocolFees);
+    expect(receiverAfter.amount - receiverBefore.amount).to.equal(before.protocolFees);
+  });
+});
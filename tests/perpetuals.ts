@@ -25,6 +25,7 @@ import {
 import * as fs from "fs";
 import * as os from "os";
 import { expect } from "chai";
+import { TestClient, CustodyInfo, PoolInfo } from "./helpers/TestClient";
 
 /**
  * Configuration for Arcium Perpetuals DEX Tests
@@ -125,6 +126,24 @@ describe("Perpetuals DEX", () => {
   }
 
 
+  // Positions lock their notional in a collateral custody
+  let pool: PoolInfo;
+  let collateralCustody: CustodyInfo;
+
+  before(async () => {
+    const testClient = new TestClient(program, provider, owner);
+    await testClient.init();
+    pool = await testClient.addPool({ name: "encryptedpool" });
+    collateralCustody = await testClient.addCustody({
+      poolName: "encryptedpool",
+      symbol: "USDC",
+      decimals: 6,
+      isStable: true,
+      // No LP liquidity in this suite, so positions can't be capped by it
+      maxUtilization: 0,
+    });
+  });
+
   type Event = anchor.IdlEvents<(typeof program)["idl"]>;
   const awaitEvent = async <E extends keyof Event>(
     eventName: E,
@@ -146,6 +165,30 @@ describe("Perpetuals DEX", () => {
     return event;
   };
 
+  // Events emitted with emit_cpi! are carried by the callback transaction's
+  // inner instructions rather than its logs
+  const getCpiEvent = async <E extends keyof Event>(
+    signature: string,
+    eventName: E
+  ): Promise<Event[E]> => {
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    for (const inner of tx?.meta?.innerInstructions ?? []) {
+      for (const ix of inner.instructions) {
+        const data = anchor.utils.bytes.bs58.decode(ix.data);
+        const event = program.coder.events.decode(
+          anchor.utils.bytes.base64.encode(Buffer.from(data.subarray(8)))
+        );
+        if (event?.name === eventName) {
+          return event.data as Event[E];
+        }
+      }
+    }
+    throw new Error(`Event ${String(eventName)} not found in ${signature}`);
+  };
+
   it("Initializes open_position computation definition", async () => {
     console.log("Initializing open_position computation definition");
 
@@ -288,7 +331,10 @@ describe("Perpetuals DEX", () => {
         Array.from(collateralCiphertext[0]),
         Array.from(publicKey),
         new anchor.BN(deserializeLE(sizeNonce).toString()),
-        new anchor.BN(deserializeLE(collateralNonce).toString())
+        new anchor.BN(deserializeLE(collateralNonce).toString()),
+        null,
+        new anchor.BN(deserializeLE(randomBytes(16)).toString()),
+        null
       )
       .accountsPartial({
         owner: owner.publicKey,
@@ -306,6 +352,8 @@ describe("Perpetuals DEX", () => {
           Buffer.from(compDefAccOffset).readUInt32LE()
         ),
         position: positionPda,
+        pool: pool.account,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -483,7 +531,10 @@ describe("Perpetuals DEX", () => {
         Array.from(collateralCiphertext[0]),
         Array.from(publicKey),
         new anchor.BN(deserializeLE(sizeNonce).toString()),
-        new anchor.BN(deserializeLE(collateralNonce).toString())
+        new anchor.BN(deserializeLE(collateralNonce).toString()),
+        null,
+        new anchor.BN(deserializeLE(randomBytes(16)).toString()),
+        null
       )
       .accountsPartial({
         owner: owner.publicKey,
@@ -495,6 +546,8 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(compDefAccOffset1).readUInt32LE()),
         position: positionPda,
+        pool: pool.account,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -521,7 +574,8 @@ describe("Perpetuals DEX", () => {
         new anchor.BN(positionId.toString()),
         new anchor.BN(currentPrice.toString()),
         Array.from(publicKey),
-        new anchor.BN(deserializeLE(valueNonce).toString())
+        new anchor.BN(deserializeLE(valueNonce).toString()),
+        new anchor.BN(deserializeLE(randomBytes(16)).toString())
       )
       .accountsPartial({
         payer: owner.publicKey,
@@ -532,6 +586,7 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(compDefAccOffset2).readUInt32LE()),
         position: positionPda,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -661,7 +716,10 @@ describe("Perpetuals DEX", () => {
         Array.from(collateralCiphertext[0]),
         Array.from(publicKey),
         new anchor.BN(deserializeLE(sizeNonce).toString()),
-        new anchor.BN(deserializeLE(collateralNonce).toString())
+        new anchor.BN(deserializeLE(collateralNonce).toString()),
+        null,
+        new anchor.BN(deserializeLE(randomBytes(16)).toString()),
+        null
       )
       .accountsPartial({
         owner: owner.publicKey,
@@ -673,6 +731,8 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(compDefAccOffset1).readUInt32LE()),
         position: positionPda,
+        pool: pool.account,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -701,10 +761,12 @@ describe("Perpetuals DEX", () => {
         new anchor.BN(positionId.toString()),
         new anchor.BN(currentPrice.toString()),
         Array.from(publicKey),
-        new anchor.BN(deserializeLE(closeNonce).toString())
+        new anchor.BN(deserializeLE(closeNonce).toString()),
+        new anchor.BN(computationOffset2.toString())
       )
       .accountsPartial({
         owner: owner.publicKey,
+        authority: owner.publicKey,
         payer: owner.publicKey,
         computationAccount: getComputationAccAddress(clusterOffset, computationOffset2),
         clusterAccount: getClusterAccount(),
@@ -713,6 +775,8 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(compDefAccOffset2).readUInt32LE()),
         position: positionPda,
+        collateralCustody: collateralCustody.account,
+        collateralCustodyOracleAccount: collateralCustody.oracleAccount,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -832,7 +896,10 @@ describe("Perpetuals DEX", () => {
         Array.from(collateralCiphertext[0]),
         Array.from(publicKey),
         new anchor.BN(deserializeLE(sizeNonce).toString()),
-        new anchor.BN(deserializeLE(collateralNonce).toString())
+        new anchor.BN(deserializeLE(collateralNonce).toString()),
+        null,
+        new anchor.BN(deserializeLE(randomBytes(16)).toString()),
+        null
       )
       .accountsPartial({
         owner: owner.publicKey,
@@ -844,6 +911,8 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(getCompDefAccOffset("open_position")).readUInt32LE()),
         position: positionPda,
+        pool: pool.account,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -883,6 +952,7 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(getCompDefAccOffset("add_collateral")).readUInt32LE()),
         position: positionPda,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -999,7 +1069,10 @@ describe("Perpetuals DEX", () => {
         Array.from(collateralCiphertext[0]),
         Array.from(publicKey),
         new anchor.BN(deserializeLE(sizeNonce).toString()),
-        new anchor.BN(deserializeLE(collateralNonce).toString())
+        new anchor.BN(deserializeLE(collateralNonce).toString()),
+        null,
+        new anchor.BN(deserializeLE(randomBytes(16)).toString()),
+        null
       )
       .accountsPartial({
         owner: owner.publicKey,
@@ -1011,6 +1084,8 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(getCompDefAccOffset("open_position")).readUInt32LE()),
         position: positionPda,
+        pool: pool.account,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -1037,8 +1112,6 @@ describe("Perpetuals DEX", () => {
     console.log("  Should be liquidatable: Yes");
 
     const computationOffset2 = new anchor.BN(randomBytes(8));
-    const liquidateEventPromise = awaitEvent("positionLiquidatedEvent");
-
     const queueSig = await program.methods
       .liquidate(
         computationOffset2,
@@ -1057,6 +1130,7 @@ describe("Perpetuals DEX", () => {
         executingPool: getExecutingPoolAccAddress(clusterOffset),
         compDefAccount: getCompDefAccAddress(program.programId, Buffer.from(getCompDefAccOffset("liquidate")).readUInt32LE()),
         position: positionPda,
+        collateralCustody: collateralCustody.account,
       })
       .signers([owner])
       .rpc({ commitment: "confirmed" });
@@ -1065,7 +1139,7 @@ describe("Perpetuals DEX", () => {
     const finalizeSig = await awaitComputationFinalization(provider as anchor.AnchorProvider, computationOffset2, program.programId, "confirmed");
     console.log("Finalize signature:", finalizeSig);
 
-    const liquidateEvent = await liquidateEventPromise;
+    const liquidateEvent = await getCpiEvent(finalizeSig, "positionLiquidatedEvent");
     console.log("\nPosition liquidated event received");
     console.log("  Position ID:", liquidateEvent.positionId.toString());
     console.log("  Owner:", liquidateEvent.owner.toString());
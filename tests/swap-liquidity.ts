@@ -131,8 +131,10 @@ describe("Swap & Liquidity Functions", () => {
             perpetuals: perpetualsAccount,
             pool: poolAccount,
             receivingCustody: receivingCustody,
+            receivingCustodyOracleAccount: oracleAccount,
             receivingCustodyTokenAccount: receivingCustodyTokenAccount,
             dispensingCustody: dispensingCustody,
+            dispensingCustodyOracleAccount: oracleAccount,
             dispensingCustodyTokenAccount: dispensingCustodyTokenAccount,
           })
           .signers([owner])
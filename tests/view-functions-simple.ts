@@ -100,7 +100,7 @@ describe("View Functions with TestClient", () => {
     const solCustody = testClient.custodies.get("testpool-SOL");
     const pool = testClient.pools.get("testpool");
 
-    const price = await program.methods
+    const { price } = await program.methods
       .getOraclePrice({ ema: false })
       .accountsPartial({
         perpetuals: testClient.perpetualsAccount,
@@ -173,7 +173,7 @@ describe("View Functions with TestClient", () => {
   it("Calls get_assets_under_management", async () => {
     const pool = testClient.pools.get("testpool");
 
-    const result = await program.methods
+    const { aumUsd: result } = await program.methods
       .getAssetsUnderManagement({})
       .accountsPartial({
         perpetuals: testClient.perpetualsAccount,
@@ -264,7 +264,7 @@ describe("View Functions", () => {
         removeCollateral: new anchor.BN(0),
       };
 
-      const result = await program.methods
+      const { liquidationPrice: result } = await program.methods
         .getLiquidationPrice(params)
         .accountsPartial({
           perpetuals: perpetualsAccount,
@@ -288,7 +288,7 @@ describe("View Functions", () => {
         removeCollateral: new anchor.BN(200_000000),
       };
 
-      const result = await program.methods
+      const { liquidationPrice: result } = await program.methods
         .getLiquidationPrice(params)
         .accountsPartial({
           perpetuals: perpetualsAccount,
@@ -318,7 +318,7 @@ describe("View Functions", () => {
         side: { long: {} },
       };
 
-      const result = await program.methods
+      const { isLiquidatable: result } = await program.methods
         .getLiquidationState(params)
         .accountsPartial({
           perpetuals: perpetualsAccount,
@@ -331,7 +331,7 @@ describe("View Functions", () => {
         })
         .view();
 
-      expect(result).to.equal(0);
+      expect(result).to.equal(false);
       console.log("Liquidation state (healthy):", result);
     });
 
@@ -344,7 +344,7 @@ describe("View Functions", () => {
         side: { long: {} },
       };
 
-      const result = await program.methods
+      const { isLiquidatable: result } = await program.methods
         .getLiquidationState(params)
         .accountsPartial({
           perpetuals: perpetualsAccount,
@@ -357,7 +357,7 @@ describe("View Functions", () => {
         })
         .view();
 
-      expect(result).to.be.greaterThan(0);
+      expect(result).to.equal(true);
       console.log("Liquidation state (liquidatable):", result);
     });
   });
@@ -368,7 +368,7 @@ describe("View Functions", () => {
         ema: false,
       };
 
-      const result = await program.methods
+      const { price: result } = await program.methods
         .getOraclePrice(params)
         .accountsPartial({
           perpetuals: perpetualsAccount,
@@ -472,7 +472,7 @@ describe("View Functions", () => {
     it("Gets pool AUM", async () => {
       const params = {};
 
-      const result = await program.methods
+      const { aumUsd: result } = await program.methods
         .getAssetsUnderManagement(params)
         .accountsPartial({
           perpetuals: perpetualsAccount,
@@ -491,7 +491,7 @@ describe("View Functions", () => {
 
       const poolInfo = testClient.pools.get("testpool");
 
-      const result = await program.methods
+      const { price: result } = await program.methods
         .getLpTokenPrice(params)
         .accountsPartial({
           perpetuals: perpetualsAccount,
@@ -61,6 +61,7 @@ import {
   encryptPositionData,
   decryptPositionData,
   generateComputationOffset,
+  generateNonce,
   generatePositionId,
   nonceToBN,
   ciphertextToBytes,
@@ -332,7 +333,10 @@ export class PerpetualsAdapter {
           encrypted.collateralEncrypted,
           Array.from(this.encryptionContext.publicKey),
           nonceToBN(encrypted.sizeNonce),
-          nonceToBN(encrypted.collateralNonce)
+          nonceToBN(encrypted.collateralNonce),
+          params.clientRequestId ?? null,
+          nonceToBN(generateNonce()),
+          params.clientTag ?? null
         )
         .accountsPartial({
           owner: this.provider.wallet.publicKey,
@@ -344,6 +348,7 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: positionPDA,
+          collateralCustody: params.collateralCustody || this.defaultCollateralCustody,
           // TODO: Add pool, custody, oracle accounts when available
         })
         .rpc({ commitment: "confirmed" });
@@ -383,6 +388,16 @@ export class PerpetualsAdapter {
    * @param params Position to close
    * @returns Transaction result
    */
+  /**
+   * Custody holding a position's locked notional. Positions opened before
+   * the field existed carry the default key and lock nothing.
+   */
+  private positionCollateralCustody(position: any): PublicKey | undefined {
+    return position.collateralCustody && !position.collateralCustody.equals(PublicKey.default)
+      ? position.collateralCustody
+      : this.defaultCollateralCustody;
+  }
+
   async closePosition(params: ClosePositionParams): Promise<TransactionResult> {
     if (this.mode === AdapterMode.Public) {
       return this.closePositionPublic(params);
@@ -433,7 +448,9 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
-          owner: this.provider.wallet.publicKey,
+          collateralCustody: this.positionCollateralCustody(position),
+          owner: position.owner,
+          authority: this.provider.wallet.publicKey,
           // TODO: Add pool, custody, oracle accounts
         })
         .rpc({ commitment: "confirmed" });
@@ -530,6 +547,7 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
+          collateralCustody: this.positionCollateralCustody(position),
           owner: this.provider.wallet.publicKey,
           // TODO: Add pool, custody accounts
         })
@@ -627,6 +645,7 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
+          collateralCustody: this.positionCollateralCustody(position),
           owner: this.provider.wallet.publicKey,
           // TODO: Add pool, custody accounts
         })
@@ -714,6 +733,7 @@ export class PerpetualsAdapter {
           executingPool,
           compDefAccount,
           position: params.positionKey,
+          collateralCustody: this.positionCollateralCustody(position),
           liquidator: this.provider.wallet.publicKey,
           // TODO: Add pool, custody, oracle accounts
         })
@@ -887,7 +907,7 @@ export class PerpetualsAdapter {
         })
         .view();
 
-      return result as anchor.BN;
+      return result.price as anchor.BN;
     } catch (error) {
       console.error("[Adapter] Error getting oracle price:", error);
       return null;
@@ -927,6 +947,9 @@ export class PerpetualsAdapter {
         this.program.programId
       )[0];
 
+      const receivingCustodyData = await this.program.account.custody.fetch(receivingCustody) as any;
+      const dispensingCustodyData = await this.program.account.custody.fetch(dispensingCustody) as any;
+
       const signature = await this.program.methods
         .swap({
           amountIn: params.amountIn,
@@ -936,12 +959,14 @@ export class PerpetualsAdapter {
           owner: this.provider.publicKey,
           fundingAccount: params.fundingAccount,
           receivingAccount: params.receivingAccount,
-          transferAuthority: this.provider.publicKey,
+          transferAuthority: this.getTransferAuthorityPDA(),
           perpetuals: this.getPerpetualsPDA(),
           pool,
           receivingCustody,
+          receivingCustodyOracleAccount: receivingCustodyData.oracle.oracleAccount,
           receivingCustodyTokenAccount,
           dispensingCustody,
+          dispensingCustodyOracleAccount: dispensingCustodyData.oracle.oracleAccount,
           dispensingCustodyTokenAccount,
         })
         .rpc();
@@ -993,7 +1018,7 @@ export class PerpetualsAdapter {
           owner: this.provider.publicKey,
           fundingAccount: params.fundingAccount,
           lpTokenAccount: params.lpTokenAccount,
-          transferAuthority: this.provider.publicKey,
+          transferAuthority: this.getTransferAuthorityPDA(),
           perpetuals: this.getPerpetualsPDA(),
           pool,
           custody,
@@ -1049,7 +1074,7 @@ export class PerpetualsAdapter {
           owner: this.provider.publicKey,
           receivingAccount: params.receivingAccount,
           lpTokenAccount: params.lpTokenAccount,
-          transferAuthority: this.provider.publicKey,
+          transferAuthority: this.getTransferAuthorityPDA(),
           perpetuals: this.getPerpetualsPDA(),
           pool,
           custody,
@@ -1197,7 +1222,7 @@ export class PerpetualsAdapter {
         })
         .view();
 
-      return result as anchor.BN;
+      return result.aumUsd as anchor.BN;
     } catch (error) {
       console.error("[Adapter] Error getting AUM:", error);
       return null;
@@ -1224,7 +1249,7 @@ export class PerpetualsAdapter {
         })
         .view();
 
-      return result as anchor.BN;
+      return result.price as anchor.BN;
     } catch (error) {
       console.error("[Adapter] Error getting LP token price:", error);
       return null;
@@ -1362,7 +1387,8 @@ export class PerpetualsAdapter {
       const signature = await (this.program as any).methods
         .closePositionPublic(position.positionId)
         .accountsPartial({
-          owner: this.provider.wallet.publicKey,
+          owner: position.owner,
+          authority: this.provider.wallet.publicKey,
           perpetuals: perpetualsAccount,
           pool: pool,
           position: params.positionKey,
@@ -1619,6 +1645,16 @@ export class PerpetualsAdapter {
     )[0];
   }
 
+  /**
+   * Get transfer authority PDA
+   */
+  private getTransferAuthorityPDA(): PublicKey {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from("transfer_authority")],
+      this.program.programId
+    )[0];
+  }
+
   /**
    * Get cluster account (from Arcium environment or devnet config)
    * For devnet, uses getClusterAccAddress with cluster offset
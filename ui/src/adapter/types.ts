@@ -74,6 +74,8 @@ export interface OpenPositionParams {
   pool?: PublicKey;          // Optional pool override
   custody?: PublicKey;       // Optional custody override
   collateralCustody?: PublicKey; // Optional collateral custody override
+  clientRequestId?: anchor.BN;   // Echoed in events so retries can be detected
+  clientTag?: number[];          // Up to 32 bytes echoed in open/close events
   fundingAccount?: PublicKey;    // Token account for collateral (required for public mode)
 }
 
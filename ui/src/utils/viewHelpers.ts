@@ -126,7 +126,8 @@ export class ViewHelper {
     const index = IDL.instructions.findIndex(
       (f) => f.name === "get_assets_under_management"
     );
-    return this.decodeLogs(result, index);
+    const res: any = this.decodeLogs(result, index);
+    return res.aumUsd;
   };
 
   getEntryPriceAndFee = async (
@@ -247,9 +248,10 @@ export class ViewHelper {
       (f) => f.name === "get_liquidation_price"
     );
     console.log("results in liquidation price", result);
-    console.log("decode logs", Number(this.decodeLogs(result, index)));
+    const res: any = this.decodeLogs(result, index);
+    console.log("decode logs", Number(res.liquidationPrice));
     console.log("\n\n\n***");
-    return this.decodeLogs(result, index);
+    return res.liquidationPrice;
   };
 
   getLiquidationState = async (position: PositionAccount): Promise<BN> => {
@@ -271,7 +273,8 @@ export class ViewHelper {
     const index = IDL.instructions.findIndex(
       (f) => f.name === "get_liquidation_state"
     );
-    return this.decodeLogs(result, index);
+    const res: any = this.decodeLogs(result, index);
+    return new BN(res.isLiquidatable ? 1 : 0);
   };
 
   getPnl = async (position: PositionAccount): Promise<ProfitAndLoss> => {
@@ -423,6 +426,7 @@ export class ViewHelper {
     const index = IDL.instructions.findIndex(
       (f) => f.name === "get_oracle_price"
     );
-    return this.decodeLogs<BN>(result, index);
+    const res: any = this.decodeLogs(result, index);
+    return res.price;
   };
 }